@@ -1,5 +1,27 @@
 //! Contains some part of the AST that are common in the different parts of the compiler
 
+use std::collections::HashMap;
+use std::fmt;
+
+/// Byte span of a construct in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a new span from its byte offsets
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Get the smallest span containing both spans
+    pub fn merge(&self, span: &Span) -> Span {
+        Span::new(self.start.min(span.start), self.end.max(span.end))
+    }
+}
+
 /// Binary operation type
 #[derive(Debug, Clone)]
 pub enum BinOp {
@@ -27,20 +49,140 @@ pub enum UnOp {
     UMinus,
 }
 
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mod => "mod",
+            BinOp::Or => "or",
+            BinOp::Xor => "xor",
+            BinOp::And => "and",
+            BinOp::Impl => "=>",
+            BinOp::Neq => "<>",
+            BinOp::Eq => "=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnOp::Not => write!(f, "not "),
+            UnOp::UMinus => write!(f, "-"),
+        }
+    }
+}
+
+/// Iterators applying a node on each element of arrays
+/// The accumulator of fold and mapfold is the first input and output of the node.
+/// red is a fold whose accumulator is initialized with the first element
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IteratorKind {
+    Map,
+    Fold,
+    MapFold,
+    Red,
+}
+
+impl IteratorKind {
+    /// Get the types of the inputs of an iterator of size n, given the inputs of the node
+    pub fn inputs(&self, in_type: &Vec<Type>, n: usize) -> Vec<Type> {
+        let array = |typ: &Type| Type::Array(box typ.clone(), n);
+        match self {
+            IteratorKind::Map => in_type.iter().map(array).collect(),
+            IteratorKind::Fold | IteratorKind::MapFold => in_type[..1]
+                .iter()
+                .cloned()
+                .chain(in_type[1..].iter().map(array))
+                .collect(),
+            IteratorKind::Red => vec![array(&in_type[0])],
+        }
+    }
+
+    /// Get the types of the outputs of an iterator of size n, given the outputs of the node
+    pub fn outputs(&self, out_type: &Vec<Type>, n: usize) -> Vec<Type> {
+        let array = |typ: &Type| Type::Array(box typ.clone(), n);
+        match self {
+            IteratorKind::Map => out_type.iter().map(array).collect(),
+            IteratorKind::Fold | IteratorKind::MapFold => out_type[..1]
+                .iter()
+                .cloned()
+                .chain(out_type[1..].iter().map(array))
+                .collect(),
+            IteratorKind::Red => out_type.clone(),
+        }
+    }
+}
+
+impl fmt::Display for IteratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IteratorKind::Map => write!(f, "map"),
+            IteratorKind::Fold => write!(f, "fold"),
+            IteratorKind::MapFold => write!(f, "mapfold"),
+            IteratorKind::Red => write!(f, "red"),
+        }
+    }
+}
+
 /// Different types of the synchronous language
+/// User types (enums, records and aliases) are referred to by their name
+/// Arrays have a static size. ConstArray is an array whose size is given
+/// by a constant, it is replaced by an Array before typing
+/// Var is a type variable 'a of a polymorphic node, it is replaced by
+/// a concrete type before the translation into minils
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
     Real,
     Bool,
+    Named(String),
+    Array(Box<Type>, usize),
+    ConstArray(Box<Type>, String),
+    Var(String),
+}
+
+impl Type {
+    /// Replace the type variables bound in a type
+    pub fn substitute(&self, bindings: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Var(name) => match bindings.get(name) {
+                Some(typ) => typ.clone(),
+                None => self.clone(),
+            },
+            Type::Array(box typ, n) => Type::Array(box typ.substitute(bindings), *n),
+            _ => self.clone(),
+        }
+    }
+
+    /// Get the type variables of a type
+    pub fn variables(&self) -> Vec<&String> {
+        match self {
+            Type::Var(name) => vec![name],
+            Type::Array(box typ, _) | Type::ConstArray(box typ, _) => typ.variables(),
+            _ => vec![],
+        }
+    }
 }
 
 /// Constant values
-#[derive(Debug, Clone)]
+/// Enum constructors and records are given with the name of their type
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Bool(bool),
     Int(i32),
     Real(f32),
+    Enum(String, String),
+    Array(Vec<Value>),
+    Record(String, Vec<(String, Value)>),
 }
 
 impl Value {
@@ -50,48 +192,237 @@ impl Value {
             Value::Bool(_) => Type::Bool,
             Value::Int(_) => Type::Int,
             Value::Real(_) => Type::Real,
+            Value::Enum(t, _) => Type::Named(t.clone()),
+            Value::Array(v) => Type::Array(box v[0].get_type(), v.len()),
+            Value::Record(t, _) => Type::Named(t.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Real => write!(f, "real"),
+            Type::Bool => write!(f, "bool"),
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Array(typ, n) => write!(f, "{}^{}", typ, n),
+            Type::ConstArray(typ, n) => write!(f, "{}^{}", typ, n),
+            Type::Var(name) => write!(f, "'{}", name),
         }
     }
 }
 
-/// A clock used in the synchronous language
-/// Const means that it refer to a statically computable expression
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Real(r) => write!(f, "{}", r),
+            Value::Enum(_, c) => write!(f, "{}", c),
+            Value::Array(v) => {
+                let values: Vec<String> = v.iter().map(|value| value.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Value::Record(_, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!("{} = {}", field, value))
+                    .collect();
+                write!(f, "{{{}}}", fields.join("; "))
+            }
+        }
+    }
+}
+
+/// Declaration of a type by the user
+#[derive(Debug, Clone)]
+pub struct TypeDecl {
+    pub name: String,
+    pub def: TypeDef,
+    pub span: Span,
+}
+
+/// Definition of a user type
+/// An alias is another name for an existing type
 #[derive(Debug, Clone, PartialEq)]
-pub enum Clock {
-    Const,
-    Ck(Vec<(String, bool)>),
+pub enum TypeDef {
+    Enum(Vec<String>),
+    Record(Vec<(String, Type)>),
+    Alias(Type),
 }
 
-impl Clock {
-    /// Check if two clocks are compatible
-    /// Two clocks are compatible is they are equals, or if one of them is const
-    pub fn is_compatible(clock1: &Clock, clock2: &Clock) -> bool {
-        match clock1 {
-            Clock::Const => true,
-            Clock::Ck(hm1) => match clock2 {
-                Clock::Const => true,
-                Clock::Ck(hm2) => hm1 == hm2,
+impl TypeDecl {
+    /// Get the definition of a type, given its name
+    pub fn lookup<'a>(types: &'a Vec<TypeDecl>, name: &str) -> Option<&'a TypeDef> {
+        types
+            .iter()
+            .find(|decl| decl.name == name)
+            .map(|decl| &decl.def)
+    }
+
+    /// Get the constructors of an enum type, given its name
+    pub fn constructors<'a>(types: &'a Vec<TypeDecl>, name: &str) -> &'a Vec<String> {
+        match TypeDecl::lookup(types, name) {
+            Some(TypeDef::Enum(constructors)) => constructors,
+            Some(_) => panic!("Type {} is not an enum", name),
+            None => panic!("Type {} is not declared", name),
+        }
+    }
+
+    /// Get the fields of a record type given its name, with their resolved types
+    pub fn fields(types: &Vec<TypeDecl>, name: &str) -> Vec<(String, Type)> {
+        match TypeDecl::lookup(types, name) {
+            Some(TypeDef::Record(fields)) => fields
+                .iter()
+                .map(|(field, typ)| (field.clone(), TypeDecl::resolve(types, typ).unwrap()))
+                .collect(),
+            Some(_) => panic!("Type {} is not a record", name),
+            None => panic!("Type {} is not declared", name),
+        }
+    }
+
+    /// Check if a type is an enum type
+    pub fn is_enum(types: &Vec<TypeDecl>, typ: &Type) -> bool {
+        match typ {
+            Type::Named(name) => match TypeDecl::lookup(types, name) {
+                Some(TypeDef::Enum(_)) => true,
+                _ => false,
             },
+            _ => false,
         }
     }
 
-    /// Check is the clock is faster or equal than the given clock
-    pub fn is_faster_or_equal_than(&self, clock: &Clock) -> bool {
-        match &self {
-            Clock::Const => true,
-            Clock::Ck(v_1) => match clock {
-                Clock::Const => v_1.len() == 0,
-                Clock::Ck(v_2) => {
-                    if v_1.len() > v_2.len() {
-                        false
-                    } else {
-                        v_1.iter()
-                            .zip(v_2)
-                            .map(|((c_1, _), (c_2, _))| c_1 == c_2)
-                            .fold(true, |acc, b| b && acc)
-                    }
+    /// Replace the aliases in a type by the type they stand for
+    /// Return an error message if a type is not declared, or if an alias
+    /// refers to itself
+    pub fn resolve(types: &Vec<TypeDecl>, typ: &Type) -> Result<Type, String> {
+        TypeDecl::resolve_rec(types, typ, &mut vec![])
+    }
+
+    fn resolve_rec(types: &Vec<TypeDecl>, typ: &Type, aliases: &mut Vec<String>) -> Result<Type, String> {
+        match typ {
+            Type::Named(name) => match TypeDecl::lookup(types, name) {
+                None => Err(format!("Type {} used but not declared", name)),
+                Some(TypeDef::Alias(_)) if aliases.contains(name) => {
+                    Err(format!("Type alias {} is cyclic", name))
                 }
+                Some(TypeDef::Alias(t)) => {
+                    aliases.push(name.clone());
+                    TypeDecl::resolve_rec(types, t, aliases)
+                }
+                Some(_) => Ok(typ.clone()),
             },
+            Type::Array(t, n) => Ok(Type::Array(box TypeDecl::resolve_rec(types, t, aliases)?, *n)),
+            Type::ConstArray(t, n) => Ok(Type::ConstArray(
+                box TypeDecl::resolve_rec(types, t, aliases)?,
+                n.clone(),
+            )),
+            _ => Ok(typ.clone()),
+        }
+    }
+
+    /// Get all the values a clock variable of a given type can take
+    pub fn clock_values(types: &Vec<TypeDecl>, typ: &Type) -> Vec<Value> {
+        match typ {
+            Type::Named(name) => TypeDecl::constructors(types, name)
+                .iter()
+                .map(|c| Value::Enum(name.clone(), c.clone()))
+                .collect(),
+            _ => vec![Value::Bool(true), Value::Bool(false)],
+        }
+    }
+}
+
+/// A constant of the program, evaluated at compile time
+/// Constants declared in a node are given with the name of the node
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub name: String,
+    pub value: Value,
+    pub node: Option<String>,
+}
+
+/// A node implemented in Rust, declared without equations
+/// An imported node, written `imported node f(...) returns (...) = "path::f";`,
+/// is a stateless Rust function, which is called directly.
+/// An extern node, written `extern node f(...) returns (...) = "path::F";`,
+/// has a state: it is a Rust type implementing Debug and Default, with
+/// the reset and step methods of the generated machines.
+/// When no path is given, the name of the node is used
+#[derive(Debug, Clone)]
+pub struct ImportedNode {
+    pub name: String,
+    pub in_params: Vec<(String, Type)>,
+    pub out_params: Vec<(String, Type)>,
+    pub path: String,
+    pub stateful: bool,
+    pub span: Span,
+}
+
+/// An assertion of a node, whose condition is given by a boolean variable
+/// text is the source of the condition, used in the generated code.
+/// An assumption only depends on the inputs of the node, or is assumed by
+/// its contract, so that a verification backend can assume it holds
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub var: String,
+    pub text: String,
+    pub kind: AssertionKind,
+    pub assumption: bool,
+    pub span: Span,
+}
+
+/// Origin of an assertion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssertionKind {
+    /// `assert e;` in the body of a node
+    Assert,
+    /// `assume e;` in the contract of a node
+    Assume,
+    /// `guarantee e;` in the contract of a node, or an `ensure` of one of
+    /// its modes
+    Guarantee,
+}
+
+impl fmt::Display for AssertionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssertionKind::Assert => write!(f, "assertion"),
+            AssertionKind::Assume => write!(f, "assumption"),
+            AssertionKind::Guarantee => write!(f, "guarantee"),
+        }
+    }
+}
+
+/// A clock used in the synchronous language, given by the list of the
+/// sampling variables from the base clock, with the value (a boolean, or an
+/// enum constructor) they should take
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clock {
+    Ck(Vec<(String, Value)>),
+}
+
+impl Clock {
+    /// Check is the clock is faster or equal than the given clock, that is if
+    /// it is a prefix of it
+    pub fn is_faster_or_equal_than(&self, clock: &Clock) -> bool {
+        let (Clock::Ck(v_1), Clock::Ck(v_2)) = (self, clock);
+        v_1.len() <= v_2.len() && v_1.iter().zip(v_2).all(|(ck_1, ck_2)| ck_1 == ck_2)
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Clock::Ck(v) = self;
+        write!(f, "base")?;
+        for (ck, value) in v {
+            match value {
+                Value::Bool(true) => write!(f, " on {}", ck)?,
+                Value::Bool(false) => write!(f, " on not {}", ck)?,
+                value => write!(f, " on {}({})", value, ck)?,
+            }
         }
+        Ok(())
     }
 }
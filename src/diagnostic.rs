@@ -0,0 +1,153 @@
+//! Contains the diagnostics emitted by the compiler, and functions to
+//! display them with the part of the source file they refer to.
+
+use crate::ast::Span;
+
+use std::fs::File;
+use std::io::Read;
+
+/// A source file given to the compiler
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub contents: String,
+}
+
+impl Source {
+    /// Create a source from its name and its contents
+    pub fn new(name: String, contents: String) -> Source {
+        Source { name, contents }
+    }
+
+    /// Load a source file from the disk
+    pub fn from_file(filename: &str) -> std::io::Result<Source> {
+        let mut f = File::open(filename)?;
+        let mut contents = String::new();
+        f.read_to_string(&mut contents)?;
+        Ok(Source::new(filename.to_owned(), contents))
+    }
+
+    /// Get the line and the column (both starting at 1) of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.contents.len());
+        let before = &self.contents[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let col = self.contents[line_start..offset].chars().count() + 1;
+        (line, col)
+    }
+
+    /// Get the content of a line (starting at 1), without the line return
+    pub fn line(&self, line: usize) -> &str {
+        self.contents.lines().nth(line - 1).unwrap_or("")
+    }
+}
+
+/// The set of source files given to the compiler.
+/// Each file is given its own range of offsets, so that a span
+/// alone is enough to know which file it refers to.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<(usize, Source)>,
+}
+
+impl SourceMap {
+    /// Create an empty source map
+    pub fn new() -> SourceMap {
+        SourceMap { files: vec![] }
+    }
+
+    /// Add a source file, and return the offset of its first byte
+    pub fn add(&mut self, source: Source) -> usize {
+        let offset = match self.files.last() {
+            None => 0,
+            Some((offset, source)) => offset + source.contents.len() + 1,
+        };
+        self.files.push((offset, source));
+        offset
+    }
+
+    /// Get the source files, with their offsets
+    pub fn files(&self) -> &Vec<(usize, Source)> {
+        &self.files
+    }
+
+    /// Get the source file containing an offset, and the offset of its first byte
+    pub fn lookup(&self, offset: usize) -> Option<(usize, &Source)> {
+        self.files
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+            .map(|(start, source)| (*start, source))
+    }
+}
+
+/// An error message, with the location in the source it refers to
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic pointing at a span of the source
+    pub fn new(message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            message,
+            span: Some(span),
+        }
+    }
+
+    /// Create a diagnostic that is not related to a part of the source
+    pub fn without_span(message: String) -> Diagnostic {
+        Diagnostic {
+            message,
+            span: None,
+        }
+    }
+
+    /// Prefix the message of the diagnostic
+    pub fn with_context(mut self, context: &str) -> Diagnostic {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    /// Display the diagnostic in the same way rustc does, as
+    /// file:line:col followed by the underlined source snippet
+    pub fn render(&self, sources: &SourceMap) -> String {
+        self.render_with_level("error", sources)
+    }
+
+    /// Display the diagnostic, with a given level (error, warning, ...)
+    pub fn render_with_level(&self, level: &str, sources: &SourceMap) -> String {
+        let (offset, source, span) = match self.span.and_then(|span| {
+            sources
+                .lookup(span.start)
+                .map(|(offset, source)| (offset, source, span))
+        }) {
+            None => return format!("{}: {}\n", level, self.message),
+            Some(location) => location,
+        };
+        let (line, col) = source.line_col(span.start - offset);
+        let (end_line, end_col) = source.line_col(span.end - offset);
+        let line_str = source.line(line);
+        let end_col = if end_line == line {
+            end_col
+        } else {
+            line_str.chars().count() + 1
+        };
+        let n_carets = if end_col > col { end_col - col } else { 1 };
+        let margin = " ".repeat(line.to_string().len());
+        let padding: String = line_str
+            .chars()
+            .take(col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let mut s = format!("{}: {}\n", level, self.message);
+        s += &format!("{}--> {}:{}:{}\n", margin, source.name, line, col);
+        s += &format!("{} |\n", margin);
+        s += &format!("{} | {}\n", line, line_str);
+        s += &format!("{} | {}{}\n", margin, padding, "^".repeat(n_carets));
+        s
+    }
+}
@@ -0,0 +1,69 @@
+//! Contains the errors that can be raised while compiling a LucyRS program
+
+use crate::diagnostic::{Diagnostic, SourceMap};
+
+use std::fmt;
+
+/// An error raised by one of the compilation passes
+#[derive(Debug, Clone)]
+pub enum CompileError {
+    Io(Diagnostic),
+    Parse(Diagnostic),
+    Resolution(Diagnostic),
+    Blocks(Diagnostic),
+    Specialization(Diagnostic),
+    Constant(Diagnostic),
+    Causality(Diagnostic),
+    Typing(Diagnostic),
+    Clock(Diagnostic),
+    Initialization(Diagnostic),
+    Backend(Diagnostic),
+}
+
+impl CompileError {
+    /// Get the name of the pass that raised the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CompileError::Io(_) => "io",
+            CompileError::Parse(_) => "parse",
+            CompileError::Resolution(_) => "resolution",
+            CompileError::Blocks(_) => "blocks",
+            CompileError::Specialization(_) => "specialization",
+            CompileError::Constant(_) => "constant",
+            CompileError::Causality(_) => "causality",
+            CompileError::Typing(_) => "typing",
+            CompileError::Clock(_) => "clock",
+            CompileError::Initialization(_) => "initialization",
+            CompileError::Backend(_) => "backend",
+        }
+    }
+
+    /// Get the diagnostic describing the error
+    pub fn diagnostic(&self) -> &Diagnostic {
+        match self {
+            CompileError::Io(d)
+            | CompileError::Parse(d)
+            | CompileError::Resolution(d)
+            | CompileError::Blocks(d)
+            | CompileError::Specialization(d)
+            | CompileError::Constant(d)
+            | CompileError::Causality(d)
+            | CompileError::Typing(d)
+            | CompileError::Clock(d)
+            | CompileError::Initialization(d)
+            | CompileError::Backend(d) => d,
+        }
+    }
+
+    /// Display the error with the part of the source it refers to
+    pub fn render(&self, sources: &SourceMap) -> String {
+        self.diagnostic()
+            .render_with_level(&format!("error[{}]", self.kind()), sources)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.kind(), self.diagnostic().message)
+    }
+}
@@ -48,7 +48,9 @@ impl IdentGenerator {
 }
 
 /// Generate an new string from a string
+/// The qualified names P::x of the packages become P__x
 pub fn gen_ident(s: String, index: u32) -> String {
+    let s = s.replace("::", "__");
     if index == 0 {
         s + "_"
     } else {
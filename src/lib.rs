@@ -0,0 +1,236 @@
+//! lucyrs is a compiler for a small synchronous language (like lustre),
+//! which is compiled into Rust.
+//!
+//! The compiler can be called in-process with the `Compiler` builder:
+//!
+//! ```ignore
+//! let compilation = lucyrs::Compiler::new()
+//!     .path("examples/fby.lus")
+//!     .entry_node("check")
+//!     .compile()?;
+//! println!("{}", compilation.code);
+//! ```
+
+#![feature(box_syntax)]
+#![feature(box_patterns)]
+
+pub mod ast;
+pub mod diagnostic;
+pub mod error;
+pub mod ident;
+pub mod lucy;
+pub mod minils;
+pub mod obc;
+
+pub use crate::diagnostic::{Diagnostic, Source, SourceMap};
+pub use crate::error::CompileError;
+pub use crate::obc::{AssertMode, BackendOptions};
+
+use std::path::{Path, PathBuf};
+
+/// Builder used to configure and run the compiler
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+    sources: SourceMap,
+    load_errors: Vec<CompileError>,
+    /// Files already loaded, so that a file included twice is loaded once
+    loaded: Vec<PathBuf>,
+    entry_node: Option<String>,
+    options: BackendOptions,
+}
+
+/// Result of a compilation, containing every intermediate representation
+/// of the program, and the generated Rust code
+#[derive(Debug, Clone)]
+pub struct Compilation {
+    pub types: Vec<ast::TypeDecl>,
+    pub constants: Vec<ast::Constant>,
+    pub imported: Vec<ast::ImportedNode>,
+    pub lucy: Vec<lucy::clock_typed_ast::Node>,
+    pub minils: Vec<minils::ast::Node>,
+    pub normalized_minils: Vec<minils::normalized_ast::Node>,
+    pub obc: Vec<obc::ast::Machine>,
+    pub code: String,
+}
+
+impl Compiler {
+    /// Create a compiler without any source
+    pub fn new() -> Compiler {
+        Default::default()
+    }
+
+    /// Add a source given as a string, and the files it includes, relatively
+    /// to the current directory. name is used in error messages
+    pub fn source(mut self, name: &str, contents: &str) -> Compiler {
+        let offset = self
+            .sources
+            .add(Source::new(name.to_owned(), contents.to_owned()));
+        self.load_includes(offset, Path::new(""), &mut vec![]);
+        self
+    }
+
+    /// Add a source file given by its path, and the files it includes
+    /// If a file cannot be read, the error is reported by compile
+    pub fn path(mut self, path: &str) -> Compiler {
+        let canonical = canonical_path(Path::new(path));
+        if self.loaded.contains(&canonical) {
+            return self;
+        }
+        match Source::from_file(path) {
+            Ok(source) => {
+                self.loaded.push(canonical.clone());
+                let offset = self.sources.add(source);
+                let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+                self.load_includes(offset, directory, &mut vec![(canonical, path.to_owned())]);
+            }
+            Err(error) => self.load_errors.push(CompileError::Io(Diagnostic::without_span(
+                format!("Cannot read file {}: {}", path, error),
+            ))),
+        }
+        self
+    }
+
+    /// Load the files included by the source at offset, relatively to the
+    /// given directory, and the files they include
+    /// stack contains the files being loaded with their names, to detect
+    /// circular includes
+    fn load_includes(
+        &mut self,
+        offset: usize,
+        directory: &Path,
+        stack: &mut Vec<(PathBuf, String)>,
+    ) {
+        let source = self.sources.lookup(offset).unwrap().1.clone();
+        // The syntax errors are reported when the sources are parsed
+        let includes = match lucy::parse_file(&source, offset) {
+            Ok(program) => program.includes,
+            Err(_) => return,
+        };
+        for (file, span) in includes {
+            let path = directory.join(&file);
+            let canonical = canonical_path(&path);
+            let name = path.to_string_lossy().to_string();
+            if let Some(i) = stack.iter().position(|(p, _)| p == &canonical) {
+                let cycle: Vec<&str> = stack[i..]
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .chain(Some(name.as_str()))
+                    .collect();
+                self.load_errors.push(CompileError::Io(Diagnostic::new(
+                    format!("Circular include of {}: {}", file, cycle.join(" includes ")),
+                    span,
+                )));
+                continue;
+            }
+            if self.loaded.contains(&canonical) {
+                continue;
+            }
+            match Source::from_file(&name) {
+                Ok(source) => {
+                    self.loaded.push(canonical.clone());
+                    let offset = self.sources.add(source);
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    stack.push((canonical, name));
+                    self.load_includes(offset, directory, stack);
+                    stack.pop();
+                }
+                Err(error) => self.load_errors.push(CompileError::Io(Diagnostic::new(
+                    format!("Cannot read the included file {}: {}", file, error),
+                    span,
+                ))),
+            }
+        }
+    }
+
+    /// Set the node used as entry point of the generated program
+    pub fn entry_node(mut self, node: &str) -> Compiler {
+        self.entry_node = Some(node.to_owned());
+        self
+    }
+
+    /// Set the options of the Rust backend
+    pub fn options(mut self, options: BackendOptions) -> Compiler {
+        self.options = options;
+        self
+    }
+
+    /// Get the sources given to the compiler, used to display the errors
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Parse the sources, and return the untyped LucyRS program
+    pub fn parse(&self) -> Result<lucy::ast::Program, Vec<CompileError>> {
+        let mut errors = self.load_errors.clone();
+        let mut program = lucy::ast::Program::default();
+        for (offset, source) in self.sources.files() {
+            match lucy::parse_file(source, *offset) {
+                Ok(mut file_program) => {
+                    program.types.append(&mut file_program.types);
+                    program.constants.append(&mut file_program.constants);
+                    program.nodes.append(&mut file_program.nodes);
+                    program.imported.append(&mut file_program.imported);
+                    program.packages.append(&mut file_program.packages);
+                }
+                Err(mut file_errors) => errors.append(&mut file_errors),
+            }
+        }
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parse and check the sources, and return the typed LucyRS nodes
+    pub fn check(&self) -> Result<Vec<lucy::clock_typed_ast::Node>, Vec<CompileError>> {
+        let program = lucy::flatten_packages(self.parse()?)?;
+        let program = lucy::compile_blocks(program)?;
+        let program = lucy::specialize(program)?;
+        let (program, _) = lucy::substitute_constants(program)?;
+        lucy::type_nodes(program, self.entry_node.as_ref().map(|s| s.as_str()))
+    }
+
+    /// Run the whole compilation
+    pub fn compile(&self) -> Result<Compilation, Vec<CompileError>> {
+        let program = lucy::flatten_packages(self.parse()?)?;
+        let program = lucy::compile_blocks(program)?;
+        let program = lucy::specialize(program)?;
+        let (program, constants) = lucy::substitute_constants(program)?;
+        let types = program.types.clone();
+        let imported = program.imported.clone();
+        let entry_node = self.entry_node.as_ref().map(|s| s.as_str());
+        let mut lucy_nodes = lucy::monomorphise(lucy::type_nodes(program, entry_node)?);
+        // The assertions which are not checked are not evaluated either
+        if self.options.asserts == AssertMode::Ignore {
+            lucy_nodes = lucy::remove_assertions(lucy_nodes);
+        }
+        let minils_nodes = lucy::to_minils(lucy_nodes.clone(), &types)?;
+        let normalized_nodes = minils::normalize(minils_nodes.clone());
+        let obc_machines = minils::to_obc(normalized_nodes.clone(), &imported);
+        let code = obc::to_rust(
+            obc_machines.clone(),
+            &types,
+            &constants,
+            entry_node,
+            &self.options,
+        )
+        .map_err(|error| vec![error])?;
+        Ok(Compilation {
+            types,
+            constants,
+            imported,
+            lucy: lucy_nodes,
+            minils: minils_nodes,
+            normalized_minils: normalized_nodes,
+            obc: obc_machines,
+            code,
+        })
+    }
+}
+
+/// Get the canonical form of a path, used to know if a file was already loaded
+/// A path which does not exist is kept as it is
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
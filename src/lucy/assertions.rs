@@ -0,0 +1,80 @@
+//! Remove the assertions of typed LucyRS nodes, when they are not checked by
+//! the generated code. The equations defining their conditions, and the
+//! equations only used by them, are removed with them, so that they are not
+//! evaluated.
+
+use crate::ast::Clock;
+use crate::lucy::clock_typed_ast::{BaseExpr, Expr, Node};
+
+use std::collections::HashSet;
+
+/// Remove the assertions of a node, and the equations that are only used to
+/// compute their conditions
+pub fn remove_assertions(mut node: Node) -> Node {
+    node.assertions = vec![];
+
+    // The variables needed by the outputs, with the variables of their clocks
+    let mut live: HashSet<String> = HashSet::new();
+    for (var, _) in &node.out_params {
+        live.insert(var.clone());
+        if let Some(clock) = node.param_clocks.get(var) {
+            clock_variables(clock, &mut live);
+        }
+    }
+    let mut kept = vec![false; node.eq_list.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, (vars, expr)) in node.eq_list.iter().enumerate() {
+            if kept[i] || !vars.iter().any(|var| live.contains(var)) {
+                continue;
+            }
+            kept[i] = true;
+            changed = true;
+            used_variables(expr, &mut live);
+            for var in vars {
+                live.insert(var.clone());
+                if let Some((_, clock)) = node.local_params.get(var) {
+                    clock_variables(clock, &mut live);
+                }
+            }
+        }
+    }
+
+    let eq_list = node.eq_list.into_iter().zip(kept);
+    node.eq_list = eq_list
+        .filter(|(_, kept)| *kept)
+        .map(|(eq, _)| eq)
+        .collect();
+    node.local_params.retain(|var, _| live.contains(var));
+    node
+}
+
+/// Add the variables used by an expression, including the variables of its
+/// clock
+fn used_variables(expr: &Expr, used: &mut HashSet<String>) {
+    clock_variables(&expr.clock, used);
+    match &expr.expr {
+        BaseExpr::Var(var)
+        | BaseExpr::When(_, var, _)
+        | BaseExpr::Merge(var, _)
+        | BaseExpr::Current(var, _)
+        | BaseExpr::Reset(_, var)
+        | BaseExpr::FunCall(_, _, Some(var), _) => {
+            used.insert(var.clone());
+        }
+        _ => (),
+    }
+    if let BaseExpr::FunCall(_, _, _, clocks) = &expr.expr {
+        clocks.iter().for_each(|clock| clock_variables(clock, used));
+    }
+    for e in expr.sub_exprs() {
+        used_variables(e, used);
+    }
+}
+
+/// Add the variables a clock is sampled on
+fn clock_variables(clock: &Clock, used: &mut HashSet<String>) {
+    let Clock::Ck(v) = clock;
+    used.extend(v.iter().map(|(ck, _)| ck.clone()));
+}
@@ -1,30 +1,360 @@
 //! Contains the untyped AST for the LucyRS language
 
-use crate::ast::{BinOp, Type, UnOp, Value, Clock};
+use crate::ast::{
+    AssertionKind, BinOp, Clock, ImportedNode, IteratorKind, Span, Type, TypeDecl, UnOp, Value,
+};
+use crate::diagnostic::Diagnostic;
 
 use std::collections::HashMap;
+use std::fmt;
+
+/// A LucyRS program, containing type declarations, constants, nodes,
+/// and the nodes implemented in Rust
+/// The packages are flattened before the other passes, and the included
+/// files are loaded by the compiler, with the span of the include
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub types: Vec<TypeDecl>,
+    pub constants: Vec<ConstDecl>,
+    pub nodes: Vec<Node>,
+    pub imported: Vec<ImportedNode>,
+    pub packages: Vec<Package>,
+    pub includes: Vec<(String, Span)>,
+}
+
+impl Program {
+    /// Add a declaration to the program, and return its name
+    pub fn push(&mut self, item: Item) -> Option<String> {
+        match item {
+            Item::Type(decl) => {
+                let name = decl.name.clone();
+                self.types.push(decl);
+                Some(name)
+            }
+            Item::Constant(decl) => {
+                let name = decl.name.clone();
+                self.constants.push(decl);
+                Some(name)
+            }
+            Item::Node(Some(node)) => {
+                let name = node.name.clone();
+                self.nodes.push(node);
+                Some(name)
+            }
+            Item::Node(None) => None,
+            Item::Imported(node) => {
+                let name = node.name.clone();
+                self.imported.push(node);
+                Some(name)
+            }
+        }
+    }
+}
+
+/// Declaration of a file or of a package
+/// A node is None if it contains a syntax error
+#[derive(Debug, Clone)]
+pub enum Item {
+    Type(TypeDecl),
+    Constant(ConstDecl),
+    Node(Option<Node>),
+    Imported(ImportedNode),
+}
+
+/// A package, written `package P ... end;`, whose declarations are referred
+/// to as `P::x` outside of it
+/// The private declarations can only be used inside the package
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub program: Program,
+    pub private: Vec<String>,
+    pub span: Span,
+}
+
+/// Declaration of a constant, whose value is computed at compile time
+#[derive(Debug, Clone)]
+pub struct ConstDecl {
+    pub name: String,
+    pub typ: Type,
+    pub expr: Expr,
+    pub span: Span,
+}
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
+    /// Declared with `function` instead of `node`, so that it has no state
+    pub function: bool,
+    pub static_params: Vec<StaticParam>,
     pub in_params: Vec<(String, Type)>,
     pub out_params: Vec<(String, Type)>,
-    pub local_params: HashMap<String, (Type, Clock)>,
-    pub eq_list: Vec<(Vec<String>, Expr)>,
+    /// Clocks of the inputs and outputs declared with `when`, the others
+    /// are inferred
+    pub param_clocks: HashMap<String, Clock>,
+    /// Classes the type variables of the signature are restricted to
+    pub constraints: Vec<(String, TypeClass)>,
+    /// Local variables, whose type and clock are inferred when they are not
+    /// given
+    pub local_params: HashMap<String, (Option<Type>, Option<Clock>)>,
+    pub constants: Vec<ConstDecl>,
+    pub eq_list: Vec<Equation>,
+    pub blocks: Vec<Block>,
+    pub asserts: Vec<Assert>,
+    pub span: Span,
+    pub pragmas: Vec<Pragma>,
+    /// Span of the declaration of each variable
+    pub var_spans: HashMap<String, Span>,
+    pub var_pragmas: HashMap<String, Vec<Pragma>>,
+}
+
+/// Class of types a type variable can be restricted to, with `where 'a: numeric`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeClass {
+    /// int and real, on which arithmetic operators and comparisons are defined
+    Numeric,
 }
 
+impl fmt::Display for TypeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeClass::Numeric => write!(f, "numeric"),
+        }
+    }
+}
+
+/// Static parameter of a node, given between `<<` and `>>` in its declaration
+#[derive(Debug, Clone)]
+pub struct StaticParam {
+    pub name: String,
+    pub kind: StaticKind,
+    pub span: Span,
+}
+
+/// A static parameter is either a constant, or a node given by
+/// the types of its inputs and outputs
+#[derive(Debug, Clone)]
+pub enum StaticKind {
+    Const(Type),
+    Node(Vec<Type>, Vec<Type>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Equation {
+    pub idents: Vec<String>,
+    pub expr: Expr,
+    pub span: Span,
+    pub pragmas: Vec<Pragma>,
+}
+
+/// Assertion written `assert e;` in the body of a node, or clause of its
+/// contract, which should be true at each instant
+/// When the blocks are compiled, the condition is defined by an equation,
+/// and replaced by the variable it defines. An assertion which only uses
+/// the inputs of the node is an assumption on its environment
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Assert {
+    pub expr: Expr,
+    /// Source of the condition, used in the messages of the generated code
+    pub text: String,
+    pub kind: AssertionKind,
+    pub assumption: bool,
+    pub span: Span,
+}
+
+/// Statement of the body of a node, of a state of an automaton,
+/// or of a reset block
+/// Assertions are only allowed in the body of a node
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Equation(Equation),
+    Block(Block),
+    Assert(Assert),
+}
+
+/// Block of statements, compiled into equations before typing
+#[derive(Debug, Clone)]
+pub enum Block {
+    Automaton(Automaton),
+    Reset(ResetBlock),
+}
+
+/// Statements that are reset when a condition is true, written
+/// `reset <statements> every <expr>`
+#[derive(Debug, Clone)]
+pub struct ResetBlock {
+    pub eq_list: Vec<Equation>,
+    pub blocks: Vec<Block>,
+    pub cond: Expr,
+    pub span: Span,
+}
+
+/// An automaton, whose first state is the initial state
+#[derive(Debug, Clone)]
+pub struct Automaton {
+    pub states: Vec<State>,
+    pub span: Span,
+}
+
+/// A state of an automaton, with the equations active in this state
+/// and the transitions leaving it
+#[derive(Debug, Clone)]
+pub struct State {
+    pub name: String,
+    pub eq_list: Vec<Equation>,
+    pub blocks: Vec<Block>,
+    pub transitions: Vec<Transition>,
+    pub span: Span,
+}
+
+/// A transition of an automaton
+/// Strong transitions (unless) are taken at the beginning of an instant,
+/// and the target state is active in the same instant. Weak transitions
+/// (until) are taken at the end of an instant, for the next instant.
+/// The target state is reset if reset is true (then), and is resumed
+/// otherwise (continue).
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub strong: bool,
+    pub cond: Expr,
+    pub reset: bool,
+    pub target: String,
+    pub span: Span,
+}
+
+/// Split statements into equations, blocks and assertions
+pub fn split_statements(statements: Vec<Statement>) -> (Vec<Equation>, Vec<Block>, Vec<Assert>) {
+    let mut eq_list = vec![];
+    let mut blocks = vec![];
+    let mut asserts = vec![];
+    for statement in statements {
+        match statement {
+            Statement::Equation(eq) => eq_list.push(eq),
+            Statement::Block(block) => blocks.push(block),
+            Statement::Assert(assert) => asserts.push(assert),
+        }
+    }
+    (eq_list, blocks, asserts)
+}
+
+/// Annotation given by the user with `--%name args` or `(*@name args *)`
+/// args_start is the offset of the arguments in the source map, so that
+/// the pragmas written in LucyRS, like contracts, can be parsed
+#[derive(Debug, Clone)]
+pub struct Pragma {
+    pub name: String,
+    pub args: String,
+    pub args_start: usize,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub expr: BaseExpr,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(expr: BaseExpr, span: Span) -> Expr {
+        Expr { expr, span }
+    }
+
+    /// Get the name of a clock, a reset condition of a call, or an expression
+    /// sampled by current, which are variables once the blocks are compiled
+    pub fn as_var(&self) -> &String {
+        match &self.expr {
+            BaseExpr::Var(name) => name,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Sampling constructors are given by their names.
+/// The clocks of when and merge, the sampled expression of current and the
+/// reset condition of a call may be any expression, they are replaced by
+/// variables when the blocks are compiled.
+/// Current defaults and iterator sizes may name a constant
+/// or a constructor, they are given as an enum value without type
+/// until constants are substituted and constructors are typed.
+/// For boolean clocks, they are true and false.
+/// Slices a[i..j] contain both a[i] and a[j].
+/// The type of a record is only known after typing, from its fields.
+/// Reset(e, r) resets the state of e when the variable r is true, it is
+/// only generated from reset blocks and automata.
+/// StaticCall(f, static_args, args, r) calls a node with static parameters,
+/// it is replaced by a call to an instance of the node before typing.
+/// A tuple (e1, ..., en) has the values of its expressions, it is split
+/// into scalar equations when it is translated to MiniLS.
+#[derive(Debug, Clone)]
+pub enum BaseExpr {
     Value(Value),
     UnOp(UnOp, Box<Expr>),
     BinOp(BinOp, Box<Expr>, Box<Expr>),
-    When(Box<Expr>, String, bool),
-    Merge(String, Box<Expr>, Box<Expr>),
-    Fby(Value, Box<Expr>),
+    When(Box<Expr>, Box<Expr>, String),
+    Merge(Box<Expr>, Vec<(String, Expr)>),
+    Fby(Box<Expr>, Box<Expr>),
     IfThenElse(Box<Expr>, Box<Expr>, Box<Expr>),
     Var(String),
-    FunCall(String, Vec<Expr>, Option<String>),
-    Current(String, Value),
+    FunCall(String, Vec<Expr>, Option<Box<Expr>>),
+    StaticCall(String, Vec<Expr>, Vec<Expr>, Option<Box<Expr>>),
+    Iterate(IteratorKind, String, Value, Vec<Expr>),
+    Current(Box<Expr>, Value),
     Pre(Box<Expr>),
     Arrow(Vec<Expr>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, i32, i32),
+    Concat(Box<Expr>, Box<Expr>),
+    Record(Vec<(String, Expr)>),
+    Field(Box<Expr>, String),
+    With(Box<Expr>, Vec<(String, Expr)>),
+    Reset(Box<Expr>, String),
+    Tuple(Vec<Expr>),
+}
+
+/// Get the branches of a merge from its arguments.
+/// Arguments are either two expressions (merge on a boolean), or branches
+/// written as (constructor -> expr), which are parsed as arrows.
+pub fn merge_branches(args: Vec<Expr>, span: Span) -> Result<Vec<(String, Expr)>, Diagnostic> {
+    let is_branch = |e: &Expr| match &e.expr {
+        BaseExpr::Arrow(v) => match v[0].expr {
+            BaseExpr::Var(_) | BaseExpr::Value(Value::Bool(_)) => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    if args.iter().all(is_branch) {
+        Ok(args
+            .into_iter()
+            .map(|arg| match arg.expr {
+                BaseExpr::Arrow(mut v) => {
+                    let constructor = match v.remove(0).expr {
+                        BaseExpr::Var(c) => c,
+                        BaseExpr::Value(Value::Bool(b)) => b.to_string(),
+                        _ => unreachable!(),
+                    };
+                    let expr = if v.len() == 1 {
+                        v.remove(0)
+                    } else {
+                        let span = v[0].span.merge(&v[v.len() - 1].span);
+                        Expr::new(BaseExpr::Arrow(v), span)
+                    };
+                    (constructor, expr)
+                }
+                _ => unreachable!(),
+            })
+            .collect())
+    } else if args.len() == 2 && !args.iter().any(is_branch) {
+        let mut args = args;
+        let e_f = args.pop().unwrap();
+        let e_t = args.pop().unwrap();
+        Ok(vec![(String::from("true"), e_t), (String::from("false"), e_f)])
+    } else {
+        Err(Diagnostic::new(
+            String::from(
+                "A merge expects either two expressions, or branches of the form (constructor -> expr)",
+            ),
+            span,
+        ))
+    }
 }
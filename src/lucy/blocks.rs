@@ -0,0 +1,999 @@
+//! Compile the blocks of LucyRS nodes, automata and reset blocks, into
+//! plain equations, so that the following passes only see equations.
+//!
+//! The equations of a reset block are reset by a variable, defined by the
+//! condition of the block. The state of an automaton is a variable of a generated enum type, and
+//! the equations of each state are sampled on the state variable with
+//! `when`. The variables defined by the automaton are merged from the
+//! values computed in each state. For an automaton with states S and T,
+//! defining a variable x, the generated equations are:
+//!
+//! ```text
+//! pstate = S fby nstate;      -- state chosen by the weak transitions
+//! preset = false fby nreset;
+//! state = merge pstate (S -> <strong transitions of S>) (T -> ...);
+//! reset = merge pstate (S -> <reset by strong transitions of S>) (T -> ...);
+//! x = merge state (S -> x_S) (T -> x_T);
+//! nstate = merge state (S -> <weak transitions of S>) (T -> ...);
+//! nreset = merge state (S -> <reset by weak transitions of S>) (T -> ...);
+//! ```
+//!
+//! where x_S is defined by the equations of S, on the clock `state = S`.
+//! The equations of S are reset when S is entered by a reset, like the
+//! equations of a reset block.
+//!
+//! The condition of each assertion is also defined by an equation on a new
+//! boolean variable, so that it is typed and scheduled like the equations.
+//!
+//! Once the blocks are compiled, the wildcards `_` on the left-hand side of
+//! the equations are replaced by new local variables `_1`, `_2`, ..., whose
+//! type and clock are inferred. They cannot clash with the names given by
+//! the user, which start with a letter.
+//!
+//! The expressions used as clocks of `when` and `merge`, or as reset
+//! conditions of calls, are then defined by equations on new local variables
+//! `_ck1`, `_ck2`, ..., so that the clocks are only given by variables. The
+//! expressions sampled by `current` are defined on variables `_current1`,
+//! ... Each new equation is reset like the expression it comes from, except
+//! for the expressions of `current`, which are not on the clock of the reset.
+//! The expressions without state which are equal are given the same
+//! variable, so that `merge (x > 0) (y when (x > 0)) (z whenot (x > 0))` has
+//! the expected clocks.
+
+use crate::ast::{AssertionKind, BinOp, Clock, Span, Type, TypeDecl, TypeDef, Value};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{
+    Assert, Automaton, BaseExpr, Block, Equation, Expr, Node, Program, ResetBlock,
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// Compile the blocks and the assertions of every node of a program
+/// The enum types of the states of the automata are added to the program
+pub fn compile_blocks(program: Program) -> Result<Program, Vec<Diagnostic>> {
+    let Program {
+        mut types,
+        constants,
+        nodes,
+        imported,
+        ..
+    } = program;
+    let mut constructors: HashSet<String> = types
+        .iter()
+        .flat_map(|decl| match &decl.def {
+            TypeDef::Enum(constructors) => constructors.clone(),
+            _ => vec![],
+        })
+        .collect();
+    let global_names: HashSet<String> = types
+        .iter()
+        .map(|decl| decl.name.clone())
+        .chain(constants.iter().map(|decl| decl.name.clone()))
+        .collect();
+
+    let mut errors = vec![];
+    let mut new_nodes = vec![];
+    for mut node in nodes {
+        if node.blocks.is_empty() && node.asserts.is_empty() {
+            name_wildcards(&mut node);
+            name_clocks(&mut node);
+            new_nodes.push(node);
+            continue;
+        }
+        let mut expander = Expander::new(&node, &global_names, &mut types, &mut constructors);
+        for block in std::mem::replace(&mut node.blocks, vec![]) {
+            expander.expand(block, vec![], None);
+        }
+        let inputs: HashSet<&String> = node.in_params.iter().map(|(name, _)| name).collect();
+        for assert in &mut node.asserts {
+            expander.expand_assert(assert, &inputs);
+        }
+        let Expander {
+            local_params,
+            eq_list,
+            errors: node_errors,
+            ..
+        } = expander;
+        if node_errors.is_empty() {
+            for (name, typ, ck) in local_params {
+                node.local_params.insert(name, (typ, ck));
+            }
+            node.eq_list.extend(eq_list);
+            name_wildcards(&mut node);
+            name_clocks(&mut node);
+            new_nodes.push(node);
+        } else {
+            let context = format!("Error while compiling the blocks of node {}", node.name);
+            errors.extend(node_errors.into_iter().map(|d| d.with_context(&context)));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Program {
+            types,
+            constants,
+            nodes: new_nodes,
+            imported,
+            ..Default::default()
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Name of the values which are not used in a tuple equation
+const WILDCARD: &str = "_";
+
+/// Replace the wildcards defined by the equations of a node by new local
+/// variables, without type nor clock
+fn name_wildcards(node: &mut Node) {
+    let mut count = 0;
+    for eq in &mut node.eq_list {
+        for x in eq.idents.iter_mut().filter(|x| *x == WILDCARD) {
+            count += 1;
+            *x = format!("_{}", count);
+            node.local_params.insert(x.clone(), (None, None));
+        }
+    }
+}
+
+/// Prefix of the variables defined by the expressions used as clocks or as
+/// reset conditions
+const CLOCK: &str = "_ck";
+
+/// Prefix of the variables defined by the expressions sampled by current
+const SAMPLED: &str = "_current";
+
+/// Replace the expressions used as clocks or reset conditions, and sampled
+/// by current, by new local variables, without type nor clock
+fn name_clocks(node: &mut Node) {
+    let mut namer = ClockNamer {
+        counts: HashMap::new(),
+        stateless: HashMap::new(),
+        eq_list: vec![],
+    };
+    for eq in &mut node.eq_list {
+        namer.name_expr(&mut eq.expr, &mut vec![]);
+    }
+    for eq in namer.eq_list {
+        node.local_params.insert(eq.idents[0].clone(), (None, None));
+        node.var_spans.insert(eq.idents[0].clone(), eq.span);
+        node.eq_list.push(eq);
+    }
+}
+
+/// Equations defining the clocks of a node
+struct ClockNamer {
+    counts: HashMap<&'static str, usize>,
+    /// Variables defined by the expressions without state, given by their
+    /// description
+    stateless: HashMap<String, String>,
+    eq_list: Vec<Equation>,
+}
+
+impl ClockNamer {
+    /// Name the clocks of an expression, reset by the given variables from
+    /// the outermost one
+    fn name_expr(&mut self, expr: &mut Expr, resets: &mut Vec<String>) {
+        match &mut expr.expr {
+            BaseExpr::Value(_) | BaseExpr::Var(_) => (),
+            BaseExpr::When(box e, box ck, _) => {
+                self.name_expr(e, resets);
+                self.name(ck, CLOCK, resets);
+            }
+            BaseExpr::Merge(box ck, branches) => {
+                self.name(ck, CLOCK, resets);
+                branches
+                    .iter_mut()
+                    .for_each(|(_, e)| self.name_expr(e, resets));
+            }
+            BaseExpr::Current(box e, _) => self.name(e, SAMPLED, &mut vec![]),
+            BaseExpr::FunCall(_, exprs, ck) | BaseExpr::StaticCall(_, _, exprs, ck) => {
+                exprs.iter_mut().for_each(|e| self.name_expr(e, resets));
+                if let Some(box ck) = ck {
+                    self.name(ck, CLOCK, resets);
+                }
+            }
+            BaseExpr::Reset(box e, r) => {
+                resets.push(r.clone());
+                self.name_expr(e, resets);
+                resets.pop();
+            }
+            BaseExpr::UnOp(_, box e)
+            | BaseExpr::Pre(box e)
+            | BaseExpr::Slice(box e, _, _)
+            | BaseExpr::Field(box e, _) => self.name_expr(e, resets),
+            BaseExpr::BinOp(_, box e1, box e2)
+            | BaseExpr::Fby(box e1, box e2)
+            | BaseExpr::Index(box e1, box e2)
+            | BaseExpr::Concat(box e1, box e2) => {
+                self.name_expr(e1, resets);
+                self.name_expr(e2, resets);
+            }
+            BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+                self.name_expr(e1, resets);
+                self.name_expr(e2, resets);
+                self.name_expr(e3, resets);
+            }
+            BaseExpr::Arrow(exprs)
+            | BaseExpr::Array(exprs)
+            | BaseExpr::Tuple(exprs)
+            | BaseExpr::Iterate(_, _, _, exprs) => {
+                exprs.iter_mut().for_each(|e| self.name_expr(e, resets))
+            }
+            BaseExpr::Record(fields) => fields
+                .iter_mut()
+                .for_each(|(_, e)| self.name_expr(e, resets)),
+            BaseExpr::With(box e, fields) => {
+                self.name_expr(e, resets);
+                fields
+                    .iter_mut()
+                    .for_each(|(_, e)| self.name_expr(e, resets));
+            }
+        }
+    }
+
+    /// Replace an expression which is not a variable by a new variable
+    /// defined by it
+    fn name(&mut self, expr: &mut Expr, prefix: &'static str, resets: &mut Vec<String>) {
+        self.name_expr(expr, resets);
+        if let BaseExpr::Var(_) = expr.expr {
+            return;
+        }
+        let span = expr.span;
+        let key = if prefix == CLOCK {
+            stateless_key(expr)
+        } else {
+            None
+        };
+        if let Some(name) = key.as_ref().and_then(|key| self.stateless.get(key)) {
+            *expr = var(name, span);
+            return;
+        }
+        let count = self.counts.entry(prefix).or_insert(0);
+        *count += 1;
+        let name = format!("{}{}", prefix, count);
+        let def = std::mem::replace(expr, var(&name, span));
+        let def = match key {
+            Some(key) => {
+                self.stateless.insert(key, name.clone());
+                def
+            }
+            None => resets.iter().rev().fold(def, |e, r| reset_by(e, r)),
+        };
+        self.eq_list.push(Equation {
+            idents: vec![name],
+            expr: def,
+            span,
+            pragmas: vec![],
+        });
+    }
+}
+
+/// Describe an expression which has no state, so that the expressions
+/// which are equal have the same description
+fn stateless_key(expr: &Expr) -> Option<String> {
+    match &expr.expr {
+        BaseExpr::Var(name) => Some(name.clone()),
+        BaseExpr::Value(v) => Some(format!("{:?}", v)),
+        BaseExpr::UnOp(op, box e) => Some(format!("{:?}({})", op, stateless_key(e)?)),
+        BaseExpr::BinOp(op, box e1, box e2) => Some(format!(
+            "{:?}({}, {})",
+            op,
+            stateless_key(e1)?,
+            stateless_key(e2)?
+        )),
+        BaseExpr::IfThenElse(box e1, box e2, box e3) => Some(format!(
+            "if({}, {}, {})",
+            stateless_key(e1)?,
+            stateless_key(e2)?,
+            stateless_key(e3)?
+        )),
+        _ => None,
+    }
+}
+
+/// State used to compile the blocks of a node
+struct Expander<'a> {
+    node: String,
+    /// The node is a function, which cannot contain an automaton
+    function: bool,
+    /// Type of the variables of the node, including the generated ones,
+    /// None if it is inferred during typing
+    variables: HashMap<String, Option<Type>>,
+    /// Names that cannot be given to a generated variable
+    names: HashSet<String>,
+    types: &'a mut Vec<TypeDecl>,
+    constructors: &'a mut HashSet<String>,
+    /// Sampled copies of the variables, given the variable and the sampling
+    samples: HashMap<(String, String, String), String>,
+    local_params: Vec<(String, Option<Type>, Option<Clock>)>,
+    eq_list: Vec<Equation>,
+    errors: Vec<Diagnostic>,
+}
+
+/// Renaming of the variables in a state of an automaton
+/// The variables defined by the automaton are replaced by their value in
+/// the state, and the other variables are sampled on the state
+struct Renaming {
+    defined: HashMap<String, String>,
+    ck: String,
+    constructor: String,
+    clock: Vec<(String, Value)>,
+}
+
+impl<'a> Expander<'a> {
+    fn new(
+        node: &Node,
+        global_names: &HashSet<String>,
+        types: &'a mut Vec<TypeDecl>,
+        constructors: &'a mut HashSet<String>,
+    ) -> Expander<'a> {
+        let variables: HashMap<String, Option<Type>> = node
+            .in_params
+            .iter()
+            .chain(node.out_params.iter())
+            .map(|(name, typ)| (name.clone(), Some(typ.clone())))
+            .chain(
+                node.local_params
+                    .iter()
+                    .map(|(name, (typ, _))| (name.clone(), typ.clone())),
+            )
+            .collect();
+        let names = variables
+            .keys()
+            .cloned()
+            .chain(global_names.iter().cloned())
+            .chain(constructors.iter().cloned())
+            .collect();
+        Expander {
+            node: node.name.clone(),
+            function: node.function,
+            variables,
+            names,
+            types,
+            constructors,
+            samples: HashMap::new(),
+            local_params: vec![],
+            eq_list: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Get a name that is not used yet, based on the given name
+    fn fresh(&mut self, base: &str) -> String {
+        let mut name = base.to_owned();
+        let mut i = 1;
+        while self.names.contains(&name) {
+            name = format!("{}_{}", base, i);
+            i += 1;
+        }
+        self.names.insert(name.clone());
+        name
+    }
+
+    /// Declare a new local variable
+    fn declare(&mut self, base: &str, typ: Type, clock: &Vec<(String, Value)>) -> String {
+        let name = self.fresh(base);
+        self.variables.insert(name.clone(), Some(typ.clone()));
+        self.local_params
+            .push((name.clone(), Some(typ), Some(Clock::Ck(clock.clone()))));
+        name
+    }
+
+    /// Declare a new local variable with the type of another variable,
+    /// which may only be known after typing
+    fn declare_copy(&mut self, base: &str, of: &str, clock: &Vec<(String, Value)>) -> String {
+        let name = self.fresh(base);
+        let typ = self.variables[of].clone();
+        self.variables.insert(name.clone(), typ.clone());
+        self.local_params
+            .push((name.clone(), typ, Some(Clock::Ck(clock.clone()))));
+        name
+    }
+
+    /// Add an equation defining a single variable
+    fn define(&mut self, name: &str, expr: Expr) {
+        let span = expr.span;
+        self.eq_list.push(Equation {
+            idents: vec![name.to_owned()],
+            expr,
+            span,
+            pragmas: vec![],
+        });
+    }
+
+    /// Define the condition of an assertion by an equation on a new boolean
+    /// variable, which replaces it. An assertion written `assert e;` is an
+    /// assumption if its condition only uses the inputs of the node
+    fn expand_assert(&mut self, assert: &mut Assert, inputs: &HashSet<&String>) {
+        if assert.kind == AssertionKind::Assert {
+            let mut used = vec![];
+            used_variables(&assert.expr, &mut used);
+            assert.assumption = used
+                .into_iter()
+                .all(|name| inputs.contains(name) || !self.variables.contains_key(name));
+        }
+        let span = assert.expr.span;
+        let name = self.declare("assert", Type::Bool, &vec![]);
+        let expr = std::mem::replace(&mut assert.expr, var(&name, span));
+        self.define(&name, expr);
+    }
+
+    /// Compile a block whose variables are on the given clock
+    /// reset is a variable on the same clock that resets the block
+    fn expand(&mut self, block: Block, clock: Vec<(String, Value)>, reset: Option<String>) {
+        match block {
+            Block::Automaton(automaton) => self.expand_automaton(automaton, clock, reset),
+            Block::Reset(block) => self.expand_reset(block, clock, reset),
+        }
+    }
+
+    /// Compile a reset block, the expression of each of its equations is
+    /// reset by a variable that is true when the condition of the block or
+    /// one of the enclosing blocks is true
+    fn expand_reset(
+        &mut self,
+        block: ResetBlock,
+        clock: Vec<(String, Value)>,
+        reset: Option<String>,
+    ) {
+        let span = block.span;
+        let r = match (&reset, block.cond.expr) {
+            (None, BaseExpr::Var(r)) if self.variables.contains_key(&r) => r,
+            (_, cond) => {
+                let cond = Expr::new(cond, block.cond.span);
+                let r = self.declare("_reset", Type::Bool, &clock);
+                let r_expr = match &reset {
+                    Some(reset) => binop(BinOp::Or, var(reset, span), reset_by(cond, reset)),
+                    None => cond,
+                };
+                self.define(&r, r_expr);
+                r
+            }
+        };
+        for mut eq in block.eq_list {
+            eq.expr = reset_by(eq.expr, &r);
+            self.eq_list.push(eq);
+        }
+        for inner in block.blocks {
+            self.expand(inner, clock.clone(), Some(r.clone()));
+        }
+    }
+
+    /// Compile an automaton whose variables are on the given clock
+    /// reset is a variable on the same clock that resets the automaton
+    fn expand_automaton(
+        &mut self,
+        automaton: Automaton,
+        clock: Vec<(String, Value)>,
+        reset: Option<String>,
+    ) {
+        // The state of the automaton would be reported as the fby of its
+        // compiled equations
+        if self.function {
+            self.errors.push(Diagnostic::new(
+                format!(
+                    "The function {} cannot contain an automaton, which needs a state",
+                    self.node
+                ),
+                automaton.span,
+            ));
+            return;
+        }
+        if let Err(diagnostic) = self.check_automaton(&automaton) {
+            self.errors.push(diagnostic);
+            return;
+        }
+        let span = automaton.span;
+        let defined = defined_variables(&automaton);
+        for var in &defined {
+            if !self.variables.contains_key(var) {
+                self.errors.push(Diagnostic::new(
+                    format!("Variable {} used but not declared", var),
+                    span,
+                ));
+                return;
+            }
+        }
+
+        let type_name = self.fresh(&format!("{}_automaton", self.node));
+        let mut state_constructors = vec![];
+        for state in &automaton.states {
+            let constructor = self.fresh(&state.name);
+            self.constructors.insert(constructor.clone());
+            state_constructors.push(constructor);
+        }
+        self.types.push(TypeDecl {
+            name: type_name.clone(),
+            def: TypeDef::Enum(state_constructors.clone()),
+            span,
+        });
+        let state_indices: HashMap<String, usize> = automaton
+            .states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| (state.name.clone(), i))
+            .collect();
+        let state_index = |name: &String| *state_indices.get(name).unwrap();
+        let state_value = |i: usize| Value::Enum(type_name.clone(), state_constructors[i].clone());
+        let state_type = Type::Named(type_name.clone());
+
+        let st = self.declare("state", state_type.clone(), &clock);
+        let ps = self.declare("pstate", state_type.clone(), &clock);
+        let ns = self.declare("nstate", state_type.clone(), &clock);
+        let r = self.declare("reset", Type::Bool, &clock);
+        let pnr = self.declare("preset", Type::Bool, &clock);
+        let nr = self.declare("nreset", Type::Bool, &clock);
+
+        // Previous state, and reset asked by the weak transitions
+        let ps_expr = reset_with(
+            &reset,
+            value(state_value(0), span),
+            fby(state_value(0), var(&ns, span)),
+        );
+        self.define(&ps, ps_expr);
+        let pnr_expr = reset_with(
+            &reset,
+            value(Value::Bool(true), span),
+            fby(Value::Bool(false), var(&nr, span)),
+        );
+        self.define(&pnr, pnr_expr);
+
+        // Strong transitions, evaluated in the previous state, whose
+        // conditions are reset when this state was entered by a reset
+        let mut st_branches = vec![];
+        let mut r_branches = vec![];
+        for (i, state) in automaton.states.iter().enumerate() {
+            let renaming = Renaming {
+                defined: HashMap::new(),
+                ck: ps.clone(),
+                constructor: state_constructors[i].clone(),
+                clock: with_clock(&clock, &ps, state_value(i)),
+            };
+            let mut target = value(state_value(i), state.span);
+            let pnr_state = self.sample(&pnr, &renaming, state.span);
+            let mut reset_target = var(&pnr_state, state.span);
+            for transition in state.transitions.iter().rev().filter(|t| t.strong) {
+                let cond = self.rename(transition.cond.clone(), &renaming);
+                let cond_var = self.declare("cond", Type::Bool, &renaming.clock);
+                self.define(&cond_var, reset_by(cond, &pnr_state));
+                let j = state_index(&transition.target);
+                let cond = var(&cond_var, transition.span);
+                target = ite(cond.clone(), value(state_value(j), transition.span), target);
+                reset_target = ite(
+                    cond,
+                    value(Value::Bool(transition.reset), transition.span),
+                    reset_target,
+                );
+            }
+            st_branches.push((state_constructors[i].clone(), target));
+            r_branches.push((state_constructors[i].clone(), reset_target));
+        }
+        self.define(&st, merge(&ps, st_branches, span));
+        let r_expr = merge(&ps, r_branches, span);
+        let r_expr = match &reset {
+            Some(reset) => binop(BinOp::Or, var(reset, span), r_expr),
+            None => r_expr,
+        };
+        self.define(&r, r_expr);
+
+        // Equations of the states, and weak transitions
+        let mut var_branches: Vec<Vec<(String, Expr)>> = vec![vec![]; defined.len()];
+        let mut ns_branches = vec![];
+        let mut nr_branches = vec![];
+        for (i, state) in automaton.states.into_iter().enumerate() {
+            let state_clock = with_clock(&clock, &st, state_value(i));
+            let state_reset =
+                self.declare(&format!("reset_{}", state.name), Type::Bool, &state_clock);
+            self.define(
+                &state_reset,
+                when(var(&r, state.span), &st, &state_constructors[i]),
+            );
+            let mut renaming = Renaming {
+                defined: HashMap::new(),
+                ck: st.clone(),
+                constructor: state_constructors[i].clone(),
+                clock: state_clock.clone(),
+            };
+            for (j, x) in defined.iter().enumerate() {
+                let x_state = self.declare_copy(&format!("{}_{}", x, state.name), x, &state_clock);
+                renaming.defined.insert(x.clone(), x_state.clone());
+                var_branches[j].push((state_constructors[i].clone(), var(&x_state, state.span)));
+            }
+
+            for eq in state.eq_list {
+                let idents = eq
+                    .idents
+                    .iter()
+                    .map(|x| renaming.defined.get(x).unwrap_or(x).clone())
+                    .collect();
+                let expr = self.rename(eq.expr, &renaming);
+                self.eq_list.push(Equation {
+                    idents,
+                    expr: reset_by(expr, &state_reset),
+                    span: eq.span,
+                    pragmas: eq.pragmas,
+                });
+            }
+            for inner in state.blocks {
+                let inner = self.rename_block(inner, &renaming);
+                self.expand(inner, state_clock.clone(), Some(state_reset.clone()));
+            }
+
+            let mut target = value(state_value(i), state.span);
+            let mut reset_target = value(Value::Bool(false), state.span);
+            for transition in state.transitions.into_iter().rev().filter(|t| !t.strong) {
+                let cond = self.rename(transition.cond, &renaming);
+                let cond_var = self.declare("cond", Type::Bool, &state_clock);
+                self.define(&cond_var, reset_by(cond, &state_reset));
+                let j = state_index(&transition.target);
+                let cond = var(&cond_var, transition.span);
+                target = ite(cond.clone(), value(state_value(j), transition.span), target);
+                reset_target = ite(
+                    cond,
+                    value(Value::Bool(transition.reset), transition.span),
+                    reset_target,
+                );
+            }
+            ns_branches.push((state_constructors[i].clone(), target));
+            nr_branches.push((state_constructors[i].clone(), reset_target));
+        }
+        for (x, branches) in defined.iter().zip(var_branches) {
+            self.define(x, merge(&st, branches, span));
+        }
+        self.define(&ns, merge(&st, ns_branches, span));
+        self.define(&nr, merge(&st, nr_branches, span));
+    }
+
+    /// Check that the states of an automaton have different names, that
+    /// the transitions go to declared states, and that every state defines
+    /// the same variables
+    fn check_automaton(&self, automaton: &Automaton) -> Result<(), Diagnostic> {
+        let states = &automaton.states;
+        for (i, state) in states.iter().enumerate() {
+            if states[..i].iter().any(|other| other.name == state.name) {
+                return Err(Diagnostic::new(
+                    format!("State {} was declared twice", state.name),
+                    state.span,
+                ));
+            }
+        }
+        for state in states {
+            for transition in &state.transitions {
+                if !states.iter().any(|other| other.name == transition.target) {
+                    return Err(Diagnostic::new(
+                        format!("State {} used but not declared", transition.target),
+                        transition.span,
+                    ));
+                }
+            }
+        }
+        let defined_first = block_defined_variables(&states[0].eq_list, &states[0].blocks);
+        for state in &states[1..] {
+            let defined = block_defined_variables(&state.eq_list, &state.blocks);
+            let missing = defined_first.symmetric_difference(&defined).next();
+            if let Some(var) = missing {
+                let (with, without) = if defined.contains(var) {
+                    (&state.name, &states[0].name)
+                } else {
+                    (&states[0].name, &state.name)
+                };
+                return Err(Diagnostic::new(
+                    format!(
+                        "Variable {} is defined in state {} but not in state {}",
+                        var, with, without
+                    ),
+                    automaton.span,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the copy of a variable sampled on a state
+    fn sample(&mut self, name: &str, renaming: &Renaming, span: Span) -> String {
+        let constructor = &renaming.constructor;
+        let key = (name.to_owned(), renaming.ck.clone(), constructor.clone());
+        if let Some(sampled) = self.samples.get(&key) {
+            return sampled.clone();
+        }
+        let sampled =
+            self.declare_copy(&format!("{}_{}", name, constructor), name, &renaming.clock);
+        self.define(&sampled, when(var(name, span), &renaming.ck, constructor));
+        self.samples.insert(key, sampled.clone());
+        sampled
+    }
+
+    /// Rename a variable of a state
+    fn rename_var(&mut self, name: String, renaming: &Renaming, span: Span) -> String {
+        match renaming.defined.get(&name) {
+            Some(renamed) => renamed.clone(),
+            None if self.variables.contains_key(&name) => self.sample(&name, renaming, span),
+            None => name,
+        }
+    }
+
+    /// Rename the variables of an expression of a state
+    fn rename(&mut self, expr: Expr, renaming: &Renaming) -> Expr {
+        let span = expr.span;
+        let base = match expr.expr {
+            BaseExpr::Var(name) => BaseExpr::Var(self.rename_var(name, renaming, span)),
+            BaseExpr::Value(v) => BaseExpr::Value(v),
+            BaseExpr::UnOp(op, box e) => BaseExpr::UnOp(op, box self.rename(e, renaming)),
+            BaseExpr::BinOp(op, box e1, box e2) => {
+                BaseExpr::BinOp(op, box self.rename(e1, renaming), box self.rename(e2, renaming))
+            }
+            BaseExpr::When(box e, box ck, c) => {
+                let e = self.rename(e, renaming);
+                BaseExpr::When(box e, box self.rename(ck, renaming), c)
+            }
+            BaseExpr::Merge(box ck, branches) => {
+                let ck = self.rename(ck, renaming);
+                let branches = branches
+                    .into_iter()
+                    .map(|(c, e)| (c, self.rename(e, renaming)))
+                    .collect();
+                BaseExpr::Merge(box ck, branches)
+            }
+            BaseExpr::Fby(box e1, box e2) => {
+                BaseExpr::Fby(box self.rename(e1, renaming), box self.rename(e2, renaming))
+            }
+            BaseExpr::IfThenElse(box c, box t, box e) => BaseExpr::IfThenElse(
+                box self.rename(c, renaming),
+                box self.rename(t, renaming),
+                box self.rename(e, renaming),
+            ),
+            BaseExpr::FunCall(fun, args, ck) => {
+                let args = args.into_iter().map(|e| self.rename(e, renaming)).collect();
+                let ck = ck.map(|ck| box self.rename(*ck, renaming));
+                BaseExpr::FunCall(fun, args, ck)
+            }
+            BaseExpr::StaticCall(fun, static_args, args, ck) => {
+                let args = args.into_iter().map(|e| self.rename(e, renaming)).collect();
+                let ck = ck.map(|ck| box self.rename(*ck, renaming));
+                BaseExpr::StaticCall(fun, static_args, args, ck)
+            }
+            BaseExpr::Iterate(kind, fun, n, args) => {
+                let args = args.into_iter().map(|e| self.rename(e, renaming)).collect();
+                BaseExpr::Iterate(kind, fun, n, args)
+            }
+            BaseExpr::Current(x, v) => {
+                let current = Expr::new(BaseExpr::Current(x, v), span);
+                return when(current, &renaming.ck, &renaming.constructor);
+            }
+            BaseExpr::Pre(box e) => BaseExpr::Pre(box self.rename(e, renaming)),
+            BaseExpr::Arrow(exprs) => BaseExpr::Arrow(
+                exprs
+                    .into_iter()
+                    .map(|e| self.rename(e, renaming))
+                    .collect(),
+            ),
+            BaseExpr::Array(exprs) => BaseExpr::Array(
+                exprs
+                    .into_iter()
+                    .map(|e| self.rename(e, renaming))
+                    .collect(),
+            ),
+            BaseExpr::Tuple(exprs) => BaseExpr::Tuple(
+                exprs
+                    .into_iter()
+                    .map(|e| self.rename(e, renaming))
+                    .collect(),
+            ),
+            BaseExpr::Index(box e, box i) => {
+                BaseExpr::Index(box self.rename(e, renaming), box self.rename(i, renaming))
+            }
+            BaseExpr::Slice(box e, i, j) => BaseExpr::Slice(box self.rename(e, renaming), i, j),
+            BaseExpr::Concat(box e1, box e2) => {
+                BaseExpr::Concat(box self.rename(e1, renaming), box self.rename(e2, renaming))
+            }
+            BaseExpr::Record(fields) => BaseExpr::Record(
+                fields
+                    .into_iter()
+                    .map(|(f, e)| (f, self.rename(e, renaming)))
+                    .collect(),
+            ),
+            BaseExpr::Field(box e, f) => BaseExpr::Field(box self.rename(e, renaming), f),
+            BaseExpr::With(box e, fields) => {
+                let e = self.rename(e, renaming);
+                let fields = fields
+                    .into_iter()
+                    .map(|(f, e)| (f, self.rename(e, renaming)))
+                    .collect();
+                BaseExpr::With(box e, fields)
+            }
+            BaseExpr::Reset(box e, r) => {
+                let e = self.rename(e, renaming);
+                BaseExpr::Reset(box e, self.rename_var(r, renaming, span))
+            }
+        };
+        Expr::new(base, span)
+    }
+
+    /// Rename the variables of the equations of a block nested in a state
+    fn rename_equations(&mut self, eq_list: Vec<Equation>, renaming: &Renaming) -> Vec<Equation> {
+        let mut renamed = vec![];
+        for mut eq in eq_list {
+            let span = eq.span;
+            eq.idents = eq
+                .idents
+                .into_iter()
+                .map(|x| self.rename_var(x, renaming, span))
+                .collect();
+            eq.expr = self.rename(eq.expr, renaming);
+            renamed.push(eq);
+        }
+        renamed
+    }
+
+    /// Rename the variables of a block nested in a state
+    /// The nested block is reset with the state when it is expanded
+    fn rename_block(&mut self, block: Block, renaming: &Renaming) -> Block {
+        match block {
+            Block::Automaton(automaton) => {
+                let mut states = vec![];
+                for mut state in automaton.states {
+                    state.eq_list = self.rename_equations(state.eq_list, renaming);
+                    state.blocks = state
+                        .blocks
+                        .into_iter()
+                        .map(|inner| self.rename_block(inner, renaming))
+                        .collect();
+                    for transition in &mut state.transitions {
+                        transition.cond = self.rename(transition.cond.clone(), renaming);
+                    }
+                    states.push(state);
+                }
+                Block::Automaton(Automaton {
+                    states,
+                    span: automaton.span,
+                })
+            }
+            Block::Reset(block) => Block::Reset(ResetBlock {
+                eq_list: self.rename_equations(block.eq_list, renaming),
+                blocks: block
+                    .blocks
+                    .into_iter()
+                    .map(|inner| self.rename_block(inner, renaming))
+                    .collect(),
+                cond: self.rename(block.cond, renaming),
+                span: block.span,
+            }),
+        }
+    }
+}
+
+/// Get the names used in an expression, which are variables or constants
+fn used_variables<'e>(expr: &'e Expr, used: &mut Vec<&'e String>) {
+    match &expr.expr {
+        BaseExpr::Value(_) => (),
+        BaseExpr::Var(name) => used.push(name),
+        BaseExpr::UnOp(_, box e)
+        | BaseExpr::Current(box e, _)
+        | BaseExpr::Pre(box e)
+        | BaseExpr::Slice(box e, _, _)
+        | BaseExpr::Field(box e, _) => used_variables(e, used),
+        BaseExpr::Reset(box e, ck) => {
+            used.push(ck);
+            used_variables(e, used);
+        }
+        BaseExpr::BinOp(_, box e1, box e2)
+        | BaseExpr::When(box e1, box e2, _)
+        | BaseExpr::Fby(box e1, box e2)
+        | BaseExpr::Index(box e1, box e2)
+        | BaseExpr::Concat(box e1, box e2) => {
+            used_variables(e1, used);
+            used_variables(e2, used);
+        }
+        BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+            used_variables(e1, used);
+            used_variables(e2, used);
+            used_variables(e3, used);
+        }
+        BaseExpr::Merge(box ck, branches) => {
+            used_variables(ck, used);
+            branches.iter().for_each(|(_, e)| used_variables(e, used));
+        }
+        BaseExpr::FunCall(_, exprs, ck) | BaseExpr::StaticCall(_, _, exprs, ck) => {
+            ck.iter().for_each(|ck| used_variables(ck, used));
+            exprs.iter().for_each(|e| used_variables(e, used));
+        }
+        BaseExpr::Arrow(exprs)
+        | BaseExpr::Array(exprs)
+        | BaseExpr::Tuple(exprs)
+        | BaseExpr::Iterate(_, _, _, exprs) => exprs.iter().for_each(|e| used_variables(e, used)),
+        BaseExpr::Record(fields) => fields.iter().for_each(|(_, e)| used_variables(e, used)),
+        BaseExpr::With(box e, fields) => {
+            used_variables(e, used);
+            fields.iter().for_each(|(_, e)| used_variables(e, used));
+        }
+    }
+}
+
+/// Get the variables defined by an automaton
+fn defined_variables(automaton: &Automaton) -> Vec<String> {
+    let state = &automaton.states[0];
+    let mut defined: Vec<String> = block_defined_variables(&state.eq_list, &state.blocks)
+        .into_iter()
+        .collect();
+    defined.sort();
+    defined
+}
+
+/// Get the variables defined by equations and the blocks next to them
+fn block_defined_variables(eq_list: &Vec<Equation>, blocks: &Vec<Block>) -> HashSet<String> {
+    let mut defined: HashSet<String> = eq_list
+        .iter()
+        .flat_map(|eq| eq.idents.iter().filter(|x| *x != WILDCARD).cloned())
+        .collect();
+    for block in blocks {
+        match block {
+            Block::Automaton(automaton) => {
+                for state in &automaton.states {
+                    defined.extend(block_defined_variables(&state.eq_list, &state.blocks));
+                }
+            }
+            Block::Reset(block) => {
+                defined.extend(block_defined_variables(&block.eq_list, &block.blocks))
+            }
+        }
+    }
+    defined
+}
+
+/// Add a sampling to a clock
+fn with_clock(clock: &Vec<(String, Value)>, ck: &str, value: Value) -> Vec<(String, Value)> {
+    let mut clock = clock.clone();
+    clock.push((ck.to_owned(), value));
+    clock
+}
+
+/// Replace an expression by a value when a reset variable is true
+fn reset_with(reset: &Option<String>, init: Expr, expr: Expr) -> Expr {
+    match reset {
+        None => expr,
+        Some(reset) => {
+            let span = expr.span;
+            ite(var(reset, span), init, expr)
+        }
+    }
+}
+
+/// Reset the state of an expression when a variable is true
+fn reset_by(e: Expr, reset: &str) -> Expr {
+    let span = e.span;
+    Expr::new(BaseExpr::Reset(box e, reset.to_owned()), span)
+}
+
+fn var(name: &str, span: Span) -> Expr {
+    Expr::new(BaseExpr::Var(name.to_owned()), span)
+}
+
+fn value(v: Value, span: Span) -> Expr {
+    Expr::new(BaseExpr::Value(v), span)
+}
+
+fn fby(v: Value, e: Expr) -> Expr {
+    let span = e.span;
+    Expr::new(BaseExpr::Fby(box value(v, span), box e), span)
+}
+
+fn ite(c: Expr, t: Expr, e: Expr) -> Expr {
+    let span = c.span;
+    Expr::new(BaseExpr::IfThenElse(box c, box t, box e), span)
+}
+
+fn binop(op: BinOp, e1: Expr, e2: Expr) -> Expr {
+    let span = e1.span;
+    Expr::new(BaseExpr::BinOp(op, box e1, box e2), span)
+}
+
+fn when(e: Expr, ck: &str, constructor: &str) -> Expr {
+    let span = e.span;
+    Expr::new(BaseExpr::When(box e, box var(ck, span), constructor.to_owned()), span)
+}
+
+fn merge(ck: &str, branches: Vec<(String, Expr)>, span: Span) -> Expr {
+    Expr::new(BaseExpr::Merge(box var(ck, span), branches), span)
+}
@@ -0,0 +1,113 @@
+//! Check that the nodes declared with `function` have no state: they cannot
+//! use fby, pre, -> and current, nor call a node with a state. Their automata
+//! are reported when the blocks are compiled.
+
+use crate::ast::{ImportedNode, Span};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{BaseExpr, Expr, Node};
+
+use std::collections::HashSet;
+use std::iter::once;
+
+/// Check that the functions of a program have no state
+pub fn check_functions(
+    nodes: &Vec<Node>,
+    imported: &Vec<ImportedNode>,
+) -> Result<(), Vec<Diagnostic>> {
+    let stateful = stateful_nodes(nodes, imported);
+    let mut errors = vec![];
+    for node in nodes.iter().filter(|node| node.function) {
+        let mut uses = vec![];
+        for eq in &node.eq_list {
+            get_stateful_uses(&eq.expr, &stateful, &mut uses);
+        }
+        errors.extend(uses.into_iter().map(|(what, span)| {
+            Diagnostic::new(format!("The function {} cannot {}", node.name, what), span)
+        }));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Get the names of the nodes with a state
+/// A node has a state if it uses fby, pre, -> or current, or calls
+/// a node with a state. The extern nodes have a state.
+fn stateful_nodes(nodes: &Vec<Node>, imported: &Vec<ImportedNode>) -> HashSet<String> {
+    let mut stateful: HashSet<String> = imported
+        .iter()
+        .filter(|node| node.stateful)
+        .map(|node| node.name.clone())
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in nodes {
+            if stateful.contains(&node.name) {
+                continue;
+            }
+            let mut uses = vec![];
+            for eq in &node.eq_list {
+                get_stateful_uses(&eq.expr, &stateful, &mut uses);
+            }
+            if !uses.is_empty() {
+                stateful.insert(node.name.clone());
+                changed = true;
+            }
+        }
+    }
+    stateful
+}
+
+/// Get the constructs of an expression which need a state, described
+/// for the error messages, with their span
+fn get_stateful_uses(expr: &Expr, stateful: &HashSet<String>, uses: &mut Vec<(String, Span)>) {
+    let sub_exprs: Vec<&Expr> = match &expr.expr {
+        BaseExpr::Value(_) | BaseExpr::Var(_) => vec![],
+        BaseExpr::Current(_, _) => {
+            uses.push((String::from("use current, which needs a state"), expr.span));
+            vec![]
+        }
+        BaseExpr::Fby(box init, box e) => {
+            uses.push((String::from("use fby, which needs a state"), expr.span));
+            vec![init, e]
+        }
+        BaseExpr::Pre(box e) => {
+            uses.push((String::from("use pre, which needs a state"), expr.span));
+            vec![e]
+        }
+        BaseExpr::Arrow(exprs) => {
+            uses.push((String::from("use ->, which needs a state"), expr.span));
+            exprs.iter().collect()
+        }
+        BaseExpr::FunCall(fun, exprs, _) | BaseExpr::Iterate(_, fun, _, exprs) => {
+            if stateful.contains(fun) {
+                uses.push((
+                    format!("call the node {}, which has a state", fun),
+                    expr.span,
+                ));
+            }
+            exprs.iter().collect()
+        }
+        BaseExpr::UnOp(_, box e)
+        | BaseExpr::When(box e, _, _)
+        | BaseExpr::Slice(box e, _, _)
+        | BaseExpr::Field(box e, _)
+        | BaseExpr::Reset(box e, _) => vec![e],
+        BaseExpr::BinOp(_, box e1, box e2)
+        | BaseExpr::Index(box e1, box e2)
+        | BaseExpr::Concat(box e1, box e2) => vec![e1, e2],
+        BaseExpr::IfThenElse(box e1, box e2, box e3) => vec![e1, e2, e3],
+        BaseExpr::Array(exprs) | BaseExpr::Tuple(exprs) => exprs.iter().collect(),
+        BaseExpr::Merge(_, branches) | BaseExpr::Record(branches) => {
+            branches.iter().map(|(_, e)| e).collect()
+        }
+        BaseExpr::With(box e, fields) => once(e).chain(fields.iter().map(|(_, e)| e)).collect(),
+        BaseExpr::StaticCall(_, _, _, _) => unreachable!(),
+    };
+    for e in sub_exprs {
+        get_stateful_uses(e, stateful, uses);
+    }
+}
@@ -1,16 +1,42 @@
 //! Contains the AST for the typed languages with clock annotations
 
-use crate::ast::{BinOp, Clock, Type, UnOp, Value};
+use crate::ast::{Assertion, AssertionKind, BinOp, Clock, IteratorKind, Span, Type, UnOp, Value};
 
 use std::collections::HashMap;
+use std::iter::once;
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
     pub in_params: Vec<(String, Type)>,
     pub out_params: Vec<(String, Type)>,
+    /// Clocks of the inputs and outputs that are not on the base clock
+    pub param_clocks: HashMap<String, Clock>,
+    /// Local variables, with their inferred clock
     pub local_params: HashMap<String, (Type, Clock)>,
     pub eq_list: Vec<(Vec<String>, Expr)>,
+    pub assertions: Vec<Assertion>,
+    pub span: Span,
+}
+
+impl Node {
+    /// Get the assertions on the inputs of the node, which verification
+    /// backends can use as assumptions
+    pub fn assumptions(&self) -> Vec<&Assertion> {
+        self.assertions
+            .iter()
+            .filter(|assertion| assertion.assumption)
+            .collect()
+    }
+
+    /// Get the guarantees of the contract of the node, which verification
+    /// backends should prove under its assumptions
+    pub fn guarantees(&self) -> Vec<&Assertion> {
+        self.assertions
+            .iter()
+            .filter(|assertion| assertion.kind == AssertionKind::Guarantee)
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +44,7 @@ pub struct Expr {
     pub expr: BaseExpr,
     pub typ: Vec<Type>,
     pub clock: Clock,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -25,13 +52,52 @@ pub enum BaseExpr {
     Value(Value),
     UnOp(UnOp, Box<Expr>),
     BinOp(BinOp, Box<Expr>, Box<Expr>),
-    When(Box<Expr>, String, bool),
-    Merge(String, Box<Expr>, Box<Expr>),
-    Fby(Value, Box<Expr>),
+    When(Box<Expr>, String, Value),
+    Merge(String, Vec<(Value, Expr)>),
+    Fby(Box<Expr>, Box<Expr>),
     IfThenElse(Box<Expr>, Box<Expr>, Box<Expr>),
     Var(String),
-    FunCall(String, Vec<Expr>, Option<String>),
+    /// Call of a node, with the clocks of its outputs
+    FunCall(String, Vec<Expr>, Option<String>, Vec<Clock>),
+    Iterate(IteratorKind, String, usize, Vec<Expr>),
     Current(String, Value),
     Pre(Box<Expr>),
     Arrow(Vec<Expr>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, usize, usize),
+    Concat(Box<Expr>, Box<Expr>),
+    Record(String, Vec<(String, Expr)>),
+    Field(Box<Expr>, String),
+    With(Box<Expr>, Vec<(String, Expr)>),
+    Reset(Box<Expr>, String),
+    Tuple(Vec<Expr>),
+}
+
+impl Expr {
+    /// Get the subexpressions of an expression
+    pub fn sub_exprs(&self) -> Vec<&Expr> {
+        match &self.expr {
+            BaseExpr::Value(_) | BaseExpr::Var(_) | BaseExpr::Current(_, _) => vec![],
+            BaseExpr::UnOp(_, box e)
+            | BaseExpr::When(box e, _, _)
+            | BaseExpr::Pre(box e)
+            | BaseExpr::Slice(box e, _, _)
+            | BaseExpr::Field(box e, _)
+            | BaseExpr::Reset(box e, _) => vec![e],
+            BaseExpr::BinOp(_, box e1, box e2)
+            | BaseExpr::Fby(box e1, box e2)
+            | BaseExpr::Index(box e1, box e2)
+            | BaseExpr::Concat(box e1, box e2) => vec![e1, e2],
+            BaseExpr::IfThenElse(box e1, box e2, box e3) => vec![e1, e2, e3],
+            BaseExpr::FunCall(_, exprs, _, _)
+            | BaseExpr::Iterate(_, _, _, exprs)
+            | BaseExpr::Arrow(exprs)
+            | BaseExpr::Array(exprs)
+            | BaseExpr::Tuple(exprs) => exprs.iter().collect(),
+            BaseExpr::Merge(_, branches) => branches.iter().map(|(_, e)| e).collect(),
+            BaseExpr::Record(_, fields) => fields.iter().map(|(_, e)| e).collect(),
+            BaseExpr::With(box e, fields) => once(e).chain(fields.iter().map(|(_, e)| e)).collect(),
+        }
+    }
 }
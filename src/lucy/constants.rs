@@ -0,0 +1,502 @@
+//! Evaluate the constants of a LucyRS program, and replace them by their
+//! values in the types and the nodes, so that the following passes never
+//! see them.
+//!
+//! Constants are declared at file scope, or in a node before its local
+//! variables. They can be declared in any order, and the constants of a
+//! node hide the global constants with the same name.
+
+use crate::ast::{BinOp, Constant, Span, Type, TypeDecl, TypeDef, UnOp, Value};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{BaseExpr, ConstDecl, Expr, Node, Program};
+
+use std::collections::HashMap;
+
+/// Constants visible from a part of the program
+pub struct Scope<'a> {
+    decls: HashMap<&'a str, &'a ConstDecl>,
+    values: HashMap<String, Value>,
+    outer: Option<&'a Scope<'a>>,
+    /// Constants being evaluated, used to detect cyclic definitions
+    visiting: Vec<String>,
+    types: &'a Vec<TypeDecl>,
+    constructors: &'a HashMap<String, String>,
+}
+
+/// Evaluate the constants of a program, and substitute them
+/// Return the program without constants, and the value of each constant
+pub fn substitute_constants(program: Program) -> Result<(Program, Vec<Constant>), Vec<Diagnostic>> {
+    let Program {
+        types,
+        constants: global_decls,
+        nodes,
+        mut imported,
+        ..
+    } = program;
+    let constructors = constructors(&types);
+
+    let mut errors = vec![];
+    let mut globals = Scope::new(&global_decls, None, &types, &constructors);
+    let mut constants = vec![];
+    match globals.evaluate_all(&global_decls) {
+        Ok(values) => constants.extend(values.into_iter().map(|(name, value)| Constant {
+            name,
+            value,
+            node: None,
+        })),
+        Err(mut diagnostics) => errors.append(&mut diagnostics),
+    }
+
+    let mut new_types = vec![];
+    for decl in &types {
+        match substitute_type_decl(decl.clone(), &globals) {
+            Ok(decl) => new_types.push(decl),
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+
+    let mut new_nodes = vec![];
+    for node in nodes {
+        let mut scope = Scope::new(&node.constants, Some(&globals), &types, &constructors);
+        let context = format!("Error while evaluating the constants of node {}", node.name);
+        match scope.evaluate_all(&node.constants) {
+            Ok(values) => constants.extend(values.into_iter().map(|(name, value)| Constant {
+                name,
+                value,
+                node: Some(node.name.clone()),
+            })),
+            Err(diagnostics) => {
+                errors.extend(diagnostics.into_iter().map(|d| d.with_context(&context)));
+                continue;
+            }
+        }
+        match substitute_node(node.clone(), &scope) {
+            Ok(node) => new_nodes.push(node),
+            Err(diagnostics) => {
+                errors.extend(diagnostics.into_iter().map(|d| d.with_context(&context)))
+            }
+        }
+    }
+
+    for node in &mut imported {
+        let params = node.in_params.iter_mut().chain(node.out_params.iter_mut());
+        for (_, typ) in params {
+            match globals.substitute_type(typ, node.span) {
+                Ok(new_typ) => *typ = new_typ,
+                Err(diagnostic) => errors.push(diagnostic),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        let program = Program {
+            types: new_types,
+            constants: vec![],
+            nodes: new_nodes,
+            imported,
+            ..Default::default()
+        };
+        Ok((program, constants))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Evaluate the global constants of a program, to report their errors
+/// before the passes that use their values
+pub fn evaluate_globals(program: &Program) -> Result<(), Vec<Diagnostic>> {
+    let constructors = constructors(&program.types);
+    let mut globals = Scope::new(&program.constants, None, &program.types, &constructors);
+    globals.evaluate_all(&program.constants).map(|_| ())
+}
+
+/// Get the type of each enum constructor
+pub fn constructors(types: &Vec<TypeDecl>) -> HashMap<String, String> {
+    let mut constructors = HashMap::new();
+    for decl in types {
+        if let TypeDef::Enum(names) = &decl.def {
+            for name in names {
+                constructors.insert(name.clone(), decl.name.clone());
+            }
+        }
+    }
+    constructors
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(
+        decls: &'a Vec<ConstDecl>,
+        outer: Option<&'a Scope<'a>>,
+        types: &'a Vec<TypeDecl>,
+        constructors: &'a HashMap<String, String>,
+    ) -> Scope<'a> {
+        Scope {
+            decls: decls.iter().map(|decl| (decl.name.as_str(), decl)).collect(),
+            values: HashMap::new(),
+            outer,
+            visiting: vec![],
+            types,
+            constructors,
+        }
+    }
+
+    /// Evaluate all the constants declared in the scope, in the declaration order
+    pub fn evaluate_all(&mut self, decls: &Vec<ConstDecl>) -> Result<Vec<(String, Value)>, Vec<Diagnostic>> {
+        let mut errors = vec![];
+        let mut values = vec![];
+        for (i, decl) in decls.iter().enumerate() {
+            if decls[..i].iter().any(|other| other.name == decl.name) {
+                errors.push(Diagnostic::new(
+                    format!("Constant {} was declared twice", decl.name),
+                    decl.span,
+                ));
+            } else if self.constructors.contains_key(&decl.name) {
+                errors.push(Diagnostic::new(
+                    format!("Constant {} has the name of a constructor", decl.name),
+                    decl.span,
+                ));
+            } else {
+                match self.lookup(&decl.name, decl.span) {
+                    Ok(Some(value)) => values.push((decl.name.clone(), value)),
+                    Ok(None) => unreachable!(),
+                    Err(diagnostic) => errors.push(diagnostic),
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(values)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Get the value of a constant, evaluating it if needed
+    /// Return None if there is no constant with this name
+    fn lookup(&mut self, name: &str, span: Span) -> Result<Option<Value>, Diagnostic> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(Some(value.clone()));
+        }
+        match self.decls.get(name).cloned() {
+            Some(decl) => {
+                if self.visiting.iter().any(|other| other == name) {
+                    return Err(Diagnostic::new(
+                        format!("Constant {} is defined in terms of itself", name),
+                        span,
+                    ));
+                }
+                self.visiting.push(name.to_owned());
+                let value = self.evaluate_decl(decl);
+                self.visiting.pop();
+                let value = value?;
+                self.values.insert(name.to_owned(), value.clone());
+                Ok(Some(value))
+            }
+            None => Ok(self.outer.and_then(|outer| outer.get(name))),
+        }
+    }
+
+    /// Get the value of a constant that was already evaluated
+    fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.outer.and_then(|outer| outer.get(name)),
+        }
+    }
+
+    /// Evaluate a constant declaration, and check its type
+    fn evaluate_decl(&mut self, decl: &ConstDecl) -> Result<Value, Diagnostic> {
+        let value = self.evaluate(&decl.expr)?;
+        let typ = TypeDecl::resolve(self.types, &decl.typ)
+            .map_err(|message| Diagnostic::new(message, decl.span))?;
+        let typ = self.substitute_type_mut(&typ, decl.span)?;
+        if value.get_type() != typ {
+            return Err(Diagnostic::new(
+                format!(
+                    "The constant {} has type {}, but its value has type {}",
+                    decl.name,
+                    typ,
+                    value.get_type()
+                ),
+                decl.span,
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Compute the value of a constant expression
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, Diagnostic> {
+        let span = expr.span;
+        let error = |message: &str| Err(Diagnostic::new(String::from(message), span));
+        match &expr.expr {
+            BaseExpr::Value(v) => Ok(v.clone()),
+            BaseExpr::Var(name) => match self.lookup(name, span)? {
+                Some(value) => Ok(value),
+                None => match self.constructors.get(name) {
+                    Some(typ) => Ok(Value::Enum(typ.clone(), name.clone())),
+                    None => Err(Diagnostic::new(
+                        format!("Constant {} used but not declared", name),
+                        span,
+                    )),
+                },
+            },
+            BaseExpr::UnOp(op, box e) => match (op, self.evaluate(e)?) {
+                (UnOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (UnOp::UMinus, Value::Int(i)) => match i.checked_neg() {
+                    Some(i) => Ok(Value::Int(i)),
+                    None => error(OVERFLOW),
+                },
+                (UnOp::UMinus, Value::Real(r)) => Ok(Value::Real(-r)),
+                _ => error("The operand of this unary operator has not the expected type"),
+            },
+            BaseExpr::BinOp(op, box lhs, box rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                match evaluate_binop(op.clone(), lhs, rhs) {
+                    Ok(value) => Ok(value),
+                    Err(message) => error(message),
+                }
+            }
+            BaseExpr::IfThenElse(box cond, box e_then, box e_else) => {
+                match self.evaluate(cond)? {
+                    Value::Bool(true) => self.evaluate(e_then),
+                    Value::Bool(false) => self.evaluate(e_else),
+                    _ => error("The conditional in a if statement should have type bool"),
+                }
+            }
+            BaseExpr::Array(exprs) => {
+                let mut values = vec![];
+                for e in exprs {
+                    values.push(self.evaluate(e)?);
+                }
+                if values.iter().any(|v| v.get_type() != values[0].get_type()) {
+                    return error("The elements of an array should have the same type");
+                }
+                Ok(Value::Array(values))
+            }
+            BaseExpr::Index(box e, box i) => match (self.evaluate(e)?, self.evaluate(i)?) {
+                (Value::Array(values), Value::Int(i)) => {
+                    if i < 0 || i as usize >= values.len() {
+                        error("The index is out of the bounds of the array")
+                    } else {
+                        Ok(values[i as usize].clone())
+                    }
+                }
+                _ => error("Only an array can be indexed, by an integer"),
+            },
+            _ => error("This expression cannot be evaluated at compile time"),
+        }
+    }
+
+    /// Get the size of an array given by a constant
+    fn size(&self, name: &str, span: Span) -> Result<usize, Diagnostic> {
+        match self.get(name) {
+            Some(Value::Int(n)) if n > 0 => Ok(n as usize),
+            Some(_) => Err(Diagnostic::new(
+                format!("The size {} of an array should be a positive integer", name),
+                span,
+            )),
+            None => Err(Diagnostic::new(
+                format!("Constant {} used but not declared", name),
+                span,
+            )),
+        }
+    }
+
+    /// Replace the constant sizes of a type, while constants are evaluated
+    fn substitute_type_mut(&mut self, typ: &Type, span: Span) -> Result<Type, Diagnostic> {
+        match typ {
+            Type::Array(box t, n) => Ok(Type::Array(box self.substitute_type_mut(t, span)?, *n)),
+            Type::ConstArray(box t, name) => {
+                self.lookup(name, span)?;
+                let t = self.substitute_type_mut(t, span)?;
+                Ok(Type::Array(box t, self.size(name, span)?))
+            }
+            _ => Ok(typ.clone()),
+        }
+    }
+
+    /// Replace the constant sizes of a type
+    pub fn substitute_type(&self, typ: &Type, span: Span) -> Result<Type, Diagnostic> {
+        match typ {
+            Type::Array(box t, n) => Ok(Type::Array(box self.substitute_type(t, span)?, *n)),
+            Type::ConstArray(box t, name) => Ok(Type::Array(
+                box self.substitute_type(t, span)?,
+                self.size(name, span)?,
+            )),
+            _ => Ok(typ.clone()),
+        }
+    }
+
+    /// Replace a constant used in place of a value, leaving the constructors
+    fn substitute_value(&self, value: Value) -> Value {
+        match value {
+            Value::Enum(ref typ, ref name) if typ.is_empty() => match self.get(name) {
+                Some(value) => value,
+                None => value,
+            },
+            value => value,
+        }
+    }
+}
+
+const OVERFLOW: &str = "Overflow in a constant expression";
+
+/// Compute the value of a binary operator applied to constants
+/// The operations whose result cannot be represented are errors
+fn evaluate_binop(op: BinOp, lhs: Value, rhs: Value) -> Result<Value, &'static str> {
+    let type_error = "The operands of this binary operator have not the expected types";
+    let finite = |r: f32| {
+        if r.is_finite() {
+            Ok(Value::Real(r))
+        } else {
+            Err(OVERFLOW)
+        }
+    };
+    match (op, lhs, rhs) {
+        (BinOp::Eq, lhs, rhs) if lhs.get_type() == rhs.get_type() => Ok(Value::Bool(lhs == rhs)),
+        (BinOp::Neq, lhs, rhs) if lhs.get_type() == rhs.get_type() => Ok(Value::Bool(lhs != rhs)),
+        (BinOp::Div, Value::Int(_), Value::Int(0)) | (BinOp::Mod, Value::Int(_), Value::Int(0)) => {
+            Err("Division by zero in a constant expression")
+        }
+        (BinOp::Div, Value::Real(_), Value::Real(b)) if b == 0.0 => {
+            Err("Division by zero in a constant expression")
+        }
+        (op, Value::Int(a), Value::Int(b)) => match op {
+            BinOp::Add => a.checked_add(b).map(Value::Int).ok_or(OVERFLOW),
+            BinOp::Sub => a.checked_sub(b).map(Value::Int).ok_or(OVERFLOW),
+            BinOp::Mul => a.checked_mul(b).map(Value::Int).ok_or(OVERFLOW),
+            BinOp::Div => a.checked_div(b).map(Value::Int).ok_or(OVERFLOW),
+            BinOp::Mod => a.checked_rem(b).map(Value::Int).ok_or(OVERFLOW),
+            BinOp::Lt => Ok(Value::Bool(a < b)),
+            BinOp::Le => Ok(Value::Bool(a <= b)),
+            BinOp::Gt => Ok(Value::Bool(a > b)),
+            BinOp::Ge => Ok(Value::Bool(a >= b)),
+            _ => Err(type_error),
+        },
+        (op, Value::Real(a), Value::Real(b)) => match op {
+            BinOp::Add => finite(a + b),
+            BinOp::Sub => finite(a - b),
+            BinOp::Mul => finite(a * b),
+            BinOp::Div => finite(a / b),
+            BinOp::Lt => Ok(Value::Bool(a < b)),
+            BinOp::Le => Ok(Value::Bool(a <= b)),
+            BinOp::Gt => Ok(Value::Bool(a > b)),
+            BinOp::Ge => Ok(Value::Bool(a >= b)),
+            _ => Err(type_error),
+        },
+        (op, Value::Bool(a), Value::Bool(b)) => match op {
+            BinOp::And => Ok(Value::Bool(a && b)),
+            BinOp::Or => Ok(Value::Bool(a || b)),
+            BinOp::Xor => Ok(Value::Bool(a ^ b)),
+            BinOp::Impl => Ok(Value::Bool(!a || b)),
+            _ => Err(type_error),
+        },
+        _ => Err(type_error),
+    }
+}
+
+/// Replace the constant sizes in a type declaration
+fn substitute_type_decl(decl: TypeDecl, scope: &Scope) -> Result<TypeDecl, Diagnostic> {
+    let span = decl.span;
+    let def = match decl.def {
+        TypeDef::Enum(constructors) => TypeDef::Enum(constructors),
+        TypeDef::Record(fields) => {
+            let mut new_fields = vec![];
+            for (field, typ) in fields {
+                new_fields.push((field, scope.substitute_type(&typ, span)?));
+            }
+            TypeDef::Record(new_fields)
+        }
+        TypeDef::Alias(typ) => TypeDef::Alias(scope.substitute_type(&typ, span)?),
+    };
+    Ok(TypeDecl { def, ..decl })
+}
+
+/// Replace the constants in the declarations and the equations of a node
+fn substitute_node(mut node: Node, scope: &Scope) -> Result<Node, Vec<Diagnostic>> {
+    let mut errors = vec![];
+    let var_spans = &node.var_spans;
+    let span = node.span;
+    let declared_variables = node
+        .in_params
+        .iter_mut()
+        .chain(node.out_params.iter_mut())
+        .map(|(ident, typ)| (&*ident, Some(typ)))
+        .chain(
+            node.local_params
+                .iter_mut()
+                .map(|(ident, (typ, _))| (ident, typ.as_mut())),
+        );
+    for (ident, typ) in declared_variables {
+        let var_span = var_spans.get(ident).cloned().unwrap_or(span);
+        if scope.get(ident).is_some() {
+            errors.push(Diagnostic::new(
+                format!("The variable {} has the name of a constant", ident),
+                var_span,
+            ));
+        }
+        if let Some(typ) = typ {
+            match scope.substitute_type(typ, var_span) {
+                Ok(new_typ) => *typ = new_typ,
+                Err(diagnostic) => errors.push(diagnostic),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    for eq in &mut node.eq_list {
+        substitute_expr(&mut eq.expr, scope);
+    }
+    node.constants = vec![];
+    Ok(node)
+}
+
+/// Replace the constants used in an expression by their values
+fn substitute_expr(expr: &mut Expr, scope: &Scope) {
+    match &mut expr.expr {
+        BaseExpr::Var(name) => {
+            if let Some(value) = scope.get(name) {
+                expr.expr = BaseExpr::Value(value);
+            }
+        }
+        BaseExpr::Value(_) => (),
+        BaseExpr::Current(_, value) => *value = scope.substitute_value(value.clone()),
+        BaseExpr::Iterate(_, _, n, exprs) => {
+            *n = scope.substitute_value(n.clone());
+            exprs.iter_mut().for_each(|e| substitute_expr(e, scope));
+        }
+        BaseExpr::UnOp(_, box e)
+        | BaseExpr::When(box e, _, _)
+        | BaseExpr::Pre(box e)
+        | BaseExpr::Slice(box e, _, _)
+        | BaseExpr::Field(box e, _)
+        | BaseExpr::Reset(box e, _) => substitute_expr(e, scope),
+        BaseExpr::BinOp(_, box e1, box e2)
+        | BaseExpr::Fby(box e1, box e2)
+        | BaseExpr::Index(box e1, box e2)
+        | BaseExpr::Concat(box e1, box e2) => {
+            substitute_expr(e1, scope);
+            substitute_expr(e2, scope);
+        }
+        BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+            substitute_expr(e1, scope);
+            substitute_expr(e2, scope);
+            substitute_expr(e3, scope);
+        }
+        BaseExpr::FunCall(_, exprs, _)
+        | BaseExpr::Arrow(exprs)
+        | BaseExpr::Array(exprs)
+        | BaseExpr::Tuple(exprs) => {
+            exprs.iter_mut().for_each(|e| substitute_expr(e, scope));
+        }
+        BaseExpr::StaticCall(_, _, _, _) => unreachable!(),
+        BaseExpr::Merge(_, branches) | BaseExpr::Record(branches) => {
+            branches.iter_mut().for_each(|(_, e)| substitute_expr(e, scope));
+        }
+        BaseExpr::With(box e, fields) => {
+            substitute_expr(e, scope);
+            fields.iter_mut().for_each(|(_, e)| substitute_expr(e, scope));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Parse the contracts of the nodes, written `(*@contract ... *)` in the
+//! declaration of a node or just before it, with the clauses:
+//!
+//! ```text
+//! assume e;
+//! guarantee e;
+//! mode m (require r; ensure e;);
+//! ```
+//!
+//! The assumptions are hypotheses on the environment of the node, and the
+//! guarantees should hold when they do. A mode guarantees each of its
+//! ensure when all of its require hold. The clauses are compiled like
+//! assertions, so that they are typed and checked by the generated code,
+//! which stops checking the guarantees once an assumption has failed.
+//!
+//! The words assume, guarantee, mode, require and ensure are only keywords
+//! in a contract, so the clauses are split here, and only their expressions
+//! are given to the parser.
+
+use crate::ast::{AssertionKind, BinOp, Span};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{Assert, BaseExpr, Expr, Pragma};
+
+/// Parse the clauses of a contract into assertions
+/// parse_expr parses an expression followed by `;`, given with its offset
+/// in the source map
+pub fn parse_contract<F>(pragma: &Pragma, mut parse_expr: F) -> Result<Vec<Assert>, Diagnostic>
+where
+    F: FnMut(&str, usize) -> Result<Expr, Diagnostic>,
+{
+    let mut scanner = Scanner {
+        text: &pragma.args,
+        pos: 0,
+        offset: pragma.args_start,
+    };
+    let mut asserts = vec![];
+    while !scanner.at_end() {
+        let (keyword, span) = scanner.word();
+        match keyword {
+            "assume" | "guarantee" => {
+                let (expr, text, end) = scanner.clause(&mut parse_expr)?;
+                let kind = if keyword == "assume" {
+                    AssertionKind::Assume
+                } else {
+                    AssertionKind::Guarantee
+                };
+                asserts.push(new_assert(expr, text, kind, Span::new(span.start, end)));
+            }
+            "mode" => {
+                let (name, name_span) = scanner.word();
+                if name.is_empty() {
+                    return Err(Diagnostic::new(
+                        String::from("Expected the name of the mode"),
+                        name_span,
+                    ));
+                }
+                if !scanner.eat('(') {
+                    return Err(Diagnostic::new(
+                        format!("Expected ( after the name of the mode {}", name),
+                        scanner.span(scanner.pos, scanner.pos + 1),
+                    ));
+                }
+                let mut requires = vec![];
+                let mut ensures = vec![];
+                while !scanner.eat(')') {
+                    let (keyword, span) = scanner.word();
+                    match keyword {
+                        "require" => requires.push(scanner.clause(&mut parse_expr)?.0),
+                        "ensure" => {
+                            let (expr, text, end) = scanner.clause(&mut parse_expr)?;
+                            ensures.push((expr, text, Span::new(span.start, end)));
+                        }
+                        _ => {
+                            return Err(Diagnostic::new(
+                                format!("Expected require or ensure in the mode {}", name),
+                                span,
+                            ))
+                        }
+                    }
+                }
+                scanner.eat(';');
+                let cond = requires
+                    .into_iter()
+                    .fold(None, |cond, require| match cond {
+                        None => Some(require),
+                        Some(cond) => Some(binop(BinOp::And, cond, require)),
+                    });
+                for (expr, text, span) in ensures {
+                    let expr = match &cond {
+                        None => expr,
+                        Some(cond) => binop(BinOp::Impl, cond.clone(), expr),
+                    };
+                    let text = format!("mode {} ensures {}", name, text);
+                    asserts.push(new_assert(expr, text, AssertionKind::Guarantee, span));
+                }
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    String::from("Expected assume, guarantee or mode in the contract"),
+                    span,
+                ))
+            }
+        }
+    }
+    Ok(asserts)
+}
+
+fn new_assert(expr: Expr, text: String, kind: AssertionKind, span: Span) -> Assert {
+    Assert {
+        expr,
+        text,
+        kind,
+        assumption: kind == AssertionKind::Assume,
+        span,
+    }
+}
+
+fn binop(op: BinOp, e1: Expr, e2: Expr) -> Expr {
+    let span = Span::new(e1.span.start, e2.span.end);
+    Expr::new(BaseExpr::BinOp(op, box e1, box e2), span)
+}
+
+/// Position in the arguments of a contract pragma, whose offset in the
+/// source map is given
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+    offset: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(self.offset + start, self.offset + end)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.text.len()
+    }
+
+    /// Read an identifier, which is empty if there is none, with its span
+    fn word(&mut self) -> (&'a str, Span) {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let span = self.span(self.pos, self.pos + len.max(1));
+        self.pos += len;
+        (&rest[..len], span)
+    }
+
+    /// Read the given character if it is the next one
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.text[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parse the expression of a clause, which ends with the first `;`
+    /// outside of parentheses, brackets and braces
+    /// Its source is returned with the offset of the end of the clause
+    fn clause<F>(&mut self, parse_expr: &mut F) -> Result<(Expr, String, usize), Diagnostic>
+    where
+        F: FnMut(&str, usize) -> Result<Expr, Diagnostic>,
+    {
+        self.skip_whitespace();
+        let start = self.pos;
+        let mut depth = 0;
+        let mut end = self.text.len();
+        for (i, c) in self.text[start..].char_indices() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth == 0 => {
+                    end = start + i;
+                    break;
+                }
+                ')' | ']' | '}' => depth -= 1,
+                ';' if depth == 0 => {
+                    end = start + i + 1;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let source = &self.text[start..end];
+        let expr = parse_expr(source, self.offset + start)?;
+        self.pos = end;
+        let text = source.trim_end_matches(';').split_whitespace();
+        let text = text.collect::<Vec<_>>().join(" ");
+        Ok((expr, text, self.offset + end))
+    }
+}
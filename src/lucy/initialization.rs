@@ -0,0 +1,445 @@
+//! Initialization analysis of the nodes, in the style of Colaço and Pouzet
+//! (Type-based initialization analysis of a synchronous dataflow language).
+//!
+//! The initialization type of a stream is the number of its first instants,
+//! counted on its own clock, where its value may be undefined: `pre x` is
+//! undefined at the first instant, `pre (pre x)` at the first two, and
+//! `0 -> pre x` never is. The types of the variables of a node are the least
+//! solution of its equations, so that a variable can be defined from the
+//! previous values of another one. A variable whose type keeps growing, like
+//! `x = pre x`, is never initialized.
+//!
+//! The signature of a node gives the types of its outputs from the types of
+//! its inputs, so that a node can be given an uninitialized input whose first
+//! values it does not use, or whose output is initialized by the caller. It
+//! is computed by checking the node with the types of the inputs of each of
+//! its calls. The variables used as clocks or resets, the conditions of the
+//! assertions, the inputs of the imported nodes and of the iterations, and
+//! the outputs of the entry node should be initialized at every instant.
+//!
+//! The branches of a merge and the variable of a current are counted on a
+//! slower clock, and a reset expression is undefined again after each reset,
+//! so they are either initialized or never initialized.
+//!
+//! An undefined value is never used, so a `pre` starts with any value of its
+//! type.
+
+use crate::diagnostic::Diagnostic;
+use crate::lucy::clock_typed_ast::{BaseExpr, Expr, Node};
+
+use std::collections::HashMap;
+
+/// Number of the first instants where a stream may be undefined
+type Init = u32;
+
+/// Type of a stream that may never be initialized
+const NEVER: Init = Init::MAX;
+
+/// Check the initialization of the nodes, the outputs of the entry node
+/// being observed at every instant
+pub fn check_initialization(
+    nodes: &Vec<Node>,
+    entry_node: Option<&str>,
+) -> Result<(), Vec<Diagnostic>> {
+    let mut signatures = Signatures {
+        nodes: nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node))
+            .collect(),
+        instances: HashMap::new(),
+        delays: HashMap::new(),
+    };
+    let mut errors = vec![];
+    for node in nodes {
+        let inputs = vec![0; node.in_params.len()];
+        let entry = entry_node == Some(node.name.as_str());
+        let (_, mut node_errors) = check_node(node, inputs, entry, &mut signatures);
+        errors.append(&mut node_errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check the initialization of a node given the types of its inputs, and
+/// give the types of its outputs
+/// The outputs of the entry node should be initialized
+fn check_node(
+    node: &Node,
+    inputs: Vec<Init>,
+    entry: bool,
+    signatures: &mut Signatures,
+) -> (Vec<Init>, Vec<Diagnostic>) {
+    // A finite type is at most the type of an input delayed by the
+    // equations, a variable whose type is larger is never initialized
+    let bound = inputs
+        .iter()
+        .filter(|init| **init != NEVER)
+        .max()
+        .unwrap_or(&0)
+        .saturating_add(signatures.delays(node));
+
+    let inputs = node.in_params.iter().map(|(var, _)| var).zip(inputs);
+    let variables = node
+        .out_params
+        .iter()
+        .map(|(var, _)| var)
+        .chain(node.local_params.keys())
+        .map(|var| (var.clone(), 0))
+        .chain(inputs.map(|(var, init)| (var.clone(), init)))
+        .collect();
+    let mut analysis = Analysis {
+        signatures,
+        variables,
+        check: false,
+        errors: vec![],
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (vars, expr) in &node.eq_list {
+            for (var, init) in vars.iter().zip(analysis.infer(expr)) {
+                let init = if init > bound { NEVER } else { init };
+                if init > analysis.variables[var] {
+                    analysis.variables.insert(var.clone(), init);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    analysis.check = true;
+    for (_, expr) in &node.eq_list {
+        analysis.infer(expr);
+    }
+    for assertion in &node.assertions {
+        let init = analysis.variables[&assertion.var];
+        if init > 0 {
+            analysis.errors.push(Diagnostic::new(
+                format!(
+                    "The condition of the {} {}",
+                    assertion.kind,
+                    uninitialized(init)
+                ),
+                assertion.span,
+            ));
+        }
+    }
+    let outputs: Vec<Init> = node
+        .out_params
+        .iter()
+        .map(|(var, _)| analysis.variables[var])
+        .collect();
+    if entry {
+        for ((output, _), init) in node.out_params.iter().zip(&outputs) {
+            if *init > 0 {
+                analysis.check_output(node, output, *init);
+            }
+        }
+    }
+    (outputs, analysis.errors)
+}
+
+/// Signatures of the nodes, given by their instances for the types of the
+/// inputs they were called with
+struct Signatures<'a> {
+    nodes: HashMap<&'a str, &'a Node>,
+    /// Types of the outputs of an instance, which are None when the inputs
+    /// make the node uninitialized
+    instances: HashMap<(String, Vec<Init>), Option<Vec<Init>>>,
+    delays: HashMap<String, Init>,
+}
+
+impl<'a> Signatures<'a> {
+    /// Get the types of the outputs of a node, given the types of its inputs
+    /// The imported nodes need initialized inputs, and have initialized
+    /// outputs
+    fn instantiate(&mut self, fun: &str, inputs: Vec<Init>, outputs: usize) -> Option<Vec<Init>> {
+        let node = match self.nodes.get(fun) {
+            Some(node) => *node,
+            None if inputs.iter().all(|init| *init == 0) => return Some(vec![0; outputs]),
+            None => return None,
+        };
+        let key = (String::from(fun), inputs);
+        if let Some(instance) = self.instances.get(&key) {
+            return instance.clone();
+        }
+        let (outputs, errors) = check_node(node, key.1.clone(), false, self);
+        let instance = if errors.is_empty() {
+            Some(outputs)
+        } else {
+            None
+        };
+        self.instances.insert(key, instance.clone());
+        instance
+    }
+
+    /// Get the number of instants a node delays its inputs, which bounds the
+    /// finite types of its outputs
+    fn delays(&mut self, node: &Node) -> Init {
+        if let Some(delays) = self.delays.get(&node.name) {
+            return *delays;
+        }
+        let delays = node
+            .eq_list
+            .iter()
+            .map(|(_, expr)| self.expr_delays(expr))
+            .fold(0, Init::saturating_add);
+        self.delays.insert(node.name.clone(), delays);
+        delays
+    }
+
+    fn expr_delays(&mut self, expr: &Expr) -> Init {
+        let delays = match &expr.expr {
+            BaseExpr::Pre(_) | BaseExpr::Fby(_, _) => 1,
+            BaseExpr::FunCall(fun, _, _, _) => match self.nodes.get(fun.as_str()) {
+                Some(node) => self.delays(*node),
+                None => 0,
+            },
+            _ => 0,
+        };
+        expr.sub_exprs()
+            .into_iter()
+            .map(|e| self.expr_delays(e))
+            .fold(delays, Init::saturating_add)
+    }
+}
+
+/// State of the analysis of a node
+struct Analysis<'a, 's> {
+    signatures: &'s mut Signatures<'a>,
+    variables: HashMap<String, Init>,
+    /// The values that should be initialized are only checked once the
+    /// types of the variables are known
+    check: bool,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a, 's> Analysis<'a, 's> {
+    /// Get the types of the values of an expression
+    fn infer(&mut self, expr: &Expr) -> Vec<Init> {
+        match &expr.expr {
+            BaseExpr::Value(_) => vec![0],
+            BaseExpr::Var(var) => vec![self.variables[var]],
+            BaseExpr::Pre(box e) => self
+                .infer(e)
+                .into_iter()
+                .map(|init| init.saturating_add(1))
+                .collect(),
+            // e1 is used at the first instant, and e2 from the second one
+            BaseExpr::Fby(box e1, box e2) => {
+                let inits_1 = self.infer(e1);
+                let inits_2 = self.infer(e2);
+                let inits = inits_1.into_iter().zip(inits_2);
+                inits
+                    .map(|(init_1, init_2)| match init_2 {
+                        0 => init_1.min(1),
+                        _ => init_2.saturating_add(1),
+                    })
+                    .collect()
+            }
+            // The i-th expression is used at the i-th instant, and the last
+            // one from then on
+            BaseExpr::Arrow(exprs) => {
+                let inits: Vec<Vec<Init>> = exprs.iter().map(|e| self.infer(e)).collect();
+                let last = exprs.len() - 1;
+                (0..expr.typ.len())
+                    .map(|j| {
+                        if inits[last][j] > last as Init {
+                            return inits[last][j];
+                        }
+                        (0..last)
+                            .filter(|i| inits[*i][j] > *i as Init)
+                            .map(|i| i as Init + 1)
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect()
+            }
+            BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
+                let init_cond = self.infer(cond)[0];
+                let inits_t = self.infer(e_t);
+                let inits_f = self.infer(e_f);
+                let inits = inits_t.into_iter().zip(inits_f);
+                inits
+                    .map(|(init_t, init_f)| init_cond.max(init_t).max(init_f))
+                    .collect()
+            }
+            BaseExpr::Tuple(exprs) => exprs.iter().flat_map(|e| self.infer(e)).collect(),
+            BaseExpr::When(box e, ck, _) => {
+                self.check_variable(ck, "a clock", expr);
+                self.infer(e)
+            }
+            BaseExpr::Merge(ck, branches) => {
+                self.check_variable(ck, "a clock", expr);
+                let mut inits = vec![0; expr.typ.len()];
+                for (_, e) in branches {
+                    for (init, init_e) in inits.iter_mut().zip(self.infer(e)) {
+                        *init = (*init).max(restart(init_e));
+                    }
+                }
+                inits
+            }
+            BaseExpr::Current(var, _) => vec![restart(self.variables[var])],
+            BaseExpr::Reset(box e, reset) => {
+                self.check_variable(reset, "a reset", expr);
+                self.infer(e).into_iter().map(restart).collect()
+            }
+            BaseExpr::FunCall(fun, exprs, reset, _) => {
+                let outputs = self.call(fun, exprs, expr.typ.len());
+                match reset {
+                    Some(reset) => {
+                        self.check_variable(reset, "a reset", expr);
+                        outputs.into_iter().map(restart).collect()
+                    }
+                    None => outputs,
+                }
+            }
+            // The instances of an iteration are given the outputs of the
+            // previous ones, so they need initialized inputs
+            BaseExpr::Iterate(_, fun, _, exprs) => {
+                for e in exprs {
+                    let init = self.infer(e)[0];
+                    self.check_input(fun, e, init);
+                }
+                let inputs = match self.signatures.nodes.get(fun.as_str()) {
+                    Some(node) => vec![0; node.in_params.len()],
+                    None => vec![],
+                };
+                let outputs = self.signatures.instantiate(fun, inputs, 0);
+                let init = outputs.unwrap_or_default().into_iter().map(restart).max();
+                vec![init.unwrap_or(0); expr.typ.len()]
+            }
+            _ => {
+                let inits = expr.sub_exprs().into_iter().map(|e| self.infer(e)[0]);
+                vec![inits.max().unwrap_or(0)]
+            }
+        }
+    }
+
+    /// Get the types of the outputs of a call, and check that the node is
+    /// given inputs which are initialized enough
+    fn call(&mut self, fun: &str, exprs: &Vec<Expr>, outputs: usize) -> Vec<Init> {
+        let inputs: Vec<Init> = exprs.iter().map(|e| self.infer(e)[0]).collect();
+        if let Some(outputs) = self.signatures.instantiate(fun, inputs.clone(), outputs) {
+            return outputs;
+        }
+        // The input to blame is one that is enough to make the node
+        // uninitialized, and there is none if the errors are in the node
+        for (i, (e, init)) in exprs.iter().zip(&inputs).enumerate() {
+            let mut alone = vec![0; inputs.len()];
+            alone[i] = *init;
+            if *init > 0 && self.signatures.instantiate(fun, alone, outputs).is_none() {
+                self.check_input(fun, e, *init);
+                break;
+            }
+        }
+        // The outputs are not blamed for the error
+        vec![0; outputs]
+    }
+
+    /// Check that a variable used as a clock or a reset is initialized
+    /// The variables whose names start with _ are defined by the expressions
+    /// written as clocks or resets
+    fn check_variable(&mut self, var: &str, usage: &str, expr: &Expr) {
+        let init = self.variables[var];
+        if self.check && init > 0 {
+            let message = if var.starts_with('_') {
+                format!("The expression used as {} {}", usage, uninitialized(init))
+            } else {
+                format!(
+                    "The variable {} is used as {}, but it {}",
+                    var,
+                    usage,
+                    uninitialized(init)
+                )
+            };
+            self.errors.push(Diagnostic::new(message, expr.span));
+        }
+    }
+
+    /// Check that an input of a node is initialized
+    fn check_input(&mut self, fun: &str, e: &Expr, init: Init) {
+        if self.check && init > 0 {
+            let cause = match self.cause(e, false) {
+                Some(var) => format!(", because of {}", var),
+                None => String::new(),
+            };
+            self.errors.push(Diagnostic::new(
+                format!(
+                    "An input of {} should be initialized, but it {}{}",
+                    fun,
+                    uninitialized(init),
+                    cause
+                ),
+                e.span,
+            ));
+        }
+    }
+
+    /// Check that an output of the entry node is initialized, blaming the
+    /// expression defining it
+    fn check_output(&mut self, node: &Node, output: &str, init: Init) {
+        let definition = node.eq_list.iter().find_map(|(vars, expr)| {
+            let i = vars.iter().position(|var| var == output)?;
+            match &expr.expr {
+                BaseExpr::Tuple(exprs) if exprs.len() == vars.len() => Some(&exprs[i]),
+                _ => Some(expr),
+            }
+        });
+        let cause = match definition.and_then(|expr| self.cause(expr, false)) {
+            Some(cause) => format!(", because of {}", cause),
+            None => String::new(),
+        };
+        self.errors.push(Diagnostic::new(
+            format!(
+                "The output {} of the entry node {} {}{}",
+                output,
+                node.name,
+                uninitialized(init),
+                cause
+            ),
+            definition.map_or(node.span, |expr| expr.span),
+        ));
+    }
+
+    /// Find a variable that makes an expression uninitialized, either because
+    /// the variable is not initialized, or because it is delayed by a pre
+    fn cause(&self, expr: &Expr, delayed: bool) -> Option<String> {
+        match &expr.expr {
+            BaseExpr::Var(var) | BaseExpr::Current(var, _)
+                if delayed || self.variables[var] > 0 =>
+            {
+                Some(var.clone())
+            }
+            BaseExpr::Pre(box e) => self.cause(e, true).map(|cause| format!("pre {}", cause)),
+            BaseExpr::Fby(box e1, box e2) => {
+                self.cause(e1, delayed).or_else(|| self.cause(e2, true))
+            }
+            _ => expr
+                .sub_exprs()
+                .into_iter()
+                .find_map(|e| self.cause(e, delayed)),
+        }
+    }
+}
+
+/// Get the type of a stream which is undefined again when it is restarted
+fn restart(init: Init) -> Init {
+    if init == 0 {
+        0
+    } else {
+        NEVER
+    }
+}
+
+/// Describe the instants where a stream may be undefined
+fn uninitialized(init: Init) -> String {
+    match init {
+        NEVER => String::from("may never be initialized"),
+        1 => String::from("may be uninitialized at the first instant"),
+        _ => format!("may be uninitialized during the first {} instants", init),
+    }
+}
@@ -0,0 +1,154 @@
+//! Preprocessing of LucyRS sources before they are given to the parser.
+//! Comments are removed, and pragmas are extracted so that they can be
+//! attached to the AST.
+//!
+//! Line comments start with `--`, and block comments are written `(* ... *)`.
+//! They are not recognized in strings.
+//! Pragmas are written `--%name args` or `(*@name args *)`.
+
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{Node, Pragma};
+
+/// Remove the comments of a source, and return the pragmas it contains.
+/// Comments are replaced by spaces, so that the offsets in the source
+/// are kept. offset is the offset of the source in the source map.
+pub fn strip_comments(source: &str, offset: usize) -> Result<(String, Vec<Pragma>), Diagnostic> {
+    let mut bytes = source.as_bytes().to_vec();
+    let mut pragmas = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if source[i..].starts_with("--") {
+            let end = source[i..].find('\n').map(|j| i + j).unwrap_or(source.len());
+            if source[i..].starts_with("--%") {
+                pragmas.push(new_pragma(&source[i + 3..end], i, end, offset));
+            }
+            blank(&mut bytes, i, end);
+            i = end;
+        } else if source[i..].starts_with("(*") {
+            let end = match source[i + 2..].find("*)") {
+                Some(j) => i + 2 + j + 2,
+                None => {
+                    return Err(Diagnostic::new(
+                        String::from("Unterminated block comment"),
+                        Span::new(offset + i, offset + i + 2),
+                    ))
+                }
+            };
+            if source[i..].starts_with("(*@") {
+                pragmas.push(new_pragma(&source[i + 3..end - 2], i, end, offset));
+            }
+            blank(&mut bytes, i, end);
+            i = end;
+        } else if source[i..].starts_with('"') {
+            // Strings cannot contain quotes, an unterminated string is
+            // reported by the parser
+            i = match source[i + 1..].find('"') {
+                Some(j) => i + 1 + j + 1,
+                None => source.len(),
+            };
+        } else {
+            i += source[i..].chars().next().unwrap().len_utf8();
+        }
+    }
+    Ok((String::from_utf8(bytes).unwrap(), pragmas))
+}
+
+/// Replace the bytes between start and end by spaces, keeping line returns
+fn blank(bytes: &mut Vec<u8>, start: usize, end: usize) {
+    for byte in &mut bytes[start..end] {
+        if *byte != b'\n' {
+            *byte = b' ';
+        }
+    }
+}
+
+/// Create a pragma from its content, the first word being its name
+/// The content starts 3 bytes after the start of the pragma
+fn new_pragma(content: &str, start: usize, end: usize, offset: usize) -> Pragma {
+    let content_start = start + 3 + (content.len() - content.trim_start().len());
+    let content = content.trim();
+    let name_end = content
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(content.len());
+    let args = &content[name_end..];
+    let args_start = content_start + name_end + (args.len() - args.trim_start().len());
+    Pragma {
+        name: content[..name_end].to_owned(),
+        args: args.trim().to_owned(),
+        args_start: offset + args_start,
+        span: Span::new(offset + start, offset + end),
+    }
+}
+
+/// Element of the AST a pragma can be attached to
+enum Target {
+    Node(usize),
+    Equation(usize, usize),
+    Declaration(usize, Span),
+}
+
+/// Attach each pragma to the node, equation or declaration that follows it
+/// A contract is attached to the node whose declaration contains it, or
+/// to the node that follows it
+pub fn attach_pragmas(
+    mut nodes: Vec<&mut Node>,
+    pragmas: Vec<Pragma>,
+) -> Result<(), Vec<Diagnostic>> {
+    let mut targets = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        targets.push((node.span.start, Target::Node(i)));
+        for (j, eq) in node.eq_list.iter().enumerate() {
+            targets.push((eq.span.start, Target::Equation(i, j)));
+        }
+        for span in node.var_spans.values() {
+            targets.push((span.start, Target::Declaration(i, *span)));
+        }
+    }
+    targets.sort_by_key(|(start, _)| *start);
+
+    let mut errors = vec![];
+    for pragma in pragmas {
+        if pragma.name == "contract" {
+            let containing = nodes.iter().position(|node| {
+                node.span.start <= pragma.span.start && pragma.span.end <= node.span.end
+            });
+            let following = match targets.iter().find(|(start, _)| *start >= pragma.span.end) {
+                Some((_, Target::Node(i))) => Some(*i),
+                _ => None,
+            };
+            match containing.or(following) {
+                Some(i) => nodes[i].pragmas.push(pragma),
+                None => errors.push(Diagnostic::new(
+                    String::from("The contract is not written in a node, nor followed by a node"),
+                    pragma.span,
+                )),
+            }
+            continue;
+        }
+        match targets.iter().find(|(start, _)| *start >= pragma.span.end) {
+            Some((_, Target::Node(i))) => nodes[*i].pragmas.push(pragma),
+            Some((_, Target::Equation(i, j))) => nodes[*i].eq_list[*j].pragmas.push(pragma),
+            Some((_, Target::Declaration(i, span))) => {
+                let node = &mut nodes[*i];
+                for (var, var_span) in &node.var_spans {
+                    if var_span == span {
+                        node.var_pragmas
+                            .entry(var.clone())
+                            .or_insert_with(Vec::new)
+                            .push(pragma.clone());
+                    }
+                }
+            }
+            None => errors.push(Diagnostic::new(
+                String::from("The pragma is not followed by a node, an equation or a declaration"),
+                pragma.span,
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -1,63 +1,275 @@
 //! This module contains the functions to parse a LucyRS file, and to
 //! translate it into typed LucyRS AST, then into minils AST.
 
+pub mod assertions;
 pub mod ast;
-pub mod check_valid_pre;
+pub mod blocks;
+pub mod check_functions;
 pub mod clock_typed_ast;
+pub mod constants;
+pub mod contracts;
 pub mod grammar;
+pub mod initialization;
+pub mod lexer;
+pub mod monomorphisation;
+pub mod packages;
 pub mod scheduling;
+pub mod specialization;
 pub mod to_minils;
 pub mod type_clock;
 pub mod typed_ast;
 pub mod typing;
 
-use std::fs::File;
-use std::io::Read;
-
 use self::clock_typed_ast as typ;
+use crate::ast::{Constant, Span, TypeDecl};
+use crate::diagnostic::{Diagnostic, Source};
+use crate::error::CompileError;
 use crate::minils::ast as mls;
 
-/// Parse a LucyRS file and return the node list
-pub fn parse_file(filename: &str) -> Vec<ast::Node> {
-    let mut f = File::open(filename).expect("file not found");
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)
-        .expect(&("Error while loading file ".to_owned() + filename));
-    let nodes = grammar::FileParser::new().parse(&contents);
-    if let Err(message) = nodes {
-        panic!(format!("Parser Error: {}", message));
+use lalrpop_util::ParseError;
+
+/// Parse a LucyRS file and return the program it contains
+/// offset is the offset given to the file in the source map
+/// The parser recovers from syntax errors, so that all of them are reported
+pub fn parse_file(source: &Source, offset: usize) -> Result<ast::Program, Vec<CompileError>> {
+    let (contents, pragmas) = lexer::strip_comments(&source.contents, offset)
+        .map_err(|diagnostic| vec![CompileError::Parse(diagnostic)])?;
+    let mut recovered_errors = vec![];
+    let result = grammar::FileParser::new().parse(offset, &mut recovered_errors, &contents);
+    let len = source.contents.len();
+    let to_error = |error| CompileError::Parse(parse_error_to_diagnostic(error, offset, len));
+    let mut errors: Vec<CompileError> = recovered_errors
+        .into_iter()
+        .map(|recovery| to_error(recovery.error))
+        .collect();
+    match result {
+        Ok(mut program) => {
+            let packages = program.packages.iter_mut();
+            let mut nodes: Vec<&mut ast::Node> = program
+                .nodes
+                .iter_mut()
+                .chain(packages.flat_map(|package| package.program.nodes.iter_mut()))
+                .collect();
+            for node in &mut nodes {
+                for assert in &mut node.asserts {
+                    let span = assert.expr.span;
+                    let text = &contents[span.start - offset..span.end - offset];
+                    assert.text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                }
+            }
+            if let Err(diagnostics) = lexer::attach_pragmas(nodes, pragmas) {
+                errors.extend(diagnostics.into_iter().map(CompileError::Parse));
+            }
+            errors.extend(parse_contracts(&mut program).into_iter().map(CompileError::Parse));
+            if errors.is_empty() {
+                Ok(program)
+            } else {
+                Err(errors)
+            }
+        }
+        Err(error) => {
+            errors.push(to_error(error));
+            Err(errors)
+        }
     }
-    nodes.unwrap()
 }
 
-/// Type the LucyRS nodes
-pub fn type_nodes(nodes: Vec<ast::Node>) -> Vec<typ::Node> {
-    let scheduled_nodes = scheduling::schedule(nodes);
-    if let Err(message) = scheduled_nodes {
-        panic!("Causality Error: {}", message);
+/// Parse the contracts attached to the nodes of a program, and add their
+/// clauses to the assertions of the nodes
+fn parse_contracts(program: &mut ast::Program) -> Vec<Diagnostic> {
+    let packages = program.packages.iter_mut();
+    let nodes = program
+        .nodes
+        .iter_mut()
+        .chain(packages.flat_map(|package| package.program.nodes.iter_mut()));
+    let mut errors = vec![];
+    let parse_expr = |text: &str, offset: usize| {
+        let mut recovered_errors = vec![];
+        grammar::ClauseExprParser::new()
+            .parse(offset, &mut recovered_errors, text)
+            .map_err(|error| parse_error_to_diagnostic(error, offset, text.len()))
+    };
+    for node in nodes {
+        for pragma in node.pragmas.iter().filter(|pragma| pragma.name == "contract") {
+            match contracts::parse_contract(pragma, parse_expr) {
+                Ok(asserts) => node.asserts.extend(asserts),
+                Err(diagnostic) => errors.push(diagnostic),
+            }
+        }
     }
-    let scheduled_nodes = scheduled_nodes.unwrap();
+    errors
+}
 
-    let typed_nodes = typing::annotate_types(scheduled_nodes);
-    if let Err(message) = typed_nodes {
-        panic!("Typing Error: {}", message);
+/// Translate a lalrpop error into a diagnostic
+/// offset is the offset of the parsed text in the source map, and len its length
+fn parse_error_to_diagnostic<T: std::fmt::Display>(
+    error: ParseError<usize, T, Diagnostic>,
+    offset: usize,
+    len: usize,
+) -> Diagnostic {
+    let span = |l: usize, r: usize| Span::new(offset + l, offset + r);
+    let eof = span(len, len);
+    match error {
+        ParseError::InvalidToken { location } => Diagnostic::new(
+            String::from("Invalid token"),
+            span(location, location + 1),
+        ),
+        ParseError::UnrecognizedToken {
+            token: None,
+            expected,
+        } => Diagnostic::new(
+            format!(
+                "Unexpected end of file, expected one of {}",
+                describe_expected(&expected)
+            ),
+            eof,
+        ),
+        ParseError::UnrecognizedToken {
+            token: Some((l, token, r)),
+            expected,
+        } => Diagnostic::new(
+            format!(
+                "Unexpected token {}, expected one of {}",
+                token,
+                describe_expected(&expected)
+            ),
+            span(l, r),
+        ),
+        ParseError::ExtraToken {
+            token: (l, token, r),
+        } => Diagnostic::new(format!("Extra token {}", token), span(l, r)),
+        ParseError::User { error } => error,
     }
-    let typed_nodes = typed_nodes.unwrap();
+}
 
-    let clock_nodes = type_clock::annotate_clocks(typed_nodes);
-    if let Err(message) = clock_nodes {
-        panic!("Clock typing error: {}", message);
-    }
-    let clock_nodes = clock_nodes.unwrap();
+/// Give readable names to the terminals expected by the parser
+fn describe_expected(expected: &Vec<String>) -> String {
+    expected
+        .iter()
+        .map(|terminal| {
+            if !terminal.starts_with("r#") {
+                terminal.clone()
+            } else if terminal.contains('\'') {
+                String::from("type variable")
+            } else if terminal.contains("[^") {
+                String::from("string")
+            } else if terminal.contains("eE") {
+                String::from("real")
+            } else if terminal.contains("a-zA-Z") {
+                String::from("identifier")
+            } else {
+                String::from("integer")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
 
-    if let Err(message) = check_valid_pre::check_valid_pre(&clock_nodes) {
-        panic!(format!("Error: {}", message));
-    }
+/// Move the declarations of the packages of a LucyRS program into the program
+pub fn flatten_packages(program: ast::Program) -> Result<ast::Program, Vec<CompileError>> {
+    packages::flatten_packages(program)
+        .map_err(|diagnostics| diagnostics.into_iter().map(CompileError::Resolution).collect())
+}
+
+/// Compile the automata and reset blocks of a LucyRS program into equations
+pub fn compile_blocks(program: ast::Program) -> Result<ast::Program, Vec<CompileError>> {
+    blocks::compile_blocks(program)
+        .map_err(|diagnostics| diagnostics.into_iter().map(CompileError::Blocks).collect())
+}
+
+/// Replace the nodes with static parameters of a LucyRS program by their instances
+/// The global constants are evaluated first, as the static arguments can use them
+pub fn specialize(program: ast::Program) -> Result<ast::Program, Vec<CompileError>> {
+    constants::evaluate_globals(&program)
+        .map_err(|diagnostics| diagnostics.into_iter().map(CompileError::Constant).collect::<Vec<_>>())?;
+    specialization::specialize(program)
+        .map_err(|diagnostics| diagnostics.into_iter().map(CompileError::Specialization).collect())
+}
+
+/// Evaluate the constants of a LucyRS program, and replace them by their values
+pub fn substitute_constants(
+    program: ast::Program,
+) -> Result<(ast::Program, Vec<Constant>), Vec<CompileError>> {
+    constants::substitute_constants(program)
+        .map_err(|diagnostics| diagnostics.into_iter().map(CompileError::Constant).collect())
+}
+
+/// Type the nodes of a LucyRS program, whose constants were substituted
+/// The outputs of the entry node, if it is given, should be initialized
+/// All the errors found in a pass are reported before stopping
+pub fn type_nodes(
+    program: ast::Program,
+    entry_node: Option<&str>,
+) -> Result<Vec<typ::Node>, Vec<CompileError>> {
+    let into_errors = |kind: fn(Diagnostic) -> CompileError| {
+        move |diagnostics: Vec<Diagnostic>| -> Vec<CompileError> {
+            diagnostics.into_iter().map(kind).collect()
+        }
+    };
+
+    let ast::Program {
+        types,
+        constants,
+        nodes,
+        imported,
+        ..
+    } = program;
+    check_functions::check_functions(&nodes, &imported)
+        .map_err(into_errors(CompileError::Typing))?;
+
+    let scheduled_nodes =
+        scheduling::schedule(nodes).map_err(into_errors(CompileError::Causality))?;
+
+    let typed_nodes = typing::annotate_types(ast::Program {
+        types,
+        constants,
+        nodes: scheduled_nodes,
+        imported,
+        ..Default::default()
+    })
+    .map_err(into_errors(CompileError::Typing))?;
+
+    let clock_nodes =
+        type_clock::annotate_clocks(typed_nodes).map_err(into_errors(CompileError::Clock))?;
+
+    initialization::check_initialization(&clock_nodes, entry_node)
+        .map_err(into_errors(CompileError::Initialization))?;
 
-    clock_nodes
+    Ok(clock_nodes)
+}
+
+/// Replace the polymorphic nodes of typed LucyRS nodes by their instances
+pub fn monomorphise(nodes: Vec<typ::Node>) -> Vec<typ::Node> {
+    monomorphisation::monomorphise(nodes)
+}
+
+/// Remove the assertions of typed LucyRS nodes, with the equations only
+/// used by them
+pub fn remove_assertions(nodes: Vec<typ::Node>) -> Vec<typ::Node> {
+    nodes
+        .into_iter()
+        .map(assertions::remove_assertions)
+        .collect()
 }
 
 /// Translate typed LucyRS nodes into minils
-pub fn to_minils(nodes: Vec<typ::Node>) -> Vec<mls::Node> {
-    nodes.into_iter().map(to_minils::to_minils).collect()
+/// The resets that cannot be sampled on the clocks of their memories are
+/// reported as clock errors
+pub fn to_minils(
+    nodes: Vec<typ::Node>,
+    types: &Vec<TypeDecl>,
+) -> Result<Vec<mls::Node>, Vec<CompileError>> {
+    let mut minils_nodes = vec![];
+    let mut errors = vec![];
+    for node in nodes {
+        match to_minils::to_minils(node, types) {
+            Ok(node) => minils_nodes.push(node),
+            Err(diagnostic) => errors.push(CompileError::Clock(diagnostic)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(minils_nodes)
+    } else {
+        Err(errors)
+    }
 }
@@ -0,0 +1,174 @@
+//! Monomorphise the polymorphic nodes, whose signature contains type
+//! variables. Each call to a polymorphic node is replaced by a call to an
+//! instance of the node for the types of the arguments, so that the
+//! following passes only see concrete types.
+
+use crate::ast::{Type, Value};
+use crate::lucy::clock_typed_ast::{BaseExpr, Expr, Node};
+
+use std::collections::{HashMap, HashSet};
+
+struct Monomorphiser {
+    /// Polymorphic nodes
+    templates: HashMap<String, Node>,
+    /// Instance created for a node and the types bound to its variables
+    instances: HashMap<String, String>,
+    /// Names of the nodes, used to give unique names to the instances
+    names: HashSet<String>,
+    /// Instances still to monomorphise, with the types bound to their variables
+    pending: Vec<(Node, HashMap<String, Type>)>,
+}
+
+/// Replace the polymorphic nodes by their instances
+pub fn monomorphise(nodes: Vec<Node>) -> Vec<Node> {
+    let mut monomorphiser = Monomorphiser {
+        templates: HashMap::new(),
+        instances: HashMap::new(),
+        names: nodes.iter().map(|node| node.name.clone()).collect(),
+        pending: vec![],
+    };
+    let mut new_nodes = vec![];
+    for node in nodes {
+        if is_polymorphic(&node) {
+            monomorphiser.templates.insert(node.name.clone(), node);
+        } else {
+            new_nodes.push(node);
+        }
+    }
+    for node in &mut new_nodes {
+        monomorphiser.monomorphise_node(node, &HashMap::new());
+    }
+    while let Some((mut node, bindings)) = monomorphiser.pending.pop() {
+        monomorphiser.monomorphise_node(&mut node, &bindings);
+        new_nodes.push(node);
+    }
+    new_nodes
+}
+
+/// Check if the signature of a node contains type variables
+fn is_polymorphic(node: &Node) -> bool {
+    node.in_params
+        .iter()
+        .any(|(_, typ)| !typ.variables().is_empty())
+}
+
+impl Monomorphiser {
+    /// Replace the type variables of a node by the types they are bound to,
+    /// and the calls to polymorphic nodes by calls to their instances
+    fn monomorphise_node(&mut self, node: &mut Node, bindings: &HashMap<String, Type>) {
+        let params = node.in_params.iter_mut().chain(node.out_params.iter_mut());
+        for (_, typ) in params {
+            *typ = typ.substitute(bindings);
+        }
+        for (typ, _) in node.local_params.values_mut() {
+            *typ = typ.substitute(bindings);
+        }
+        for (_, expr) in &mut node.eq_list {
+            self.monomorphise_expr(expr, bindings);
+        }
+    }
+
+    fn monomorphise_expr(&mut self, expr: &mut Expr, bindings: &HashMap<String, Type>) {
+        expr.typ = expr.typ.iter().map(|t| t.substitute(bindings)).collect();
+        let typ = expr.typ.clone();
+        let mut monomorphise = |e: &mut Expr| self.monomorphise_expr(e, bindings);
+        match &mut expr.expr {
+            BaseExpr::Value(value) => convert_literal(value, &typ),
+            BaseExpr::Var(_) | BaseExpr::Current(_, _) => (),
+            BaseExpr::UnOp(_, box e)
+            | BaseExpr::When(box e, _, _)
+            | BaseExpr::Pre(box e)
+            | BaseExpr::Slice(box e, _, _)
+            | BaseExpr::Field(box e, _)
+            | BaseExpr::Reset(box e, _) => monomorphise(e),
+            BaseExpr::BinOp(_, box e1, box e2)
+            | BaseExpr::Fby(box e1, box e2)
+            | BaseExpr::Index(box e1, box e2)
+            | BaseExpr::Concat(box e1, box e2) => {
+                monomorphise(e1);
+                monomorphise(e2);
+            }
+            BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+                monomorphise(e1);
+                monomorphise(e2);
+                monomorphise(e3);
+            }
+            BaseExpr::Arrow(exprs) | BaseExpr::Array(exprs) | BaseExpr::Tuple(exprs) => {
+                exprs.iter_mut().for_each(monomorphise)
+            }
+            BaseExpr::Merge(_, branches) => branches.iter_mut().for_each(|(_, e)| monomorphise(e)),
+            BaseExpr::Record(_, fields) => fields.iter_mut().for_each(|(_, e)| monomorphise(e)),
+            BaseExpr::With(box e, fields) => {
+                monomorphise(e);
+                fields.iter_mut().for_each(|(_, e)| monomorphise(e));
+            }
+            BaseExpr::FunCall(fun, exprs, _, _) => {
+                exprs.iter_mut().for_each(monomorphise);
+                if let Some(template) = self.templates.get(fun) {
+                    let expected = template.in_params.iter().map(|(_, t)| t.clone()).collect();
+                    *fun = self.instantiate(fun.clone(), expected, exprs);
+                }
+            }
+            BaseExpr::Iterate(kind, fun, n, exprs) => {
+                exprs.iter_mut().for_each(monomorphise);
+                if let Some(template) = self.templates.get(fun) {
+                    let in_type = template.in_params.iter().map(|(_, t)| t.clone()).collect();
+                    let expected = kind.inputs(&in_type, *n);
+                    *fun = self.instantiate(fun.clone(), expected, exprs);
+                }
+            }
+        }
+    }
+
+    /// Get the instance of a polymorphic node for the types of its arguments,
+    /// creating it if needed. expected are the types of the arguments given
+    /// by the signature of the node
+    fn instantiate(&mut self, fun: String, expected: Vec<Type>, exprs: &Vec<Expr>) -> String {
+        let mut bindings = HashMap::new();
+        for (typ, expr) in expected.iter().zip(exprs) {
+            bind(typ, &expr.typ[0], &mut bindings);
+        }
+        let mut variables: Vec<(&String, &Type)> = bindings.iter().collect();
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let types: Vec<String> = variables.iter().map(|(_, typ)| typ.to_string()).collect();
+        let key = format!("{}<<{}>>", fun, types.join(", "));
+        if let Some(instance) = self.instances.get(&key) {
+            return instance.clone();
+        }
+        let mut base = fun.clone();
+        for typ in &types {
+            base.push('_');
+            base.extend(typ.chars().map(|c| if c == '^' { '_' } else { c }));
+        }
+        let mut name = base.clone();
+        let mut i = 1;
+        while self.names.contains(&name) {
+            name = format!("{}_{}", base, i);
+            i += 1;
+        }
+        self.names.insert(name.clone());
+        self.instances.insert(key, name.clone());
+        let mut instance = self.templates[&fun].clone();
+        instance.name = name.clone();
+        self.pending.push((instance, bindings));
+        name
+    }
+}
+
+/// Convert an integer literal given a numeric type variable, which was bound to real
+fn convert_literal(value: &mut Value, typ: &Vec<Type>) {
+    if let (Value::Int(i), [Type::Real]) = (&value, typ.as_slice()) {
+        *value = Value::Real(*i as f32);
+    }
+}
+
+/// Bind the type variables of an expected type to the parts of a concrete type
+fn bind(expected: &Type, found: &Type, bindings: &mut HashMap<String, Type>) {
+    match (expected, found) {
+        (Type::Var(name), _) => {
+            bindings.insert(name.clone(), found.clone());
+        }
+        (Type::Array(box t1, _), Type::Array(box t2, _)) => bind(t1, t2, bindings),
+        _ => (),
+    }
+}
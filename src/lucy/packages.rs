@@ -0,0 +1,375 @@
+//! Flatten the packages of a program, written `package P ... end;`. The
+//! declarations of a package are renamed `P::x`, and inside the package,
+//! the unqualified names refer to its own declarations first. Outside of
+//! the package, its declarations are referred to by their qualified name,
+//! unless they are private.
+
+use crate::ast::{ImportedNode, Span, Type, TypeDecl, TypeDef, Value};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{BaseExpr, Block, ConstDecl, Expr, Node, Package, Program, StaticKind};
+
+use std::collections::{HashMap, HashSet};
+
+/// Names declared in a package
+struct Declarations {
+    types: HashSet<String>,
+    constants: HashSet<String>,
+    nodes: HashSet<String>,
+    private: HashSet<String>,
+}
+
+/// Kind of declaration a name refers to
+/// A variable can refer to a constant, or to a node given as a static argument
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Type,
+    Constant,
+    Node,
+    Var,
+}
+
+struct Resolver<'a> {
+    packages: &'a HashMap<String, Declarations>,
+    /// Package containing the declarations being resolved
+    current: Option<String>,
+    /// Variables, constants and static parameters of the node being resolved,
+    /// which hide the declarations of the package
+    locals: HashSet<String>,
+    errors: Vec<Diagnostic>,
+}
+
+/// Move the declarations of the packages into the program, with qualified names
+pub fn flatten_packages(program: Program) -> Result<Program, Vec<Diagnostic>> {
+    let Program {
+        types,
+        constants,
+        nodes,
+        imported,
+        packages,
+        ..
+    } = program;
+    let mut errors = vec![];
+    let mut declarations = HashMap::new();
+    for package in &packages {
+        if declarations.contains_key(&package.name) {
+            errors.push(Diagnostic::new(
+                format!("Package {} was declared twice", package.name),
+                package.span,
+            ));
+            continue;
+        }
+        let program = &package.program;
+        let names = |names: Vec<&String>| names.into_iter().cloned().collect();
+        declarations.insert(
+            package.name.clone(),
+            Declarations {
+                types: names(program.types.iter().map(|decl| &decl.name).collect()),
+                constants: names(program.constants.iter().map(|decl| &decl.name).collect()),
+                nodes: names(
+                    program
+                        .nodes
+                        .iter()
+                        .map(|node| &node.name)
+                        .chain(program.imported.iter().map(|node| &node.name))
+                        .collect(),
+                ),
+                private: package.private.iter().cloned().collect(),
+            },
+        );
+    }
+
+    let mut resolver = Resolver {
+        packages: &declarations,
+        current: None,
+        locals: HashSet::new(),
+        errors: vec![],
+    };
+    let mut flat = Program::default();
+    for Package {
+        name: package,
+        mut program,
+        ..
+    } in packages
+    {
+        let qualify = |name: &mut String| *name = format!("{}::{}", package, name);
+        for decl in &mut program.types {
+            qualify(&mut decl.name);
+        }
+        for decl in &mut program.constants {
+            qualify(&mut decl.name);
+        }
+        for node in &mut program.nodes {
+            qualify(&mut node.name);
+        }
+        for node in &mut program.imported {
+            qualify(&mut node.name);
+        }
+        resolver.current = Some(package);
+        resolver.resolve_program(&mut program);
+        flat.types.append(&mut program.types);
+        flat.constants.append(&mut program.constants);
+        flat.nodes.append(&mut program.nodes);
+        flat.imported.append(&mut program.imported);
+    }
+    resolver.current = None;
+    let mut program = Program {
+        types,
+        constants,
+        nodes,
+        imported,
+        ..Default::default()
+    };
+    resolver.resolve_program(&mut program);
+    flat.types.append(&mut program.types);
+    flat.constants.append(&mut program.constants);
+    flat.nodes.append(&mut program.nodes);
+    flat.imported.append(&mut program.imported);
+
+    errors.append(&mut resolver.errors);
+    if errors.is_empty() {
+        Ok(flat)
+    } else {
+        Err(errors)
+    }
+}
+
+impl<'a> Resolver<'a> {
+    /// Resolve the names used in the declarations of a program
+    fn resolve_program(&mut self, program: &mut Program) {
+        self.locals = HashSet::new();
+        for decl in &mut program.types {
+            self.resolve_type_decl(decl);
+        }
+        for decl in &mut program.constants {
+            self.resolve_const_decl(decl);
+        }
+        for node in &mut program.imported {
+            self.resolve_imported_node(node);
+        }
+        for node in &mut program.nodes {
+            self.resolve_node(node);
+        }
+    }
+
+    fn resolve_type_decl(&mut self, decl: &mut TypeDecl) {
+        let span = decl.span;
+        match &mut decl.def {
+            TypeDef::Enum(_) => (),
+            TypeDef::Record(fields) => {
+                for (_, typ) in fields {
+                    self.resolve_type(typ, span);
+                }
+            }
+            TypeDef::Alias(typ) => self.resolve_type(typ, span),
+        }
+    }
+
+    fn resolve_const_decl(&mut self, decl: &mut ConstDecl) {
+        self.resolve_type(&mut decl.typ, decl.span);
+        self.resolve_expr(&mut decl.expr);
+    }
+
+    fn resolve_imported_node(&mut self, node: &mut ImportedNode) {
+        let params = node.in_params.iter_mut().chain(node.out_params.iter_mut());
+        for (_, typ) in params {
+            self.resolve_type(typ, node.span);
+        }
+    }
+
+    fn resolve_node(&mut self, node: &mut Node) {
+        self.locals = node
+            .in_params
+            .iter()
+            .chain(node.out_params.iter())
+            .map(|(name, _)| name)
+            .chain(node.local_params.keys())
+            .chain(node.static_params.iter().map(|param| &param.name))
+            .chain(node.constants.iter().map(|decl| &decl.name))
+            .cloned()
+            .collect();
+        for param in &mut node.static_params {
+            match &mut param.kind {
+                StaticKind::Const(typ) => self.resolve_type(typ, param.span),
+                StaticKind::Node(inputs, outputs) => {
+                    for typ in inputs.iter_mut().chain(outputs.iter_mut()) {
+                        self.resolve_type(typ, param.span);
+                    }
+                }
+            }
+        }
+        let span = node.span;
+        let var_spans = &node.var_spans;
+        let params = node.in_params.iter_mut().chain(node.out_params.iter_mut());
+        for (name, typ) in params {
+            self.resolve_type(typ, *var_spans.get(name).unwrap_or(&span));
+        }
+        for (name, (typ, _)) in &mut node.local_params {
+            if let Some(typ) = typ {
+                self.resolve_type(typ, *var_spans.get(name).unwrap_or(&span));
+            }
+        }
+        for decl in &mut node.constants {
+            self.resolve_const_decl(decl);
+        }
+        for eq in &mut node.eq_list {
+            self.resolve_expr(&mut eq.expr);
+        }
+        for block in &mut node.blocks {
+            self.resolve_block(block);
+        }
+        for assert in &mut node.asserts {
+            self.resolve_expr(&mut assert.expr);
+        }
+    }
+
+    fn resolve_block(&mut self, block: &mut Block) {
+        match block {
+            Block::Reset(reset) => {
+                for eq in &mut reset.eq_list {
+                    self.resolve_expr(&mut eq.expr);
+                }
+                for block in &mut reset.blocks {
+                    self.resolve_block(block);
+                }
+                self.resolve_expr(&mut reset.cond);
+            }
+            Block::Automaton(automaton) => {
+                for state in &mut automaton.states {
+                    for eq in &mut state.eq_list {
+                        self.resolve_expr(&mut eq.expr);
+                    }
+                    for block in &mut state.blocks {
+                        self.resolve_block(block);
+                    }
+                    for transition in &mut state.transitions {
+                        self.resolve_expr(&mut transition.cond);
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+        match &mut expr.expr {
+            BaseExpr::Value(value) => self.resolve_value(value, span),
+            BaseExpr::Var(name) => self.resolve(name, Kind::Var, span),
+            BaseExpr::Current(box e, value) => {
+                self.resolve_expr(e);
+                self.resolve_value(value, span);
+            }
+            BaseExpr::UnOp(_, box e)
+            | BaseExpr::Pre(box e)
+            | BaseExpr::Slice(box e, _, _)
+            | BaseExpr::Field(box e, _)
+            | BaseExpr::Reset(box e, _) => self.resolve_expr(e),
+            BaseExpr::BinOp(_, box e1, box e2)
+            | BaseExpr::When(box e1, box e2, _)
+            | BaseExpr::Fby(box e1, box e2)
+            | BaseExpr::Index(box e1, box e2)
+            | BaseExpr::Concat(box e1, box e2) => {
+                self.resolve_expr(e1);
+                self.resolve_expr(e2);
+            }
+            BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+                self.resolve_expr(e1);
+                self.resolve_expr(e2);
+                self.resolve_expr(e3);
+            }
+            BaseExpr::Arrow(exprs) | BaseExpr::Array(exprs) | BaseExpr::Tuple(exprs) => {
+                exprs.iter_mut().for_each(|e| self.resolve_expr(e))
+            }
+            BaseExpr::Merge(box ck, branches) => {
+                self.resolve_expr(ck);
+                branches.iter_mut().for_each(|(_, e)| self.resolve_expr(e))
+            }
+            BaseExpr::Record(branches) => {
+                branches.iter_mut().for_each(|(_, e)| self.resolve_expr(e))
+            }
+            BaseExpr::With(box e, fields) => {
+                self.resolve_expr(e);
+                fields.iter_mut().for_each(|(_, e)| self.resolve_expr(e));
+            }
+            BaseExpr::FunCall(fun, exprs, reset) => {
+                self.resolve(fun, Kind::Node, span);
+                exprs.iter_mut().for_each(|e| self.resolve_expr(e));
+                reset.iter_mut().for_each(|e| self.resolve_expr(e));
+            }
+            BaseExpr::StaticCall(fun, static_args, exprs, reset) => {
+                self.resolve(fun, Kind::Node, span);
+                static_args.iter_mut().for_each(|e| self.resolve_expr(e));
+                exprs.iter_mut().for_each(|e| self.resolve_expr(e));
+                reset.iter_mut().for_each(|e| self.resolve_expr(e));
+            }
+            BaseExpr::Iterate(_, fun, n, exprs) => {
+                self.resolve(fun, Kind::Node, span);
+                self.resolve_value(n, span);
+                exprs.iter_mut().for_each(|e| self.resolve_expr(e));
+            }
+        }
+    }
+
+    /// Resolve the constants used as values, which are enum values without type
+    fn resolve_value(&mut self, value: &mut Value, span: Span) {
+        if let Value::Enum(typ, name) = value {
+            if typ.is_empty() {
+                self.resolve(name, Kind::Var, span);
+            }
+        }
+    }
+
+    fn resolve_type(&mut self, typ: &mut Type, span: Span) {
+        match typ {
+            Type::Named(name) => self.resolve(name, Kind::Type, span),
+            Type::Array(box typ, _) => self.resolve_type(typ, span),
+            Type::ConstArray(box typ, n) => {
+                self.resolve_type(typ, span);
+                self.resolve(n, Kind::Constant, span);
+            }
+            Type::Int | Type::Real | Type::Bool | Type::Var(_) => (),
+        }
+    }
+
+    /// Resolve a name used in a declaration
+    /// A qualified name should refer to a package, and to a public declaration
+    /// if it is used outside of the package. An unqualified name is qualified
+    /// if it refers to a declaration of the current package.
+    fn resolve(&mut self, name: &mut String, kind: Kind, span: Span) {
+        if let Some(i) = name.find("::") {
+            let (package, item) = (&name[..i], &name[i + 2..]);
+            match self.packages.get(package) {
+                None => self.errors.push(Diagnostic::new(
+                    format!("Package {} used but not declared", package),
+                    span,
+                )),
+                Some(declarations) => {
+                    if declarations.private.contains(item)
+                        && self.current.as_ref().map(|s| s.as_str()) != Some(package)
+                    {
+                        self.errors.push(Diagnostic::new(
+                            format!("{} is private to package {}", name, package),
+                            span,
+                        ))
+                    }
+                }
+            }
+        } else if let Some(package) = &self.current {
+            if kind != Kind::Type && self.locals.contains(name.as_str()) {
+                return;
+            }
+            let declarations = &self.packages[package];
+            let declared = match kind {
+                Kind::Type => declarations.types.contains(name.as_str()),
+                Kind::Constant => declarations.constants.contains(name.as_str()),
+                Kind::Node => declarations.nodes.contains(name.as_str()),
+                Kind::Var => {
+                    declarations.constants.contains(name.as_str())
+                        || declarations.nodes.contains(name.as_str())
+                }
+            };
+            if declared {
+                *name = format!("{}::{}", package, name);
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 //! Functions to schedule untyped LucyRS nodes, and check if there
 //! is multiple definitions of variables
 
-use crate::lucy::ast::Expr::*;
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::BaseExpr::*;
 use crate::lucy::ast::{Expr, Node};
 
 use petgraph::graphmap::GraphMap;
@@ -10,27 +11,35 @@ use std::collections::HashSet;
 
 /// Schedule the untyped LucyRS nodes
 /// Also, check if there is multiple definitions of variables in nodes
-pub fn schedule(nodes: Vec<Node>) -> Result<Vec<Node>, String> {
-    let mut nodes = schedule_nodes(nodes)?;
+pub fn schedule(nodes: Vec<Node>) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let mut nodes = schedule_nodes(nodes).map_err(|diagnostic| vec![diagnostic])?;
+    let mut errors = vec![];
     for node in &mut nodes {
-        check_multiple_definition(node)?;
-        match check_causality_node(node) {
-            Ok(_) => (),
-            Err(message) => {
-                return Err(format!("Node {} is not causal: {}", node.name, message));
-            }
+        if let Err(diagnostic) = check_multiple_definition(node) {
+            errors.push(diagnostic);
+            continue;
+        }
+        if let Err(diagnostic) = check_causality_node(node) {
+            errors.push(diagnostic.with_context(&format!("Node {} is not causal", node.name)));
         }
     }
-    Ok(nodes)
+    if errors.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(errors)
+    }
 }
 
 /// Check if there is multiple definitions of variables
-fn check_multiple_definition(node: &Node) -> Result<(), String> {
+fn check_multiple_definition(node: &Node) -> Result<(), Diagnostic> {
     let mut set = HashSet::new();
     for eq in &node.eq_list {
-        for ident in &eq.0 {
+        for ident in &eq.idents {
             if set.get(ident).is_some() {
-                return Err(format!("{} was defined twice in node {}", ident, node.name));
+                return Err(Diagnostic::new(
+                    format!("{} was defined twice in node {}", ident, node.name),
+                    eq.span,
+                ));
             }
             set.insert(ident);
         }
@@ -38,17 +47,21 @@ fn check_multiple_definition(node: &Node) -> Result<(), String> {
     Ok(())
 }
 
-fn schedule_nodes(nodes: Vec<Node>) -> Result<Vec<Node>, String> {
+fn schedule_nodes(nodes: Vec<Node>) -> Result<Vec<Node>, Diagnostic> {
     let mut causality_graph = GraphMap::<&str, (), petgraph::Directed>::new();
     let mut nodes_hm = HashMap::<&str, &Node>::new();
     for i in 0..nodes.len() {
         causality_graph.add_node(&nodes[i].name);
         nodes_hm.insert(&nodes[i].name, &nodes[i]);
     }
+    // The imported nodes, and the undeclared ones reported by the typing,
+    // do not call other nodes
     for node in &*nodes {
-        for (_, expr) in &node.eq_list {
-            for called_node in get_node_deps(expr) {
-                causality_graph.add_edge(&node.name, called_node, ());
+        for eq in &node.eq_list {
+            for called_node in get_node_deps(&eq.expr) {
+                if nodes_hm.contains_key(called_node) {
+                    causality_graph.add_edge(&node.name, called_node, ());
+                }
             }
         }
     }
@@ -58,28 +71,32 @@ fn schedule_nodes(nodes: Vec<Node>) -> Result<Vec<Node>, String> {
             .into_iter()
             .map(|node| (*nodes_hm.get(&node).unwrap()).clone())
             .collect()),
-        Err(cycle) => Err(format!(
-            "There is a cyclic call between the nodes. {} participates in the cycle",
-            cycle.node_id()
+        Err(cycle) => Err(Diagnostic::new(
+            format!(
+                "There is a cyclic call between the nodes. {} participates in the cycle",
+                cycle.node_id()
+            ),
+            nodes_hm.get(&cycle.node_id()).unwrap().span,
         )),
     }
 }
 
 fn get_node_deps<'a>(expr: &'a Expr) -> Vec<&'a str> {
-    match expr {
+    match &expr.expr {
         Value(_) | Var(_) | Current(_, _) => vec![],
         Pre(box e) => get_node_deps(&e),
-        Fby(_, box e) => get_node_deps(&e),
         UnOp(_, box e) => get_node_deps(&e),
-        BinOp(_, box e1, box e2) => {
+        BinOp(_, box e1, box e2) | Fby(box e1, box e2) => {
             let mut v = get_node_deps(&e1);
             v.append(&mut get_node_deps(&e2));
             v
         }
         When(box expr, _, _) => get_node_deps(&expr),
-        Merge(_, box e1, box e2) => {
-            let mut v = get_node_deps(&e1);
-            v.append(&mut get_node_deps(&e2));
+        Merge(_, branches) => {
+            let mut v = vec![];
+            for (_, expr) in branches {
+                v.append(&mut get_node_deps(&expr));
+            }
             v
         }
         IfThenElse(box e1, box e2, box e3) => {
@@ -88,7 +105,7 @@ fn get_node_deps<'a>(expr: &'a Expr) -> Vec<&'a str> {
             v.append(&mut get_node_deps(&e3));
             v
         }
-        FunCall(fun, exprs, _) => {
+        FunCall(fun, exprs, _) | Iterate(_, fun, _, exprs) => {
             let mut v = vec![];
             for expr in exprs {
                 v.append(&mut get_node_deps(&expr));
@@ -96,18 +113,39 @@ fn get_node_deps<'a>(expr: &'a Expr) -> Vec<&'a str> {
             v.push(&fun);
             v
         }
-        Arrow(exprs) => {
+        Arrow(exprs) | Array(exprs) | Tuple(exprs) => {
             let mut v = vec![];
             for expr in exprs {
                 v.append(&mut get_node_deps(&expr));
             }
             v
         }
+        Index(box e1, box e2) | Concat(box e1, box e2) => {
+            let mut v = get_node_deps(&e1);
+            v.append(&mut get_node_deps(&e2));
+            v
+        }
+        Slice(box e, _, _) | Field(box e, _) | Reset(box e, _) => get_node_deps(&e),
+        StaticCall(_, _, _, _) => unreachable!(),
+        Record(fields) => {
+            let mut v = vec![];
+            for (_, expr) in fields {
+                v.append(&mut get_node_deps(&expr));
+            }
+            v
+        }
+        With(box e, fields) => {
+            let mut v = get_node_deps(&e);
+            for (_, expr) in fields {
+                v.append(&mut get_node_deps(&expr));
+            }
+            v
+        }
     }
 }
 
 /// Check if the node is causal, and schedule it if it is
-fn check_causality_node(node: &mut Node) -> Result<(), String> {
+fn check_causality_node(node: &mut Node) -> Result<(), Diagnostic> {
     let mut causality_graph = GraphMap::<usize, (), petgraph::Directed>::new();
     for i in 0..node.eq_list.len() {
         causality_graph.add_node(i);
@@ -115,9 +153,9 @@ fn check_causality_node(node: &mut Node) -> Result<(), String> {
     let var_dependencies: Vec<Vec<&str>> = node
         .eq_list
         .iter()
-        .map(|(_, e)| get_var_deps(e, node))
+        .map(|eq| get_var_deps(&eq.expr, node))
         .collect();
-    let defined_vars: Vec<&Vec<String>> = node.eq_list.iter().map(|(v, _)| v).collect();
+    let defined_vars: Vec<&Vec<String>> = node.eq_list.iter().map(|eq| &eq.idents).collect();
     for i in 0..node.eq_list.len() {
         for j in 0..node.eq_list.len() {
             for defined_var in defined_vars[i] {
@@ -139,15 +177,17 @@ fn check_causality_node(node: &mut Node) -> Result<(), String> {
             Ok(())
         },
         Err(cycle) => {
-            Err(format!("There is a cycle in variables definitions: one variable assigned next to {} is in the cycle", node.eq_list[cycle.node_id()].0[0]))
+            let eq = &node.eq_list[cycle.node_id()];
+            Err(Diagnostic::new(format!("There is a cycle in variables definitions: one variable assigned next to {} is in the cycle", eq.idents[0]), eq.span))
         }
     }
 }
 
 /// Get the var dependencies of an expression
 fn get_var_deps<'a>(expr: &'a Expr, node: &'a Node) -> Vec<&'a str> {
-    match expr {
-        Value(_) | Fby(_, _) | Pre(_) => vec![],
+    match &expr.expr {
+        Value(_) | Pre(_) => vec![],
+        Fby(box init, _) => get_var_deps(&init, node),
         UnOp(_, box e) => get_var_deps(&e, node),
         BinOp(_, box e1, box e2) => {
             let mut v = get_var_deps(&e1, node);
@@ -156,13 +196,15 @@ fn get_var_deps<'a>(expr: &'a Expr, node: &'a Node) -> Vec<&'a str> {
         }
         When(box expr, ck, _) => {
             let mut v = get_var_deps(&expr, node);
-            v.push(ck);
+            v.push(ck.as_var());
             v
         }
-        Merge(ck, box e1, box e2) => {
-            let mut v = get_var_deps(&e1, node);
-            v.append(&mut get_var_deps(&e2, node));
-            v.push(ck);
+        Merge(ck, branches) => {
+            let mut v = vec![];
+            for (_, expr) in branches {
+                v.append(&mut get_var_deps(&expr, node));
+            }
+            v.push(ck.as_var());
             v
         }
         IfThenElse(box e1, box e2, box e3) => {
@@ -185,17 +227,43 @@ fn get_var_deps<'a>(expr: &'a Expr, node: &'a Node) -> Vec<&'a str> {
                 v.append(&mut get_var_deps(&expr, node));
             }
             if let Some(ck) = ck {
-                v.push(ck);
+                v.push(ck.as_var());
             }
             v
         }
-        Current(s, _) => vec![s],
-        Arrow(exprs) => {
+        Current(s, _) => vec![s.as_var()],
+        Arrow(exprs) | Array(exprs) | Tuple(exprs) | Iterate(_, _, _, exprs) => {
             let mut v = vec![];
             for expr in exprs {
                 v.append(&mut get_var_deps(&expr, node));
             }
             v
         }
+        Index(box e1, box e2) | Concat(box e1, box e2) => {
+            let mut v = get_var_deps(&e1, node);
+            v.append(&mut get_var_deps(&e2, node));
+            v
+        }
+        Slice(box e, _, _) | Field(box e, _) => get_var_deps(&e, node),
+        Record(fields) => {
+            let mut v = vec![];
+            for (_, expr) in fields {
+                v.append(&mut get_var_deps(&expr, node));
+            }
+            v
+        }
+        With(box e, fields) => {
+            let mut v = get_var_deps(&e, node);
+            for (_, expr) in fields {
+                v.append(&mut get_var_deps(&expr, node));
+            }
+            v
+        }
+        Reset(box e, r) => {
+            let mut v = get_var_deps(&e, node);
+            v.push(r);
+            v
+        }
+        StaticCall(_, _, _, _) => unreachable!(),
     }
 }
@@ -0,0 +1,407 @@
+//! Specialize the nodes with static parameters, declared with
+//! `node f<<n: int; g: node(int) returns (int)>>(...)`. Each call
+//! `f<<args>>(...)` is replaced by a call to an instance of f, in which the
+//! static parameters are bound to the arguments, so that the following passes
+//! only see nodes without static parameters.
+//!
+//! The constant parameters of an instance become constants of the node, and
+//! are substituted with the other constants. In an instance, the conditionals
+//! whose condition is known at compile time are replaced by the branch they
+//! select, so that a recursion over static parameters is unrolled.
+
+use crate::ast::{Span, Type, TypeDecl, Value};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{BaseExpr, ConstDecl, Expr, Node, Program, StaticKind};
+use crate::lucy::constants::{self, Scope};
+
+use std::collections::{HashMap, HashSet};
+
+/// Number of nested instances above which an instantiation
+/// is considered as not terminating
+const MAX_DEPTH: usize = 256;
+
+struct Specializer<'a> {
+    globals: &'a Scope<'a>,
+    types: &'a Vec<TypeDecl>,
+    constructors: &'a HashMap<String, String>,
+    /// Nodes with static parameters
+    templates: HashMap<String, Node>,
+    /// Types of the inputs and outputs of the nodes without static parameters
+    signatures: HashMap<String, (Vec<Type>, Vec<Type>)>,
+    /// Instance created for a node and its static arguments
+    instances: HashMap<String, String>,
+    /// Names of the nodes, used to give unique names to the instances
+    names: HashSet<String>,
+    /// Instances still to specialize, with their depth and the nodes
+    /// given to their node parameters
+    pending: Vec<(Node, usize, HashMap<String, String>)>,
+}
+
+/// Replace the calls to nodes with static parameters by calls to their
+/// instances, and remove the nodes with static parameters
+pub fn specialize(program: Program) -> Result<Program, Vec<Diagnostic>> {
+    let Program {
+        types,
+        constants,
+        nodes,
+        imported,
+        ..
+    } = program;
+    let constructors = constants::constructors(&types);
+    let mut globals = Scope::new(&constants, None, &types, &constructors);
+    globals.evaluate_all(&constants)?;
+
+    let mut specializer = Specializer {
+        globals: &globals,
+        types: &types,
+        constructors: &constructors,
+        templates: HashMap::new(),
+        signatures: HashMap::new(),
+        instances: HashMap::new(),
+        names: nodes
+            .iter()
+            .map(|node| &node.name)
+            .chain(imported.iter().map(|node| &node.name))
+            .cloned()
+            .collect(),
+        pending: vec![],
+    };
+    let take_types = |vec: &Vec<(String, Type)>| vec.iter().map(|(_, t)| t.clone()).collect();
+    for node in &imported {
+        specializer.signatures.insert(
+            node.name.clone(),
+            (take_types(&node.in_params), take_types(&node.out_params)),
+        );
+    }
+    let mut new_nodes = vec![];
+    for node in nodes {
+        if node.static_params.is_empty() {
+            specializer.signatures.insert(
+                node.name.clone(),
+                (take_types(&node.in_params), take_types(&node.out_params)),
+            );
+            new_nodes.push(node);
+        } else {
+            specializer.templates.insert(node.name.clone(), node);
+        }
+    }
+
+    let mut errors = vec![];
+    for node in &mut new_nodes {
+        errors.extend(specializer.specialize_node(node, 0, &HashMap::new()));
+    }
+    while let Some((mut node, depth, node_args)) = specializer.pending.pop() {
+        errors.extend(specializer.specialize_node(&mut node, depth, &node_args));
+        new_nodes.push(node);
+    }
+
+    if errors.is_empty() {
+        Ok(Program {
+            types,
+            constants,
+            nodes: new_nodes,
+            imported,
+            ..Default::default()
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+impl<'a> Specializer<'a> {
+    /// Replace the static calls of a node, depth being its number of enclosing instances
+    fn specialize_node(
+        &mut self,
+        node: &mut Node,
+        depth: usize,
+        node_args: &HashMap<String, String>,
+    ) -> Vec<Diagnostic> {
+        let context = format!("Error while specializing node {}", node.name);
+        let Node {
+            constants, eq_list, ..
+        } = node;
+        let mut scope = Scope::new(constants, Some(self.globals), self.types, self.constructors);
+        let mut errors = vec![];
+        for eq in eq_list {
+            self.specialize_expr(&mut eq.expr, &mut scope, node_args, depth, &mut errors);
+        }
+        errors
+            .into_iter()
+            .map(|d| d.with_context(&context))
+            .collect()
+    }
+
+    /// Replace the static calls of an expression, and the node parameters
+    fn specialize_expr(
+        &mut self,
+        expr: &mut Expr,
+        scope: &mut Scope,
+        node_args: &HashMap<String, String>,
+        depth: usize,
+        errors: &mut Vec<Diagnostic>,
+    ) {
+        if depth > 0 {
+            if let BaseExpr::IfThenElse(cond, e_then, e_else) = &expr.expr {
+                if let Ok(Value::Bool(b)) = scope.evaluate(cond) {
+                    *expr = if b { *e_then.clone() } else { *e_else.clone() };
+                    return self.specialize_expr(expr, scope, node_args, depth, errors);
+                }
+            }
+        }
+        let span = expr.span;
+        let mut specialize =
+            |e: &mut Expr| self.specialize_expr(e, scope, node_args, depth, errors);
+        match &mut expr.expr {
+            BaseExpr::Value(_) | BaseExpr::Var(_) | BaseExpr::Current(_, _) => (),
+            BaseExpr::UnOp(_, box e)
+            | BaseExpr::When(box e, _, _)
+            | BaseExpr::Pre(box e)
+            | BaseExpr::Slice(box e, _, _)
+            | BaseExpr::Field(box e, _)
+            | BaseExpr::Reset(box e, _) => specialize(e),
+            BaseExpr::BinOp(_, box e1, box e2)
+            | BaseExpr::Fby(box e1, box e2)
+            | BaseExpr::Index(box e1, box e2)
+            | BaseExpr::Concat(box e1, box e2) => {
+                specialize(e1);
+                specialize(e2);
+            }
+            BaseExpr::IfThenElse(box e1, box e2, box e3) => {
+                specialize(e1);
+                specialize(e2);
+                specialize(e3);
+            }
+            BaseExpr::Arrow(exprs) | BaseExpr::Array(exprs) | BaseExpr::Tuple(exprs) => {
+                exprs.iter_mut().for_each(specialize)
+            }
+            BaseExpr::Merge(_, branches) | BaseExpr::Record(branches) => {
+                branches.iter_mut().for_each(|(_, e)| specialize(e))
+            }
+            BaseExpr::With(box e, fields) => {
+                specialize(e);
+                fields.iter_mut().for_each(|(_, e)| specialize(e));
+            }
+            BaseExpr::FunCall(fun, exprs, _) | BaseExpr::Iterate(_, fun, _, exprs) => {
+                exprs.iter_mut().for_each(specialize);
+                if let Some(node) = node_args.get(fun) {
+                    *fun = node.clone();
+                } else if let Some(template) = self.templates.get(fun) {
+                    errors.push(Diagnostic::new(
+                        format!(
+                            "Node {} expects {} static arguments, but 0 were given",
+                            fun,
+                            template.static_params.len()
+                        ),
+                        span,
+                    ));
+                }
+            }
+            BaseExpr::StaticCall(fun, static_args, exprs, reset) => {
+                exprs.iter_mut().for_each(specialize);
+                match self.instantiate(fun, static_args, span, scope, node_args, depth) {
+                    Ok(instance) => {
+                        let exprs = std::mem::replace(exprs, vec![]);
+                        expr.expr = BaseExpr::FunCall(instance, exprs, reset.take());
+                    }
+                    Err(diagnostic) => errors.push(diagnostic),
+                }
+            }
+        }
+    }
+
+    /// Get the instance of a node for some static arguments, creating it if needed
+    fn instantiate(
+        &mut self,
+        fun: &str,
+        static_args: &Vec<Expr>,
+        span: Span,
+        scope: &mut Scope,
+        node_args: &HashMap<String, String>,
+        depth: usize,
+    ) -> Result<String, Diagnostic> {
+        let fun = node_args.get(fun).map(|s| s.as_str()).unwrap_or(fun);
+        let params = match self.templates.get(fun) {
+            Some(template) => template.static_params.clone(),
+            None if self.signatures.contains_key(fun) => {
+                return Err(Diagnostic::new(
+                    format!("Node {} has no static parameters", fun),
+                    span,
+                ))
+            }
+            None => {
+                return Err(Diagnostic::new(
+                    format!("Node {} used but not declared", fun),
+                    span,
+                ))
+            }
+        };
+        if params.len() != static_args.len() {
+            return Err(Diagnostic::new(
+                format!(
+                    "Node {} expects {} static arguments, but {} were given",
+                    fun,
+                    params.len(),
+                    static_args.len()
+                ),
+                span,
+            ));
+        }
+
+        let mut decls = vec![];
+        let mut bindings = HashMap::new();
+        let mut key_args = vec![];
+        for (param, arg) in params.iter().zip(static_args) {
+            match &param.kind {
+                StaticKind::Const(typ) => {
+                    let value = scope.evaluate(arg)?;
+                    if let Some(resolved) = self.resolve(typ, param.span) {
+                        if value.get_type() != resolved {
+                            return Err(Diagnostic::new(
+                                format!(
+                                    "The static parameter {} of node {} has type {}, but its argument has type {}",
+                                    param.name,
+                                    fun,
+                                    resolved,
+                                    value.get_type()
+                                ),
+                                arg.span,
+                            ));
+                        }
+                    }
+                    key_args.push(value.to_string());
+                    decls.push(ConstDecl {
+                        name: param.name.clone(),
+                        typ: typ.clone(),
+                        expr: Expr::new(BaseExpr::Value(value), arg.span),
+                        span: param.span,
+                    });
+                }
+                StaticKind::Node(inputs, outputs) => {
+                    let name = match &arg.expr {
+                        BaseExpr::Var(name) => node_args.get(name).unwrap_or(name).clone(),
+                        _ => {
+                            return Err(Diagnostic::new(
+                                format!(
+                                    "The static parameter {} of node {} expects a node",
+                                    param.name, fun
+                                ),
+                                arg.span,
+                            ))
+                        }
+                    };
+                    let (node_inputs, node_outputs) = match self.signatures.get(&name) {
+                        Some(signature) => signature,
+                        None if self.templates.contains_key(&name) => {
+                            return Err(Diagnostic::new(
+                                format!(
+                                    "Node {} has static parameters, it cannot be given as a static argument",
+                                    name
+                                ),
+                                arg.span,
+                            ))
+                        }
+                        None => {
+                            return Err(Diagnostic::new(
+                                format!("Node {} used but not declared", name),
+                                arg.span,
+                            ))
+                        }
+                    };
+                    if !self.same_types(inputs, node_inputs, param.span)
+                        || !self.same_types(outputs, node_outputs, param.span)
+                    {
+                        return Err(Diagnostic::new(
+                            format!(
+                                "The static parameter {} of node {} expects a {}, but node {} is a {}",
+                                param.name,
+                                fun,
+                                signature_to_string(inputs, outputs),
+                                name,
+                                signature_to_string(node_inputs, node_outputs)
+                            ),
+                            arg.span,
+                        ));
+                    }
+                    key_args.push(name.clone());
+                    bindings.insert(param.name.clone(), name);
+                }
+            }
+        }
+
+        let key = format!("{}<<{}>>", fun, key_args.join(", "));
+        if let Some(instance) = self.instances.get(&key) {
+            return Ok(instance.clone());
+        }
+        if depth >= MAX_DEPTH {
+            return Err(Diagnostic::new(
+                format!(
+                    "The instantiation of node {} does not terminate, it needs more than {} nested instances",
+                    fun, MAX_DEPTH
+                ),
+                span,
+            ));
+        }
+        let name = self.instance_name(fun, &key_args);
+        self.instances.insert(key, name.clone());
+        let mut instance = self.templates[fun].clone();
+        decls.append(&mut instance.constants);
+        instance.name = name.clone();
+        instance.static_params = vec![];
+        instance.constants = decls;
+        self.pending.push((instance, depth + 1, bindings));
+        Ok(name)
+    }
+
+    /// Give a name to an instance, different from the names of the other nodes
+    fn instance_name(&mut self, fun: &str, key_args: &Vec<String>) -> String {
+        let mut base = String::from(fun);
+        for arg in key_args {
+            base.push('_');
+            for c in arg.chars() {
+                match c {
+                    '-' => base.push('m'),
+                    '.' => base.push('_'),
+                    c if c.is_alphanumeric() || c == '_' => base.push(c),
+                    _ => (),
+                }
+            }
+        }
+        let mut name = base.clone();
+        let mut i = 1;
+        while self.names.contains(&name) {
+            name = format!("{}_{}", base, i);
+            i += 1;
+        }
+        self.names.insert(name.clone());
+        name
+    }
+
+    /// Resolve the aliases and the global constant sizes of a type
+    fn resolve(&self, typ: &Type, span: Span) -> Option<Type> {
+        let typ = TypeDecl::resolve(self.types, typ).ok()?;
+        self.globals.substitute_type(&typ, span).ok()
+    }
+
+    /// Check that the types of two signatures are the same
+    fn same_types(&self, expected: &Vec<Type>, found: &Vec<Type>, span: Span) -> bool {
+        expected.len() == found.len()
+            && expected.iter().zip(found).all(|(t1, t2)| {
+                match (self.resolve(t1, span), self.resolve(t2, span)) {
+                    (Some(t1), Some(t2)) => t1 == t2,
+                    _ => t1 == t2,
+                }
+            })
+    }
+}
+
+/// Display the signature of a node, as in `node(int) returns (int)`
+fn signature_to_string(inputs: &Vec<Type>, outputs: &Vec<Type>) -> String {
+    let join = |types: &Vec<Type>| {
+        types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    format!("node({}) returns ({})", join(inputs), join(outputs))
+}
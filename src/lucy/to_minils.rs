@@ -1,113 +1,476 @@
 //! Translate typed LucyRS AST into minils AST
 
-use crate::ast::{Clock, Type, Value, BinOp};
+use crate::ast::{BinOp, Clock, Span, Type, TypeDecl, UnOp, Value};
+use crate::diagnostic::Diagnostic;
 use crate::ident::IdentGenerator;
 use crate::lucy::clock_typed_ast as typ;
 use crate::minils::ast as minils;
 
 /// Translate a typed LucyRS AST into minils AST
-pub fn to_minils(node: typ::Node) -> minils::Node {
+/// types are the type declarations of the program
+/// The translation fails when a reset cannot be sampled on the clock of a
+/// memory it resets
+pub fn to_minils(node: typ::Node, types: &Vec<TypeDecl>) -> Result<minils::Node, Diagnostic> {
     let name = node.name;
     let in_params = node.in_params;
     let out_params = node.out_params;
+    let param_clocks = node.param_clocks;
     let local_params = node.local_params;
     let mut new_node = minils::Node {
         name,
         in_params,
         out_params,
+        param_clocks,
         local_params,
         eq_list: vec![],
+        assertions: node.assertions,
     };
     for (idents, expr) in node.eq_list {
-        let expr = to_minils_expr(
-            &IdentGenerator::new(idents[0].clone() + "_cond"),
-            expr,
-            &mut new_node,
-        );
-        new_node.eq_list.push((idents, expr));
+        let ident = IdentGenerator::new(idents[0].clone() + "_cond");
+        for (idents, expr) in split_equation(&ident, idents, expr) {
+            let expr = to_minils_expr(&ident, &None, expr, &mut new_node, types)?;
+            new_node.eq_list.push((idents, expr));
+        }
+    }
+    Ok(new_node)
+}
+
+/// Split an equation defining several variables into one equation for each
+/// variable, unless its expression is a node call. The tuples are flattened,
+/// and the operators applied to tuples are applied to each of their values.
+/// The node calls inside the expression, and the conditions which are not
+/// variables, are defined by new equations, so that they are not duplicated
+fn split_equation(
+    ident: &IdentGenerator,
+    idents: Vec<String>,
+    expr: typ::Expr,
+) -> Vec<(Vec<String>, typ::Expr)> {
+    let mut root = &expr;
+    while let typ::BaseExpr::Reset(box e, _) = &root.expr {
+        root = e;
+    }
+    match root.expr {
+        typ::BaseExpr::FunCall(_, _, _, _) | typ::BaseExpr::Iterate(_, _, _, _) => {
+            return vec![(idents, expr)]
+        }
+        _ if idents.len() == 1 => return vec![(idents, expr)],
+        _ => (),
     }
-    new_node
+    let mut eq_list = vec![];
+    let values = split(ident, &vec![], expr, &mut eq_list);
+    eq_list.extend(idents.into_iter().map(|x| vec![x]).zip(values));
+    eq_list
+}
+
+/// Split an expression into its values, the new equations are added to
+/// eq_list. resets are the variables resetting the expression
+fn split(
+    ident: &IdentGenerator,
+    resets: &Vec<String>,
+    expr: typ::Expr,
+    eq_list: &mut Vec<(Vec<String>, typ::Expr)>,
+) -> Vec<typ::Expr> {
+    if expr.typ.len() == 1 {
+        return vec![expr];
+    }
+    let typ::Expr {
+        expr,
+        typ,
+        clock,
+        span,
+    } = expr;
+    let value = |i: usize, expr| typ::Expr {
+        expr,
+        typ: vec![typ[i].clone()],
+        clock: clock.clone(),
+        span,
+    };
+    let reset = |mut expr: typ::Expr| {
+        for r in resets.iter().rev() {
+            let (typ, clock, span) = (expr.typ.clone(), expr.clock.clone(), expr.span);
+            let expr_ = typ::BaseExpr::Reset(box expr, r.clone());
+            expr = typ::Expr {
+                expr: expr_,
+                typ,
+                clock,
+                span,
+            };
+        }
+        expr
+    };
+    match expr {
+        typ::BaseExpr::Tuple(exprs) => {
+            let mut values = vec![];
+            for e in exprs {
+                values.extend(split(ident, resets, e, eq_list));
+            }
+            values
+        }
+        typ::BaseExpr::Pre(box e) => {
+            let values = split(ident, resets, e, eq_list).into_iter();
+            let values = values.map(|e| typ::BaseExpr::Pre(box e));
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::When(box e, ck, c) => {
+            let values = split(ident, resets, e, eq_list).into_iter();
+            let values = values.map(|e| typ::BaseExpr::When(box e, ck.clone(), c.clone()));
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::Reset(box e, r) => {
+            let mut inner_resets = resets.clone();
+            inner_resets.push(r.clone());
+            let values = split(ident, &inner_resets, e, eq_list).into_iter();
+            let values = values.map(|e| typ::BaseExpr::Reset(box e, r.clone()));
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::Fby(box e1, box e2) => {
+            let values_1 = split(ident, resets, e1, eq_list);
+            let values_2 = split(ident, resets, e2, eq_list);
+            let values = values_1
+                .into_iter()
+                .zip(values_2)
+                .map(|(e1, e2)| typ::BaseExpr::Fby(box e1, box e2));
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::Arrow(exprs) => {
+            let values = exprs
+                .into_iter()
+                .map(|e| split(ident, resets, e, eq_list))
+                .collect();
+            let values = transpose(values, typ.len()).into_iter();
+            let values = values.map(typ::BaseExpr::Arrow);
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::Merge(ck, branches) => {
+            let (constructors, exprs): (Vec<_>, Vec<_>) = branches.into_iter().unzip();
+            let values = exprs
+                .into_iter()
+                .map(|e| split(ident, resets, e, eq_list))
+                .collect();
+            let values = transpose(values, typ.len()).into_iter().map(|exprs| {
+                let branches = constructors.iter().cloned().zip(exprs).collect();
+                typ::BaseExpr::Merge(ck.clone(), branches)
+            });
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
+            let cond = match cond.expr {
+                typ::BaseExpr::Var(_) | typ::BaseExpr::Value(_) => cond,
+                _ => {
+                    let name = ident.new_ident().get_ident();
+                    let var = typ::Expr {
+                        expr: typ::BaseExpr::Var(name.clone()),
+                        typ: cond.typ.clone(),
+                        clock: cond.clock.clone(),
+                        span: cond.span,
+                    };
+                    eq_list.push((vec![name], reset(cond)));
+                    var
+                }
+            };
+            let values_t = split(ident, resets, e_t, eq_list);
+            let values_f = split(ident, resets, e_f, eq_list);
+            let values = values_t.into_iter().zip(values_f).map(|(e_t, e_f)| {
+                typ::BaseExpr::IfThenElse(box cond.clone(), box e_t, box e_f)
+            });
+            values.enumerate().map(|(i, e)| value(i, e)).collect()
+        }
+        typ::BaseExpr::FunCall(_, _, _, _) | typ::BaseExpr::Iterate(_, _, _, _) => {
+            let clocks = match &expr {
+                typ::BaseExpr::FunCall(_, _, _, clocks) => clocks.clone(),
+                _ => vec![clock.clone(); typ.len()],
+            };
+            let names: Vec<String> = typ.iter().map(|_| ident.new_ident().get_ident()).collect();
+            let vars = names
+                .iter()
+                .zip(clocks)
+                .enumerate()
+                .map(|(i, (name, clock))| typ::Expr {
+                    clock,
+                    ..value(i, typ::BaseExpr::Var(name.clone()))
+                })
+                .collect();
+            let call = typ::Expr {
+                expr,
+                typ: typ.clone(),
+                clock: clock.clone(),
+                span,
+            };
+            eq_list.push((names, reset(call)));
+            vars
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Group the i-th values of expressions which have n values each
+fn transpose(values: Vec<Vec<typ::Expr>>, n: usize) -> Vec<Vec<typ::Expr>> {
+    let mut transposed = vec![vec![]; n];
+    for exprs in values {
+        for (i, e) in exprs.into_iter().enumerate() {
+            transposed[i].push(e);
+        }
+    }
+    transposed
 }
 
 /// Translate a typed LucyRS expression into minils expression
 /// This function remove some syntaxic sugar from LucyRS,
 /// like if_then_else construct, or pre, or arrow
+/// reset is the variable that resets the state of the expression, if any
 fn to_minils_expr(
     ident: &IdentGenerator,
+    reset: &Option<String>,
     expr: typ::Expr,
     node: &mut minils::Node,
-) -> minils::Expr {
+    types: &Vec<TypeDecl>,
+) -> Result<minils::Expr, Diagnostic> {
     let expr_ = match expr.expr {
         typ::BaseExpr::Value(v) => minils::BaseExpr::Value(v),
         typ::BaseExpr::UnOp(op, box e1) => {
-            let e1 = to_minils_expr(ident, e1, node);
+            let e1 = to_minils_expr(ident, reset, e1, node, types)?;
             minils::BaseExpr::UnOp(op, box e1)
         }
         typ::BaseExpr::BinOp(op, box e1, box e2) => {
-            let e1 = to_minils_expr(ident, e1, node);
-            let e2 = to_minils_expr(ident, e2, node);
+            let e1 = to_minils_expr(ident, reset, e1, node, types)?;
+            let e2 = to_minils_expr(ident, reset, e2, node, types)?;
             minils::BaseExpr::BinOp(op, box e1, box e2)
         }
-        typ::BaseExpr::Fby(e1, box e2) => {
-            let e2 = to_minils_expr(ident, e2, node);
-            minils::BaseExpr::Fby(e1, box e2)
-        }
-        typ::BaseExpr::When(box e, ck, b) => {
-            let e = to_minils_expr(ident, e, node);
-            minils::BaseExpr::When(box e, ck, b)
+        typ::BaseExpr::Fby(box e1, box e2) => match e1.expr {
+            typ::BaseExpr::Value(v) => {
+                let e2 = to_minils_expr(ident, reset, e2, node, types)?;
+                let fby = minils::Expr {
+                    expr: minils::BaseExpr::Fby(v.clone(), box e2),
+                    typ: expr.typ,
+                    clock: expr.clock,
+                };
+                return reset_memory(ident, reset, v, fby, expr.span, node, types);
+            }
+            _ => to_minils_fby(
+                ident,
+                reset,
+                e1,
+                e2,
+                expr.clock.clone(),
+                expr.span,
+                node,
+                types,
+            )?,
+        },
+        typ::BaseExpr::When(box e, ck, value) => {
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            minils::BaseExpr::When(box e, ck, value)
         }
-        typ::BaseExpr::Merge(ck, box e_t, box e_f) => {
-            let e_t = to_minils_expr(ident, e_t, node);
-            let e_f = to_minils_expr(ident, e_f, node);
-            minils::BaseExpr::Merge(ck, box e_t, box e_f)
+        typ::BaseExpr::Merge(ck, branches) => {
+            let branches = branches
+                .into_iter()
+                .map(|(value, e)| Ok((value, to_minils_expr(ident, reset, e, node, types)?)))
+                .collect::<Result<_, _>>()?;
+            minils::BaseExpr::Merge(ck, branches)
         }
         typ::BaseExpr::IfThenElse(box e_cond, box e_t, box e_f) => {
-            let e_cond = to_minils_expr(ident, e_cond, node);
+            let e_cond = to_minils_expr(ident, reset, e_cond, node, types)?;
             let name_cond = ident.new_ident().get_ident();
             node.eq_list.push((vec![name_cond.clone()], e_cond));
-            let e_t = to_minils_expr(ident, e_t, node);
-            let e_f = to_minils_expr(ident, e_f, node);
-            minils::BaseExpr::Merge(name_cond, box e_t, box e_f)
+            let e_t = to_minils_expr(ident, reset, e_t, node, types)?;
+            let e_f = to_minils_expr(ident, reset, e_f, node, types)?;
+            minils::BaseExpr::Merge(
+                name_cond,
+                vec![(Value::Bool(true), e_t), (Value::Bool(false), e_f)],
+            )
         }
         typ::BaseExpr::Var(s) => minils::BaseExpr::Var(s),
-        typ::BaseExpr::FunCall(s, exprs, r) => {
+        typ::BaseExpr::FunCall(s, exprs, r, clocks) => {
             let exprs = exprs
                 .into_iter()
-                .map(|e| to_minils_expr(ident, e, node))
-                .collect();
-            minils::BaseExpr::FunCall(s, exprs, r)
+                .map(|e| to_minils_expr(ident, reset, e, node, types))
+                .collect::<Result<_, _>>()?;
+            let r = match (reset, r) {
+                (None, r) => r,
+                (Some(reset), None) => Some(reset.clone()),
+                (Some(reset), Some(r)) => Some(combine_resets(ident, reset, &r, expr.span, node)?),
+            };
+            minils::BaseExpr::FunCall(s, exprs, r, clocks)
+        }
+        typ::BaseExpr::Iterate(kind, s, n, exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|e| to_minils_expr(ident, reset, e, node, types))
+                .collect::<Result<_, _>>()?;
+            minils::BaseExpr::Iterate(kind, s, n, exprs, reset.clone())
         }
         typ::BaseExpr::Current(s, v) => {
-            let clock = &node.local_params.get(&s).unwrap().1;
-            to_minils_current(s, v, clock.clone(), expr.typ[0].clone(), node)
+            let clock = var_clock(node, &s);
+            to_minils_current(
+                ident,
+                reset,
+                s,
+                v,
+                clock,
+                expr.typ[0].clone(),
+                expr.span,
+                node,
+                types,
+            )?
         }
         typ::BaseExpr::Pre(box e) => {
-            let e = to_minils_expr(ident, e, node);
-            let value = match &e.typ[0] {
-                Type::Int => Value::Int(-12341234),
-                Type::Real => Value::Real(std::f32::NAN),
-                Type::Bool => Value::Bool(false),
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            let value = pre_value(&e.typ[0], types);
+            let fby = minils::Expr {
+                expr: minils::BaseExpr::Fby(value.clone(), box e),
+                typ: expr.typ,
+                clock: expr.clock,
             };
-            minils::BaseExpr::Fby(value, box e)
+            return reset_memory(ident, reset, value, fby, expr.span, node, types);
         }
         typ::BaseExpr::Arrow(exprs) => {
-            return to_minils_arrow(ident, exprs, expr.clock.clone(), expr.typ[0].clone(), node);
+            return to_minils_arrow(
+                ident,
+                reset,
+                exprs,
+                expr.clock.clone(),
+                expr.typ[0].clone(),
+                expr.span,
+                node,
+                types,
+            );
+        }
+        typ::BaseExpr::Array(exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|e| to_minils_expr(ident, reset, e, node, types))
+                .collect::<Result<_, _>>()?;
+            minils::BaseExpr::Array(exprs)
+        }
+        typ::BaseExpr::Index(box e, box i) => {
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            let i = to_minils_expr(ident, reset, i, node, types)?;
+            minils::BaseExpr::Index(box e, box i)
+        }
+        typ::BaseExpr::Slice(box e, i, j) => {
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            minils::BaseExpr::Slice(box e, i, j)
+        }
+        typ::BaseExpr::Concat(box e1, box e2) => {
+            let e1 = to_minils_expr(ident, reset, e1, node, types)?;
+            let e2 = to_minils_expr(ident, reset, e2, node, types)?;
+            minils::BaseExpr::Concat(box e1, box e2)
+        }
+        typ::BaseExpr::Record(record, fields) => {
+            let fields = fields
+                .into_iter()
+                .map(|(field, e)| Ok((field, to_minils_expr(ident, reset, e, node, types)?)))
+                .collect::<Result<_, _>>()?;
+            minils::BaseExpr::Record(record, fields)
+        }
+        typ::BaseExpr::Field(box e, field) => {
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            minils::BaseExpr::Field(box e, field)
         }
+        typ::BaseExpr::With(box e, fields) => {
+            let e = to_minils_expr(ident, reset, e, node, types)?;
+            let fields = fields
+                .into_iter()
+                .map(|(field, e)| Ok((field, to_minils_expr(ident, reset, e, node, types)?)))
+                .collect::<Result<_, _>>()?;
+            minils::BaseExpr::With(box e, fields)
+        }
+        typ::BaseExpr::Reset(box e, r) => {
+            return to_minils_expr(ident, &Some(r), e, node, types);
+        }
+        typ::BaseExpr::Tuple(_) => unreachable!(),
     };
-    minils::Expr {
+    Ok(minils::Expr {
         typ: expr.typ,
         expr: expr_,
         clock: expr.clock,
+    })
+}
+
+/// Get the value of a pre expression at the first instant, which is never
+/// used since the program was checked to be initialized
+fn pre_value(typ: &Type, types: &Vec<TypeDecl>) -> Value {
+    match typ {
+        Type::Int => Value::Int(0),
+        Type::Real => Value::Real(0.0),
+        Type::Bool => Value::Bool(false),
+        Type::Named(name) if TypeDecl::is_enum(types, typ) => {
+            let constructor = TypeDecl::constructors(types, name)[0].clone();
+            Value::Enum(name.clone(), constructor)
+        }
+        Type::Named(name) => {
+            let fields = TypeDecl::fields(types, name)
+                .into_iter()
+                .map(|(field, typ)| (field, pre_value(&typ, types)))
+                .collect();
+            Value::Record(name.clone(), fields)
+        }
+        Type::Array(typ, n) => Value::Array(vec![pre_value(typ, types); *n]),
+        Type::ConstArray(_, _) | Type::Var(_) => unreachable!(),
     }
 }
 
+/// Translate `e1 fby e2` when e1 is not a constant, with a flag that is true
+/// at the first instant, and when the fby is reset:
+/// `merge first (true -> e1) (false -> v fby e2)`, where the value v is never
+/// used
+fn to_minils_fby(
+    ident: &IdentGenerator,
+    reset: &Option<String>,
+    e1: typ::Expr,
+    e2: typ::Expr,
+    clock: Clock,
+    span: Span,
+    node: &mut minils::Node,
+    types: &Vec<TypeDecl>,
+) -> Result<minils::BaseExpr, Diagnostic> {
+    let first = ident.new_ident().get_ident();
+    let value_false = minils::Expr {
+        expr: minils::BaseExpr::Value(Value::Bool(false)),
+        typ: vec![Type::Bool],
+        clock: clock.clone(),
+    };
+    let first_expr = minils::Expr {
+        expr: minils::BaseExpr::Fby(Value::Bool(true), box value_false),
+        typ: vec![Type::Bool],
+        clock: clock.clone(),
+    };
+    let first_expr = reset_memory(
+        ident,
+        reset,
+        Value::Bool(true),
+        first_expr,
+        span,
+        node,
+        types,
+    )?;
+    node.eq_list.push((vec![first.clone()], first_expr));
+
+    let e1 = to_minils_expr(ident, reset, e1, node, types)?;
+    let e2 = to_minils_expr(ident, reset, e2, node, types)?;
+    let fby = minils::Expr {
+        expr: minils::BaseExpr::Fby(pre_value(&e2.typ[0], types), box e2),
+        typ: e1.typ.clone(),
+        clock,
+    };
+    Ok(minils::BaseExpr::Merge(
+        first,
+        vec![(Value::Bool(true), e1), (Value::Bool(false), fby)],
+    ))
+}
+
+/// Translate an arrow, using a counter of the instants, that is reset
+/// with the arrow
 fn to_minils_arrow(
     ident: &IdentGenerator,
+    reset: &Option<String>,
     exprs: Vec<typ::Expr>,
     clock: Clock,
     typ: Type,
+    span: Span,
     node: &mut minils::Node,
-) -> minils::Expr {
+    types: &Vec<TypeDecl>,
+) -> Result<minils::Expr, Diagnostic> {
     let counter = ident.new_ident().get_ident();
     let var_counter = minils::Expr {
         expr: minils::BaseExpr::Var(counter.clone()),
@@ -129,48 +492,60 @@ fn to_minils_arrow(
         typ: vec![Type::Int],
         clock: clock.clone(),
     };
+    let counter_expr = reset_memory(ident, reset, Value::Int(0), counter_expr, span, node, types)?;
     node.eq_list.push((vec![counter.clone()], counter_expr));
 
     let var_counter = typ::Expr {
         expr: typ::BaseExpr::Var(counter),
         typ: vec![Type::Int],
         clock: clock.clone(),
+        span,
     };
     let counter_equal_i = |i| {
         let value_i = typ::Expr {
             expr: typ::BaseExpr::Value(Value::Int(i)),
             typ: vec![Type::Int],
             clock: clock.clone(),
+            span,
         };
         typ::Expr {
             expr: typ::BaseExpr::BinOp(BinOp::Eq, box var_counter.clone(), box value_i),
             typ: vec![Type::Bool],
             clock: clock.clone(),
+            span,
         }
     };
     let mut expr = typ::Expr {
         expr: typ::BaseExpr::IfThenElse(box counter_equal_i((exprs.len()-2) as i32), box exprs[exprs.len()-2].clone(), box exprs[exprs.len()-1].clone()),
         typ: vec![typ.clone()],
         clock: clock.clone(),
+        span,
     };
     for i in (0..exprs.len()-2).rev() {
         expr = typ::Expr {
             expr: typ::BaseExpr::IfThenElse(box counter_equal_i(i as i32), box exprs[i].clone(), box expr),
             typ: vec![typ.clone()],
             clock: clock.clone(),
+            span,
         }
     }
-    to_minils_expr(ident, expr, node)
+    to_minils_expr(ident, reset, expr, node, types)
 }
 
 /// Translate a LucyRS current expression into a minils expression
+/// The last value of the variable is kept on the base clock, so it can only
+/// be reset by a reset variable on the base clock
 fn to_minils_current(
+    ident_cond: &IdentGenerator,
+    reset: &Option<String>,
     ident: String,
     value: Value,
     clock: Clock,
     typ: Type,
+    span: Span,
     node: &mut minils::Node,
-) -> minils::BaseExpr {
+    types: &Vec<TypeDecl>,
+) -> Result<minils::BaseExpr, Diagnostic> {
     let ident_current = ident.clone() + "_current";
     let ident_pre = ident.clone() + "_pre";
     let pre_var_expr = minils::Expr {
@@ -178,84 +553,279 @@ fn to_minils_current(
         typ: vec![typ.clone()],
         clock: Clock::Ck(vec![]),
     };
-    match clock.clone() {
-        Clock::Const => minils::BaseExpr::Var(ident),
-        Clock::Ck(mut v) => {
-            let mut expr = minils::Expr {
-                typ: vec![typ.clone()],
-                expr: minils::BaseExpr::Var(ident),
+    let Clock::Ck(mut v) = clock.clone();
+    let mut expr = minils::Expr {
+        typ: vec![typ.clone()],
+        expr: minils::BaseExpr::Var(ident),
+        clock,
+    };
+    while v.len() > 0 {
+        let (ck, value) = v.pop().unwrap();
+        let clock = Clock::Ck(v.clone());
+        let mut branches = vec![];
+        for other in TypeDecl::clock_values(types, &var_type(node, &ck)) {
+            if other == value {
+                branches.push((other, expr.clone()));
+            } else {
+                let mut clock_other = v.clone();
+                clock_other.push((ck.clone(), other.clone()));
+                let clock_other = Clock::Ck(clock_other);
+                branches.push((other, nested_when(pre_var_expr.clone(), clock_other)));
+            }
+        }
+        expr = minils::Expr {
+            typ: vec![typ.clone()],
+            expr: minils::BaseExpr::Merge(ck.clone(), branches),
+            clock,
+        }
+    }
+    let current_var_expr = minils::Expr {
+        expr: minils::BaseExpr::Var(ident_current.clone()),
+        typ: vec![typ.clone()],
+        clock: Clock::Ck(vec![]),
+    };
+    let pre_expr = minils::Expr {
+        typ: vec![typ],
+        expr: minils::BaseExpr::Fby(value.clone(), box current_var_expr),
+        clock: Clock::Ck(vec![]),
+    };
+    let pre_expr = reset_memory(ident_cond, reset, value, pre_expr, span, node, types)?;
+    node.eq_list.push((vec![ident_pre], pre_expr));
+    node.eq_list.push((vec![ident_current.clone()], expr));
+    Ok(minils::BaseExpr::Var(ident_current.clone()))
+}
+
+/// Replace an expression with a memory by its initial value when the
+/// reset variable is true. A reset on a faster clock than the memory is
+/// latched until the clock of the memory is present
+fn reset_memory(
+    ident: &IdentGenerator,
+    reset: &Option<String>,
+    init: Value,
+    expr: minils::Expr,
+    span: Span,
+    node: &mut minils::Node,
+    types: &Vec<TypeDecl>,
+) -> Result<minils::Expr, Diagnostic> {
+    let reset = match reset {
+        Some(reset) => reset,
+        None => return Ok(expr),
+    };
+    let reset_clock = var_clock(node, reset);
+    let reset = if reset_clock != expr.clock && reset_clock.is_faster_or_equal_than(&expr.clock) {
+        latch_reset(ident, reset, reset_clock, &expr.clock, node, types)
+    } else {
+        reset.clone()
+    };
+    let reset_expr = sample_reset(&reset, &expr.clock, span, node)?;
+    let clock = expr.clock.clone();
+    let name_cond = ident.new_ident().get_ident();
+    node.eq_list.push((vec![name_cond.clone()], reset_expr));
+    let init = minils::Expr {
+        expr: minils::BaseExpr::Value(init),
+        typ: expr.typ.clone(),
+        clock: clock.clone(),
+    };
+    Ok(minils::Expr {
+        typ: expr.typ.clone(),
+        expr: minils::BaseExpr::Merge(
+            name_cond,
+            vec![(Value::Bool(true), init), (Value::Bool(false), expr)],
+        ),
+        clock,
+    })
+}
+
+/// Sample a reset variable on a clock, which should be slower than its own
+/// clock. A reset happening while the clock is absent is not seen, unless
+/// the reset variable is latched
+/// span is the expression which is reset
+fn sample_reset(
+    reset: &str,
+    clock: &Clock,
+    span: Span,
+    node: &minils::Node,
+) -> Result<minils::Expr, Diagnostic> {
+    let reset_clock = var_clock(node, reset);
+    let mut expr = minils::Expr {
+        expr: minils::BaseExpr::Var(reset.to_owned()),
+        typ: vec![Type::Bool],
+        clock: reset_clock.clone(),
+    };
+    if !expr.clock.is_faster_or_equal_than(clock) {
+        // The generated variables are defined by the expression at span
+        let subject = if reset.starts_with('_') {
+            String::from("The reset condition")
+        } else {
+            format!("The reset variable {}", reset)
+        };
+        return Err(Diagnostic::new(
+            format!(
+                "{} is on {}, it cannot be sampled on the clock {} of a memory it resets",
+                subject, expr.clock, clock
+            ),
+            span,
+        ));
+    }
+    let (Clock::Ck(reset_clock), Clock::Ck(clock)) = (reset_clock, clock);
+    for (ck, value) in clock[reset_clock.len()..].iter().cloned() {
+        let Clock::Ck(mut sampled_clock) = expr.clock.clone();
+        sampled_clock.push((ck.clone(), value.clone()));
+        expr = minils::Expr {
+            expr: minils::BaseExpr::When(box expr, ck, value),
+            typ: vec![Type::Bool],
+            clock: Clock::Ck(sampled_clock),
+        };
+    }
+    Ok(expr)
+}
+
+/// Define a variable on the clock of a reset variable, that is true from a
+/// reset until the given slower clock is present:
+/// `latched = reset or (false fby (latched and not present))`, where present
+/// is true when the slower clock is present
+fn latch_reset(
+    ident: &IdentGenerator,
+    reset: &str,
+    reset_clock: Clock,
+    clock: &Clock,
+    node: &mut minils::Node,
+    types: &Vec<TypeDecl>,
+) -> String {
+    let bool_expr = |expr, clock: &Clock| minils::Expr {
+        expr,
+        typ: vec![Type::Bool],
+        clock: clock.clone(),
+    };
+    let Clock::Ck(v) = &reset_clock;
+    let Clock::Ck(samples) = clock;
+    let present = presence(&samples[v.len()..], v.clone(), node, types);
+    let latched = ident.new_ident().get_ident();
+    let var_latched = bool_expr(minils::BaseExpr::Var(latched.clone()), &reset_clock);
+    let not_present = bool_expr(minils::BaseExpr::UnOp(UnOp::Not, box present), &reset_clock);
+    let pending = bool_expr(
+        minils::BaseExpr::BinOp(BinOp::And, box var_latched, box not_present),
+        &reset_clock,
+    );
+    let pending = bool_expr(
+        minils::BaseExpr::Fby(Value::Bool(false), box pending),
+        &reset_clock,
+    );
+    let var_reset = bool_expr(minils::BaseExpr::Var(reset.to_owned()), &reset_clock);
+    let expr = bool_expr(
+        minils::BaseExpr::BinOp(BinOp::Or, box var_reset, box pending),
+        &reset_clock,
+    );
+    node.eq_list.push((vec![latched.clone()], expr));
+    node.local_params
+        .insert(latched.clone(), (Type::Bool, reset_clock));
+    latched
+}
+
+/// Build an expression on the clock sampled by v, that is true when the
+/// clock further sampled by samples is present
+fn presence(
+    samples: &[(String, Value)],
+    mut v: Vec<(String, Value)>,
+    node: &minils::Node,
+    types: &Vec<TypeDecl>,
+) -> minils::Expr {
+    let clock = Clock::Ck(v.clone());
+    let (ck, value) = match samples.first() {
+        Some(sample) => sample.clone(),
+        None => {
+            return minils::Expr {
+                expr: minils::BaseExpr::Value(Value::Bool(true)),
+                typ: vec![Type::Bool],
                 clock,
-            };
-            while v.len() > 0 {
-                let (ck, b) = v.pop().unwrap();
-                let mut clock_true = v.clone();
-                clock_true.push((ck.clone(), true));
-                let clock_true = Clock::Ck(clock_true);
-                let mut clock_false = v.clone();
-                clock_false.push((ck.clone(), false));
-                let clock_false = Clock::Ck(clock_false);
-                let clock = Clock::Ck(v.clone());
-                let base_expr = match b {
-                    true => minils::BaseExpr::Merge(
-                        ck.clone(),
-                        box expr,
-                        box nested_when(pre_var_expr.clone(), clock_false),
-                    ),
-                    false => minils::BaseExpr::Merge(
-                        ck.clone(),
-                        box nested_when(pre_var_expr.clone(), clock_true),
-                        box expr,
-                    ),
-                };
-                expr = minils::Expr {
-                    typ: vec![typ.clone()],
-                    expr: base_expr,
-                    clock,
-                }
             }
-            let current_var_expr = minils::Expr {
-                expr: minils::BaseExpr::Var(ident_current.clone()),
-                typ: vec![typ.clone()],
-                clock: Clock::Ck(vec![]),
-            };
-            let pre_expr = minils::Expr {
-                typ: vec![typ],
-                expr: minils::BaseExpr::Fby(value, box current_var_expr),
-                clock: Clock::Ck(vec![]),
-            };
-            node.eq_list.push((vec![ident_pre], pre_expr));
-            node.eq_list.push((vec![ident_current.clone()], expr));
-            minils::BaseExpr::Var(ident_current.clone())
         }
+    };
+    let mut branches = vec![];
+    for other in TypeDecl::clock_values(types, &var_type(node, &ck)) {
+        v.push((ck.clone(), other.clone()));
+        let branch = if other == value {
+            presence(&samples[1..], v.clone(), node, types)
+        } else {
+            minils::Expr {
+                expr: minils::BaseExpr::Value(Value::Bool(false)),
+                typ: vec![Type::Bool],
+                clock: Clock::Ck(v.clone()),
+            }
+        };
+        v.pop();
+        branches.push((other, branch));
+    }
+    minils::Expr {
+        expr: minils::BaseExpr::Merge(ck, branches),
+        typ: vec![Type::Bool],
+        clock,
     }
 }
 
+/// Define a variable that is true when one of two reset variables is true,
+/// on the slowest of their clocks
+fn combine_resets(
+    ident: &IdentGenerator,
+    reset_1: &str,
+    reset_2: &str,
+    span: Span,
+    node: &mut minils::Node,
+) -> Result<String, Diagnostic> {
+    let clock_1 = var_clock(node, reset_1);
+    let clock_2 = var_clock(node, reset_2);
+    let (Clock::Ck(v_1), Clock::Ck(v_2)) = (&clock_1, &clock_2);
+    let clock = if v_1.len() < v_2.len() {
+        clock_2.clone()
+    } else {
+        clock_1.clone()
+    };
+    let expr_1 = sample_reset(reset_1, &clock, span, node)?;
+    let expr_2 = sample_reset(reset_2, &clock, span, node)?;
+    let name = ident.new_ident().get_ident();
+    let expr = minils::Expr {
+        expr: minils::BaseExpr::BinOp(BinOp::Or, box expr_1, box expr_2),
+        typ: vec![Type::Bool],
+        clock: clock.clone(),
+    };
+    node.eq_list.push((vec![name.clone()], expr));
+    node.local_params.insert(name.clone(), (Type::Bool, clock));
+    Ok(name)
+}
+
 /// Introduce nested when in an expression that has clock base, to match the given clock
 fn nested_when(expr: minils::Expr, clock: Clock) -> minils::Expr {
-    if let Clock::Ck(v) = &expr.clock {
-        if v.len() != 0 {
-            assert!(false);
+    let Clock::Ck(v) = clock;
+    let mut expr = expr;
+    for (ck, value) in v {
+        let Clock::Ck(mut clock) = expr.clock.clone();
+        clock.push((ck.clone(), value.clone()));
+        expr = minils::Expr {
+            expr: minils::BaseExpr::When(box expr.clone(), ck, value),
+            typ: expr.typ,
+            clock: Clock::Ck(clock),
         }
     }
-    match clock {
-        Clock::Const => expr,
-        Clock::Ck(v) => {
-            let mut expr = expr;
-            for (ck, b) in v {
-                let clock = match expr.clock.clone() {
-                    Clock::Const => Clock::Ck(vec![(ck.clone(), b)]),
-                    Clock::Ck(mut v) => {
-                        v.push((ck.clone(), b));
-                        Clock::Ck(v)
-                    }
-                };
-                expr = minils::Expr {
-                    expr: minils::BaseExpr::When(box expr.clone(), ck, b),
-                    typ: expr.typ,
-                    clock: clock,
-                }
-            }
-            expr
-        }
+    expr
+}
+
+/// Get the clock of a variable of a node
+fn var_clock(node: &minils::Node, ident: &str) -> Clock {
+    match node.local_params.get(ident) {
+        Some((_, clock)) => clock.clone(),
+        None => match node.param_clocks.get(ident) {
+            Some(clock) => clock.clone(),
+            None => Clock::Ck(vec![]),
+        },
     }
 }
+
+/// Get the type of a variable of a node
+fn var_type(node: &minils::Node, ident: &str) -> Type {
+    node.in_params
+        .iter()
+        .chain(node.out_params.iter())
+        .find(|(var, _)| var == ident)
+        .map(|(_, typ)| typ.clone())
+        .unwrap_or_else(|| node.local_params.get(ident).unwrap().0.clone())
+}
@@ -1,350 +1,754 @@
-//! Type the clocks of a typed LucyRS node
+//! Infer and check the clocks of typed LucyRS nodes
+//!
+//! The clocks are inferred by unification: the clock of an expression is a
+//! term that can contain clock variables, so that a constant is on any
+//! clock, and so is a variable whose clock is not declared. The clocks of
+//! the inputs and outputs of a node give its signature, relative to the
+//! clock of its calls, and the sampling variables of these clocks are its
+//! inputs, replaced at each call by the arguments given for them. The clock
+//! variables that are still free once a node is typed are its base clock.
 
-use crate::ast::{BinOp, Clock, Type, Value};
+use crate::ast::{Clock, Span, Type, Value};
+use crate::diagnostic::Diagnostic;
 use crate::lucy::clock_typed_ast as ck;
 use crate::lucy::typed_ast as typ;
 
 use std::collections::HashMap;
 
+/// Clocks of the inputs and outputs of a node, relative to the clock of
+/// its calls, and given by its inputs
+struct Signature {
+    inputs: Vec<String>,
+    input_clocks: Vec<Clock>,
+    output_clocks: Vec<Clock>,
+}
+
+impl Signature {
+    /// Check if some inputs or outputs are not on the clock of the calls
+    fn has_sampled_params(&self) -> bool {
+        let mut clocks = self.input_clocks.iter().chain(&self.output_clocks);
+        clocks.any(is_sampled)
+    }
+}
+
+/// Check if a clock is not the base clock
+fn is_sampled(clock: &Clock) -> bool {
+    clock != &Clock::Ck(vec![])
+}
+
+/// Clock of an expression during the inference, which is the base clock of
+/// the node, a clock variable, or a clock sampled by a variable
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Base,
+    Var(usize),
+    On(Box<Term>, String, Value),
+}
+
 /// Annotate and check the validity of clocks in LucyRS typed nodes
-pub fn annotate_clocks(nodes: Vec<typ::Node>) -> Result<Vec<ck::Node>, String> {
+pub fn annotate_clocks(nodes: Vec<typ::Node>) -> Result<Vec<ck::Node>, Vec<Diagnostic>> {
+    // The callers are scheduled before the nodes they call, so the nodes
+    // are typed in reverse order to know the signatures of their callees
+    let mut signatures = HashMap::new();
     let mut clock_nodes = vec![];
-    for node in nodes {
-        clock_nodes.push(annotate_clocks_node(node)?);
+    let mut errors = vec![];
+    for node in nodes.into_iter().rev() {
+        match annotate_clocks_node(node, &signatures) {
+            Ok((node, signature)) => {
+                signatures.insert(node.name.clone(), signature);
+                clock_nodes.push(node);
+            }
+            Err(mut diagnostics) => errors.append(&mut diagnostics),
+        }
+    }
+    if errors.is_empty() {
+        clock_nodes.reverse();
+        Ok(clock_nodes)
+    } else {
+        Err(errors)
     }
-    Ok(clock_nodes)
 }
 
-/// Annotate and check the validity of clocks in a single LucyRS typed node
-fn annotate_clocks_node(node: typ::Node) -> Result<ck::Node, String> {
-    let mut variables = HashMap::new();
-    for (var, typ) in &node.in_params {
-        variables.insert(var.clone(), (typ.clone(), Clock::Ck(vec![])));
+/// Annotate and check the validity of clocks in a single LucyRS typed node,
+/// and give its signature
+fn annotate_clocks_node(
+    node: typ::Node,
+    signatures: &HashMap<String, Signature>,
+) -> Result<(ck::Node, Signature), Vec<Diagnostic>> {
+    let mut inference = Inference::new(signatures);
+    for (vars, expr) in &node.eq_list {
+        if vars.len() == 1 && vars[0].starts_with('_') {
+            inference
+                .descriptions
+                .insert(vars[0].clone(), expr.to_string());
+        }
     }
-    for (var, typ) in &node.out_params {
-        variables.insert(var.clone(), (typ.clone(), Clock::Ck(vec![])));
+    let params: Vec<&String> = node
+        .in_params
+        .iter()
+        .chain(node.out_params.iter())
+        .map(|(var, _)| var)
+        .collect();
+    let mut locals: Vec<&String> = node.local_params.keys().collect();
+    locals.sort();
+    for var in params.iter().chain(locals.iter()) {
+        let clock = inference.fresh();
+        inference.variables.insert(var.to_string(), clock);
     }
-    for (var, (typ, ck)) in &node.local_params {
-        variables.insert(var.clone(), (typ.clone(), ck.clone()));
+
+    let mut errors = vec![];
+    let declared_params = params
+        .iter()
+        .filter_map(|var| node.param_clocks.get(*var).map(|ck| (*var, ck)));
+    let declared_locals = locals
+        .iter()
+        .filter_map(|var| node.local_params[*var].1.as_ref().map(|ck| (*var, ck)));
+    for (var, clock) in declared_params.chain(declared_locals) {
+        if let Err(diagnostic) = inference.declare(var, clock, node.span) {
+            errors.push(diagnostic);
+        }
     }
 
-    let mut eq_list = vec![];
-    for (vars, expr) in node.eq_list {
-        let expr = annotate_expr(expr, &variables)?;
-        for var in &vars {
-            let (typ, ck) = variables.get(var).unwrap();
-            if !Clock::is_compatible(ck, &expr.clock) {
-                return Err(format!(
-                    "Variable {} was declared with a clock {:?}, but its computed clock is {:?}",
-                    var, ck, expr.clock
-                ));
+    let mut roots = vec![];
+    for (vars, expr) in &node.eq_list {
+        let id = inference.clocks.len();
+        if let Err(diagnostic) = inference.infer(expr, true) {
+            errors.push(diagnostic);
+            continue;
+        }
+        roots.push(id);
+        for (var, clock) in vars.iter().zip(inference.equation_clocks(expr, id)) {
+            let var_clock = inference.var(var);
+            let subject = inference.subject(var);
+            let message = |ck_var, ck| {
+                format!(
+                    "{} is on {}, but its definition is on {}",
+                    subject, ck_var, ck
+                )
+            };
+            if let Err(diagnostic) = inference.unify_or(&var_clock, &clock, message, expr.span) {
+                errors.push(diagnostic);
             }
-            variables.insert(var.clone(), (typ.clone(), expr.clock.clone()));
         }
-        eq_list.push((vars, expr));
     }
-    Ok(ck::Node {
+    errors.append(&mut inference.check_resets());
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // The clocks of the inputs and outputs are given by inputs, so that
+    // they are known at each call of the node
+    let inputs: Vec<&String> = node.in_params.iter().map(|(var, _)| var).collect();
+    let mut param_clocks = HashMap::new();
+    for var in params {
+        let clock = inference.to_clock(&inference.variables[var]);
+        let Clock::Ck(v) = &clock;
+        if let Some((ck, _)) = v.iter().find(|(ck, _)| !inputs.contains(&ck)) {
+            errors.push(Diagnostic::new(
+                format!(
+                    "The clock of {} is {}, but {} is not an input of the node",
+                    var,
+                    inference.show(&inference.variables[var]),
+                    inference.name(ck)
+                ),
+                node.span,
+            ));
+        } else if !v.is_empty() {
+            param_clocks.insert(var.clone(), clock);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let clock =
+        |(var, _): &(String, Type)| param_clocks.get(var).cloned().unwrap_or(Clock::Ck(vec![]));
+    let signature = Signature {
+        inputs: node.in_params.iter().map(|(var, _)| var.clone()).collect(),
+        input_clocks: node.in_params.iter().map(clock).collect(),
+        output_clocks: node.out_params.iter().map(clock).collect(),
+    };
+
+    let local_params = node
+        .local_params
+        .into_iter()
+        .map(|(var, (typ, _))| {
+            let clock = inference.to_clock(&inference.variables[&var]);
+            (var, (typ, clock))
+        })
+        .collect();
+    let eq_list = node
+        .eq_list
+        .into_iter()
+        .zip(roots)
+        .map(|((vars, expr), mut next)| (vars, inference.build(expr, &mut next)))
+        .collect();
+    let node = ck::Node {
         name: node.name,
         in_params: node.in_params,
         out_params: node.out_params,
-        local_params: node.local_params,
+        param_clocks,
+        local_params,
         eq_list,
-    })
+        assertions: node.assertions,
+        span: node.span,
+    };
+    Ok((node, signature))
 }
 
-/// Annotate the clocks for an expression
-fn annotate_expr(
-    expr: typ::Expr,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<ck::Expr, String> {
-    let typ = expr.typ;
-    let (expr, clock) = match expr.expr {
-        typ::BaseExpr::Value(v) => (ck::BaseExpr::Value(v), Clock::Const),
-        typ::BaseExpr::UnOp(op, box e) => {
-            let e = annotate_expr(e, vars)?;
-            let clock = e.clock.clone();
-            (ck::BaseExpr::UnOp(op, box e), clock)
-        }
-        typ::BaseExpr::BinOp(op, box e1, box e2) => annotate_binop(op, e1, e2, vars)?,
-        typ::BaseExpr::When(box e, s, b) => annotate_when(e, s, b, vars)?,
-        typ::BaseExpr::Merge(ck, box e_t, box e_f) => annotate_merge(ck, e_t, e_f, vars)?,
-        typ::BaseExpr::Fby(v, box e) => {
-            let e = annotate_expr(e, vars)?;
-            let clock = e.clock.clone();
-            (ck::BaseExpr::Fby(v, box e), clock)
-        }
-        typ::BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
-            annotate_ifthenelse(cond, e_t, e_f, vars)?
-        }
-        typ::BaseExpr::Var(s) => annotate_var(s, vars),
-        typ::BaseExpr::FunCall(s, exprs, ck) => annotate_funcall(s, exprs, ck, vars)?,
-        typ::BaseExpr::Current(s, v) => annotate_current(s, v, vars),
-        typ::BaseExpr::Pre(box e) => {
-            let e = annotate_expr(e, vars)?;
-            let clock = e.clock.clone();
-            (ck::BaseExpr::Pre(box e), clock)
-        }
-        typ::BaseExpr::Arrow(exprs) => annotate_arrow(exprs, vars)?,
-    };
-    Ok(ck::Expr { expr, typ, clock })
+const ARRAY_CLOCK_ERROR: &str = "The expressions of an array construct should have the same clock";
+const RECORD_CLOCK_ERROR: &str = "The expressions of a record construct should have the same clock";
+
+/// State of the inference of the clocks of a node
+struct Inference<'a> {
+    signatures: &'a HashMap<String, Signature>,
+    /// Clocks of the variables of the node
+    variables: HashMap<String, Term>,
+    /// Clock variables, with the clock they are bound to
+    bindings: Vec<Option<Term>>,
+    /// Clocks of the expressions, in the order they are visited
+    clocks: Vec<Term>,
+    /// Clocks of the outputs of the node calls, given by the index of the call
+    outputs: HashMap<usize, Vec<Term>>,
+    /// Reset variables, with the clock of the expression they reset, which
+    /// are checked once the clocks are known
+    resets: Vec<(String, Term, Span)>,
+    /// Expressions defining the generated variables, whose names start with
+    /// _, used in the messages instead of their names
+    descriptions: HashMap<String, String>,
 }
 
-fn annotate_binop(
-    op: BinOp,
-    e1: typ::Expr,
-    e2: typ::Expr,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let mut e1 = annotate_expr(e1, vars)?;
-    let mut e2 = annotate_expr(e2, vars)?;
-    let clock1 = e1.clock.clone();
-    let clock2 = e2.clock.clone();
-    if !Clock::is_compatible(&e1.clock, &e2.clock) {
-        return Err(String::from(
-            "The two expressions have incompatible clocks in the binary operation",
-        ));
+impl<'a> Inference<'a> {
+    fn new(signatures: &'a HashMap<String, Signature>) -> Inference<'a> {
+        Inference {
+            signatures,
+            variables: HashMap::new(),
+            bindings: vec![],
+            clocks: vec![],
+            outputs: HashMap::new(),
+            resets: vec![],
+            descriptions: HashMap::new(),
+        }
     }
-    lower_clock(&mut e1, &clock1);
-    lower_clock(&mut e2, &clock2);
-    let clock = e1.clock.clone();
-    Ok((ck::BaseExpr::BinOp(op, box e1, box e2), clock))
-}
 
-fn annotate_when(
-    e: typ::Expr,
-    s: String,
-    b: bool,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let mut e = annotate_expr(e, vars)?;
-    if e.clock == Clock::Const {
-        lower_clock(&mut e, &Clock::Ck(vec![]));
+    /// Get a new clock variable
+    fn fresh(&mut self) -> Term {
+        self.bindings.push(None);
+        Term::Var(self.bindings.len() - 1)
     }
-    let clock = match e.clock.clone() {
-        Clock::Const => unreachable!(),
-        Clock::Ck(mut v) => {
-            if let Some((ck, _)) = v.last() {
-                if ck != &s {
-                    v.push((s.clone(), b));
-                }
-            } else {
-                v.push((s.clone(), b));
+
+    /// Get the clock of a variable of the node
+    fn var(&mut self, var: &str) -> Term {
+        match self.variables.get(var) {
+            Some(clock) => clock.clone(),
+            None => {
+                let clock = self.fresh();
+                self.variables.insert(var.to_owned(), clock.clone());
+                clock
             }
-            Clock::Ck(v)
         }
-    };
-    Ok((ck::BaseExpr::When(box e, s, b), clock))
-}
+    }
 
-fn annotate_merge(
-    ck: String,
-    e_t: typ::Expr,
-    e_f: typ::Expr,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let e_t = annotate_expr(e_t, vars)?;
-    let e_f = annotate_expr(e_f, vars)?;
-    let mut v_t = match &e_t.clock {
-        Clock::Const => {
-            return Err(String::from(
-                "Left expression in a merge should have a clock on true(merge clock)",
-            ));
+    /// Follow the bindings of a clock variable
+    fn resolve(&self, term: &Term) -> Term {
+        let mut term = term.clone();
+        while let Term::Var(i) = term {
+            match &self.bindings[i] {
+                Some(bound) => term = bound.clone(),
+                None => break,
+            }
         }
-        Clock::Ck(v) => {
-            if let Some((ck_, true)) = v.last() {
-                if ck_ != &ck {
-                    return Err(String::from(
-                        "Left expression in a merge should have a clock on true(merge clock)",
-                    ));
+        term
+    }
+
+    /// Check if a clock variable occurs in a clock
+    fn occurs(&self, i: usize, term: &Term) -> bool {
+        match self.resolve(term) {
+            Term::Base => false,
+            Term::Var(j) => i == j,
+            Term::On(box term, _, _) => self.occurs(i, &term),
+        }
+    }
+
+    /// Unify two clocks, binding their clock variables so that they are equal
+    fn unify(&mut self, term1: &Term, term2: &Term) -> bool {
+        match (self.resolve(term1), self.resolve(term2)) {
+            (Term::Var(i), Term::Var(j)) if i == j => true,
+            (Term::Var(i), term) | (term, Term::Var(i)) => {
+                if self.occurs(i, &term) {
+                    false
+                } else {
+                    self.bindings[i] = Some(term);
+                    true
                 }
-                v.clone()
-            } else {
-                return Err(String::from(
-                    "Left expression in a merge should have a clock on true(merge clock)",
-                ));
             }
+            (Term::Base, Term::Base) => true,
+            (Term::On(box term1, ck1, value1), Term::On(box term2, ck2, value2)) => {
+                ck1 == ck2 && value1 == value2 && self.unify(&term1, &term2)
+            }
+            _ => false,
         }
-    };
-    let mut v_f = match &e_f.clock {
-        Clock::Const => {
-            return Err(String::from(
-                "Right expression in a merge should have a clock on false(merge clock)",
-            ));
+    }
+
+    /// Unify two clocks, or give an error whose message is built from the
+    /// two clocks, in which case the bindings are left unchanged
+    fn unify_or<F>(
+        &mut self,
+        term1: &Term,
+        term2: &Term,
+        message: F,
+        span: Span,
+    ) -> Result<(), Diagnostic>
+    where
+        F: FnOnce(String, String) -> String,
+    {
+        let bindings = self.bindings.clone();
+        if self.unify(term1, term2) {
+            Ok(())
+        } else {
+            self.bindings = bindings;
+            let message = message(self.show(term1), self.show(term2));
+            Err(Diagnostic::new(message, span))
         }
-        Clock::Ck(v) => {
-            if let Some((ck_, false)) = v.last() {
-                if ck_ != &ck {
-                    return Err(String::from(
-                        "Left expression in a merge should have a clock on false(merge clock)",
-                    ));
-                }
-                v.clone()
-            } else {
-                return Err(String::from(
-                    "Right expression in a merge should have a clock on false(merge clock)",
-                ));
+    }
+
+    /// Get the free clock variable a clock is sampled from, if it is not
+    /// the base clock, with the sampling variables
+    fn flatten(&self, term: &Term) -> (Option<usize>, Vec<(String, Value)>) {
+        match self.resolve(term) {
+            Term::Base => (None, vec![]),
+            Term::Var(i) => (Some(i), vec![]),
+            Term::On(box term, ck, value) => {
+                let (root, mut v) = self.flatten(&term);
+                v.push((ck, value));
+                (root, v)
             }
         }
-    };
-    v_t.pop();
-    v_f.pop();
-    if v_t != v_f {
-        return Err(String::from("Both expressions in a merge construct should have the same clock (modulo the merge clock)"));
     }
-    let ck_clock = &vars.get(&ck).unwrap().1;
-    if !Clock::is_compatible(ck_clock, &Clock::Ck(v_f)) {
-        return Err(String::from(
-            "Expressions in merge construct should have clock compatible with the merge clock.",
-        ));
+
+    /// Get the clock of an expression, the free clock variables being the
+    /// base clock
+    fn to_clock(&self, term: &Term) -> Clock {
+        Clock::Ck(self.flatten(term).1)
     }
-    Ok((ck::BaseExpr::Merge(ck, box e_t, box e_f), Clock::Ck(v_t)))
-}
 
-fn annotate_ifthenelse(
-    cond: typ::Expr,
-    e_t: typ::Expr,
-    e_f: typ::Expr,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let mut cond = annotate_expr(cond, vars)?;
-    let mut e_t = annotate_expr(e_t, vars)?;
-    let mut e_f = annotate_expr(e_f, vars)?;
-    if !Clock::is_compatible(&e_t.clock, &cond.clock)
-        || !Clock::is_compatible(&e_f.clock, &cond.clock)
-    {
-        return Err(String::from(
-            "Expressions in a if construct should have compatible clocks",
-        ));
+    /// Write a clock for the error messages, the free clock variables being
+    /// the base clock, and the generated variables being replaced by their
+    /// expressions
+    fn show(&self, term: &Term) -> String {
+        let (_, v) = self.flatten(term);
+        let v = v
+            .into_iter()
+            .map(|(ck, value)| match self.descriptions.get(&ck) {
+                Some(description) => (format!("({})", description), value),
+                None => (ck, value),
+            })
+            .collect();
+        Clock::Ck(v).to_string()
     }
-    lower_clock(&mut cond, &e_t.clock);
-    lower_clock(&mut cond, &e_f.clock);
-    lower_clock(&mut e_t, &cond.clock);
-    lower_clock(&mut e_t, &e_f.clock);
-    lower_clock(&mut e_f, &cond.clock);
-    lower_clock(&mut e_t, &e_f.clock);
-    let clock = cond.clock.clone();
-    Ok((ck::BaseExpr::IfThenElse(box cond, box e_t, box e_f), clock))
-}
 
-fn annotate_var(var: String, vars: &HashMap<String, (Type, Clock)>) -> (ck::BaseExpr, Clock) {
-    match vars.get(&var) {
-        None => (ck::BaseExpr::Var(var), Clock::Ck(vec![])),
-        Some(ck) => (ck::BaseExpr::Var(var), ck.1.clone()),
+    /// Name a variable in the messages, by its expression if it is generated
+    fn name(&self, var: &str) -> String {
+        match self.descriptions.get(var) {
+            Some(description) => description.clone(),
+            None => var.to_owned(),
+        }
     }
-}
 
-fn annotate_funcall(
-    fun: String,
-    exprs: Vec<typ::Expr>,
-    reset: Option<String>,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let mut exprs_ = vec![];
-    for expr in exprs {
-        exprs_.push(annotate_expr(expr, vars)?);
+    /// Name a variable at the start of the messages
+    fn subject(&self, var: &str) -> String {
+        match self.descriptions.get(var) {
+            Some(description) => format!("The expression {}", description),
+            None => format!("The variable {}", var),
+        }
     }
-    let clock = exprs_[0].clock.clone();
-    for i in 0..exprs_.len() {
-        for j in 0..exprs_.len() {
-            if !Clock::is_compatible(&exprs_[i].clock, &exprs_[j].clock) {
-                return Err(String::from(
-                    "Parameters of node call should have the same clock",
-                ));
-            }
-            let clock_j = exprs_[j].clock.clone();
-            let clock_i = exprs_[i].clock.clone();
-            lower_clock(&mut exprs_[i], &clock_j);
-            lower_clock(&mut exprs_[j], &clock_i);
+
+    /// Give a variable its declared clock `when c1 ... when cn`, which is
+    /// sampled from the clock of c1, each ci being on the clock sampled by
+    /// the previous ones
+    fn declare(&mut self, var: &str, clock: &Clock, span: Span) -> Result<(), Diagnostic> {
+        let Clock::Ck(v) = clock;
+        let mut term = match v.first() {
+            Some((ck, _)) => self.var(ck),
+            None => Term::Base,
+        };
+        for (ck, value) in v {
+            let ck_clock = self.var(ck);
+            let name = self.name(var);
+            let message = |ck_clock, clock| {
+                format!(
+                    "The clock of {} uses {}, which is on {}, but it should be on {}",
+                    name, ck, ck_clock, clock
+                )
+            };
+            self.unify_or(&ck_clock, &term, message, span)?;
+            term = Term::On(box term, ck.clone(), value.clone());
         }
+        let var_clock = self.var(var);
+        let subject = self.subject(var);
+        let message = |ck_var, clock| {
+            format!(
+                "{} is on {}, but it is declared on {}",
+                subject, ck_var, clock
+            )
+        };
+        self.unify_or(&var_clock, &term, message, span)
     }
-    if let Some(reset) = reset.clone() {
-        let reset_clock = &vars.get(&reset).unwrap().1;
-        if !reset_clock.is_faster_or_equal_than(&clock) {
-            return Err(String::from(
-                "Reset clock should be faster or equal than the clock of a node call",
-            ));
+
+    /// Infer the clock of an expression, which is the clock of the call for
+    /// a node call. At the root of an equation, possibly under a reset, it
+    /// can be the call of a node whose outputs are not on the clock of the
+    /// call.
+    /// The clocks of the expression and of its subexpressions are recorded
+    /// in prefix order, to build the annotated expression.
+    fn infer(&mut self, expr: &typ::Expr, root: bool) -> Result<Term, Diagnostic> {
+        let id = self.clocks.len();
+        self.clocks.push(Term::Base);
+        let span = expr.span;
+        let clock = match &expr.expr {
+            typ::BaseExpr::Value(_) => self.fresh(),
+            typ::BaseExpr::Var(s) => self.var(s),
+            typ::BaseExpr::UnOp(_, box e)
+            | typ::BaseExpr::Pre(box e)
+            | typ::BaseExpr::Slice(box e, _, _)
+            | typ::BaseExpr::Field(box e, _) => self.infer(e, false)?,
+            typ::BaseExpr::BinOp(_, box e1, box e2) => {
+                let message = "The expressions of a binary operation should have the same clock";
+                self.same_clock(vec![e1, e2], message, span)?
+            }
+            typ::BaseExpr::Fby(box e1, box e2) => {
+                let message = "The expressions of a fby should have the same clock";
+                self.same_clock(vec![e1, e2], message, span)?
+            }
+            typ::BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
+                let message = "The expressions of an if construct should have the same clock";
+                self.same_clock(vec![cond, e_t, e_f], message, span)?
+            }
+            typ::BaseExpr::Arrow(exprs) => {
+                let message = "The expressions of an arrow construct should have the same clock";
+                self.same_clock(exprs.iter().collect(), message, span)?
+            }
+            typ::BaseExpr::Array(exprs) => {
+                self.same_clock(exprs.iter().collect(), ARRAY_CLOCK_ERROR, span)?
+            }
+            typ::BaseExpr::Tuple(exprs) => {
+                let message = "The expressions of a tuple should have the same clock";
+                self.same_clock(exprs.iter().collect(), message, span)?
+            }
+            typ::BaseExpr::Index(box e1, box e2) | typ::BaseExpr::Concat(box e1, box e2) => {
+                self.same_clock(vec![e1, e2], ARRAY_CLOCK_ERROR, span)?
+            }
+            typ::BaseExpr::Record(_, fields) => {
+                let exprs = fields.iter().map(|(_, e)| e).collect();
+                self.same_clock(exprs, RECORD_CLOCK_ERROR, span)?
+            }
+            typ::BaseExpr::With(box e, fields) => {
+                let exprs = Some(e).into_iter().chain(fields.iter().map(|(_, e)| e));
+                self.same_clock(exprs.collect(), RECORD_CLOCK_ERROR, span)?
+            }
+            typ::BaseExpr::When(box e, s, value) => {
+                let clock = self.infer(e, false)?;
+                let ck_clock = self.var(s);
+                let name = self.name(s);
+                let message = |ck_clock, clock| {
+                    format!(
+                        "The clock {} is on {}, but the expression sampled on it is on {}",
+                        name, ck_clock, clock
+                    )
+                };
+                self.unify_or(&ck_clock, &clock, message, span)?;
+                Term::On(box clock, s.clone(), value.clone())
+            }
+            typ::BaseExpr::Merge(s, branches) => {
+                let clock = self.var(s);
+                for (value, e) in branches {
+                    let e_clock = self.infer(e, false)?;
+                    let sampled = Term::On(box clock.clone(), s.clone(), value.clone());
+                    let message = |e_clock, sampled| {
+                        format!(
+                            "The branch {} of a merge should be on {}, but it is on {}",
+                            value, sampled, e_clock
+                        )
+                    };
+                    self.unify_or(&e_clock, &sampled, message, e.span)?;
+                }
+                clock
+            }
+            typ::BaseExpr::Current(s, _) => {
+                self.var(s);
+                Term::Base
+            }
+            typ::BaseExpr::FunCall(fun, exprs, reset) => {
+                self.infer_funcall(id, fun, exprs, reset, expr.typ.len(), root, span)?
+            }
+            typ::BaseExpr::Iterate(_, fun, _, exprs) => {
+                let signature = self.signatures.get(fun);
+                if signature.map_or(false, Signature::has_sampled_params) {
+                    return Err(Diagnostic::new(
+                        format!(
+                            "Node {} has inputs or outputs on a clock, it cannot be iterated",
+                            fun
+                        ),
+                        span,
+                    ));
+                }
+                let message = "Parameters of an iterator should have the same clock";
+                self.same_clock(exprs.iter().collect(), message, span)?
+            }
+            typ::BaseExpr::Reset(box e, r) => {
+                let clock = self.infer(e, root)?;
+                self.resets.push((r.clone(), clock.clone(), span));
+                clock
+            }
+        };
+        self.clocks[id] = clock.clone();
+        Ok(clock)
+    }
+
+    /// Infer the clocks of expressions that should be on the same clock,
+    /// and return this clock
+    fn same_clock(
+        &mut self,
+        exprs: Vec<&typ::Expr>,
+        message: &str,
+        span: Span,
+    ) -> Result<Term, Diagnostic> {
+        let clock = self.fresh();
+        for expr in exprs {
+            let e_clock = self.infer(expr, false)?;
+            let message = |clock, e_clock| format!("{}, found {} and {}", message, clock, e_clock);
+            self.unify_or(&clock, &e_clock, message, span)?;
         }
+        Ok(clock)
     }
-    Ok((ck::BaseExpr::FunCall(fun, exprs_, reset), clock))
-}
 
-fn annotate_current(
-    ident: String,
-    value: Value,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> (ck::BaseExpr, Clock) {
-    match &vars.get(&ident).unwrap().1 {
-        Clock::Const => (ck::BaseExpr::Var(ident), Clock::Const),
-        Clock::Ck(hm) => {
-            if hm.len() == 0 {
-                (ck::BaseExpr::Var(ident), Clock::Ck(vec![]))
-            } else {
-                (ck::BaseExpr::Current(ident, value), Clock::Ck(vec![]))
+    /// Infer the clock of a node call. For a node with a signature, the
+    /// clocks of the arguments are the clocks of its inputs, with the
+    /// arguments given for their clock variables, on the clock of the call
+    fn infer_funcall(
+        &mut self,
+        id: usize,
+        fun: &str,
+        exprs: &[typ::Expr],
+        reset: &Option<String>,
+        n_outputs: usize,
+        root: bool,
+        span: Span,
+    ) -> Result<Term, Diagnostic> {
+        let mut expr_clocks = vec![];
+        for expr in exprs {
+            expr_clocks.push(self.infer(expr, false)?);
+        }
+        let clock = self.fresh();
+        let signatures = self.signatures;
+        let outputs = match signatures.get(fun) {
+            None => {
+                for expr_clock in &expr_clocks {
+                    let message = |clock, expr_clock| {
+                        format!(
+                            "Parameters of node call should have the same clock, found {} and {}",
+                            clock, expr_clock
+                        )
+                    };
+                    self.unify_or(&clock, expr_clock, message, span)?;
+                }
+                vec![clock.clone(); n_outputs]
             }
+            Some(signature) => {
+                let mut arguments = HashMap::new();
+                for (input, expr) in signature.inputs.iter().zip(exprs) {
+                    if let typ::BaseExpr::Var(var) = &expr.expr {
+                        arguments.insert(input, var);
+                    }
+                }
+                let instantiate = |sig_clock: &Clock| {
+                    let Clock::Ck(v) = sig_clock;
+                    let mut term = clock.clone();
+                    for (ck, value) in v {
+                        match arguments.get(ck) {
+                            Some(var) => term = Term::On(box term, var.to_string(), value.clone()),
+                            None => {
+                                return Err(Diagnostic::new(
+                                    format!(
+                                        "The clock {} of {} should be given as a variable",
+                                        ck, fun
+                                    ),
+                                    span,
+                                ))
+                            }
+                        }
+                    }
+                    Ok(term)
+                };
+                let inputs = signature.inputs.iter().zip(&signature.input_clocks);
+                for ((input, input_clock), (expr, expr_clock)) in
+                    inputs.zip(exprs.iter().zip(&expr_clocks))
+                {
+                    let input_clock = instantiate(input_clock)?;
+                    let message = |expr_clock, input_clock| {
+                        format!(
+                            "The input {} of {} should be on {}, but it is on {}",
+                            input, fun, input_clock, expr_clock
+                        )
+                    };
+                    self.unify_or(expr_clock, &input_clock, message, expr.span)?;
+                }
+                if !root && signature.output_clocks.iter().any(is_sampled) {
+                    return Err(Diagnostic::new(
+                        format!(
+                            "The outputs of {} are not on the clock of the call, it should be called at the root of an equation",
+                            fun
+                        ),
+                        span,
+                    ));
+                }
+                let outputs = signature.output_clocks.iter().map(instantiate);
+                outputs.collect::<Result<_, _>>()?
+            }
+        };
+        if let Some(reset) = reset {
+            self.resets.push((reset.clone(), clock.clone(), span));
         }
+        self.outputs.insert(id, outputs);
+        Ok(clock)
     }
-}
 
-fn annotate_arrow(
-    exprs: Vec<typ::Expr>,
-    vars: &HashMap<String, (Type, Clock)>,
-) -> Result<(ck::BaseExpr, Clock), String> {
-    let mut annotated_exprs = vec![];
-    for expr in exprs {
-        annotated_exprs.push(annotate_expr(expr, vars)?);
+    /// Get the clocks of the values of the expression of an equation, which
+    /// are the clocks of the outputs for a node call
+    fn equation_clocks(&self, expr: &typ::Expr, id: usize) -> Vec<Term> {
+        match &expr.expr {
+            typ::BaseExpr::FunCall(_, _, _) => self.outputs[&id].clone(),
+            typ::BaseExpr::Reset(box e, _) => self.equation_clocks(e, id + 1),
+            _ => vec![self.clocks[id].clone(); expr.typ.len()],
+        }
     }
-    let n = annotated_exprs.len();
-    for i in 0..n {
-        for j in 0..i {
-            if !Clock::is_compatible(&annotated_exprs[i].clock, &annotated_exprs[j].clock) {
-                return Err(String::from(
-                    "The clocks of the expressions in an arrow construct should be the same",
+
+    /// Check that the reset variables are on the clock of the expressions
+    /// they reset, or on a faster clock. An expression whose clock is free,
+    /// such as a call with constant arguments, is put on the clock of its
+    /// reset variable.
+    fn check_resets(&mut self) -> Vec<Diagnostic> {
+        let mut errors = vec![];
+        for (reset, clock, span) in std::mem::replace(&mut self.resets, vec![]) {
+            let reset_clock = self.var(&reset);
+            if let Term::Var(_) = self.resolve(&clock) {
+                self.unify(&clock, &reset_clock);
+            }
+            if !self
+                .to_clock(&reset_clock)
+                .is_faster_or_equal_than(&self.to_clock(&clock))
+            {
+                errors.push(Diagnostic::new(
+                    format!(
+                        "{} used as a reset is on {}, it should be faster or equal than the clock {} of the expression it resets",
+                        self.subject(&reset),
+                        self.show(&reset_clock),
+                        self.show(&clock)
+                    ),
+                    span,
                 ));
             }
-            let clock_i = annotated_exprs[i].clock.clone();
-            let clock_j = annotated_exprs[j].clock.clone();
-            lower_clock(&mut annotated_exprs[i], &clock_j);
-            lower_clock(&mut annotated_exprs[j], &clock_i);
         }
+        errors
     }
-    let clock = annotated_exprs[0].clock.clone();
-    Ok((ck::BaseExpr::Arrow(annotated_exprs), clock))
-}
 
-/// Lower clocks of an expression. This means that if the clock was Const,
-/// It is changed to the given clock.
-fn lower_clock(expr: &mut ck::Expr, clock: &Clock) {
-    if let Clock::Const = clock {
-        return;
-    }
-    if let Clock::Ck(_) = &expr.clock {
-        assert!(&expr.clock == clock);
-        return;
-    }
-    expr.clock = clock.clone();
-    match &mut expr.expr {
-        ck::BaseExpr::Value(_) | ck::BaseExpr::Var(_) | ck::BaseExpr::Current(_, _) => (),
-        ck::BaseExpr::When(_, _, _) => unreachable!(),
-        ck::BaseExpr::Pre(_) => unreachable!(),
-        ck::BaseExpr::Arrow(exprs) => {
-            for expr in exprs {
-                lower_clock(expr, clock);
+    /// Build the expression annotated with the inferred clocks, whose first
+    /// clock is given by next
+    fn build(&self, expr: typ::Expr, next: &mut usize) -> ck::Expr {
+        let id = *next;
+        *next += 1;
+        let typ::Expr { expr, typ, span } = expr;
+        let expr = match expr {
+            typ::BaseExpr::Value(v) => ck::BaseExpr::Value(v),
+            typ::BaseExpr::Var(s) => ck::BaseExpr::Var(s),
+            typ::BaseExpr::UnOp(op, box e) => ck::BaseExpr::UnOp(op, box self.build(e, next)),
+            typ::BaseExpr::BinOp(op, box e1, box e2) => {
+                let e1 = self.build(e1, next);
+                let e2 = self.build(e2, next);
+                ck::BaseExpr::BinOp(op, box e1, box e2)
             }
+            typ::BaseExpr::When(box e, s, value) => {
+                ck::BaseExpr::When(box self.build(e, next), s, value)
+            }
+            typ::BaseExpr::Merge(s, branches) => {
+                let branches = branches
+                    .into_iter()
+                    .map(|(value, e)| (value, self.build(e, next)))
+                    .collect();
+                ck::BaseExpr::Merge(s, branches)
+            }
+            typ::BaseExpr::Fby(box e1, box e2) => {
+                let e1 = self.build(e1, next);
+                let e2 = self.build(e2, next);
+                ck::BaseExpr::Fby(box e1, box e2)
+            }
+            typ::BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
+                let cond = self.build(cond, next);
+                let e_t = self.build(e_t, next);
+                let e_f = self.build(e_f, next);
+                ck::BaseExpr::IfThenElse(box cond, box e_t, box e_f)
+            }
+            typ::BaseExpr::FunCall(fun, exprs, reset) => {
+                let exprs = self.build_all(exprs, next);
+                let outputs = self.outputs[&id].iter().map(|t| self.to_clock(t));
+                ck::BaseExpr::FunCall(fun, exprs, reset, outputs.collect())
+            }
+            typ::BaseExpr::Iterate(kind, fun, n, exprs) => {
+                ck::BaseExpr::Iterate(kind, fun, n, self.build_all(exprs, next))
+            }
+            typ::BaseExpr::Current(s, v) => {
+                if self.to_clock(&self.variables[&s]) == Clock::Ck(vec![]) {
+                    ck::BaseExpr::Var(s)
+                } else {
+                    ck::BaseExpr::Current(s, v)
+                }
+            }
+            typ::BaseExpr::Pre(box e) => ck::BaseExpr::Pre(box self.build(e, next)),
+            typ::BaseExpr::Arrow(exprs) => ck::BaseExpr::Arrow(self.build_all(exprs, next)),
+            typ::BaseExpr::Array(exprs) => ck::BaseExpr::Array(self.build_all(exprs, next)),
+            typ::BaseExpr::Tuple(exprs) => ck::BaseExpr::Tuple(self.build_all(exprs, next)),
+            typ::BaseExpr::Index(box e, box i) => {
+                let e = self.build(e, next);
+                let i = self.build(i, next);
+                ck::BaseExpr::Index(box e, box i)
+            }
+            typ::BaseExpr::Slice(box e, i, j) => ck::BaseExpr::Slice(box self.build(e, next), i, j),
+            typ::BaseExpr::Concat(box e1, box e2) => {
+                let e1 = self.build(e1, next);
+                let e2 = self.build(e2, next);
+                ck::BaseExpr::Concat(box e1, box e2)
+            }
+            typ::BaseExpr::Record(record, fields) => {
+                ck::BaseExpr::Record(record, self.build_fields(fields, next))
+            }
+            typ::BaseExpr::Field(box e, field) => {
+                ck::BaseExpr::Field(box self.build(e, next), field)
+            }
+            typ::BaseExpr::With(box e, fields) => {
+                let e = self.build(e, next);
+                ck::BaseExpr::With(box e, self.build_fields(fields, next))
+            }
+            typ::BaseExpr::Reset(box e, r) => ck::BaseExpr::Reset(box self.build(e, next), r),
+        };
+        ck::Expr {
+            expr,
+            typ,
+            clock: self.to_clock(&self.clocks[id]),
+            span,
         }
-        ck::BaseExpr::UnOp(_, box e) => lower_clock(e, clock),
-        ck::BaseExpr::BinOp(_, box e1, box e2) => {
-            lower_clock(e1, clock);
-            lower_clock(e2, clock);
-        }
-        ck::BaseExpr::Merge(_, _, _) => unreachable!(),
-        ck::BaseExpr::Fby(_, box e) => lower_clock(e, clock),
-        ck::BaseExpr::IfThenElse(box e1, box e2, box e3) => {
-            lower_clock(e1, clock);
-            lower_clock(e2, clock);
-            lower_clock(e3, clock);
-        }
-        ck::BaseExpr::FunCall(_, v, _) => {
-            v.iter_mut().for_each(|e| lower_clock(e, clock));
-        }
+    }
+
+    fn build_all(&self, exprs: Vec<typ::Expr>, next: &mut usize) -> Vec<ck::Expr> {
+        exprs.into_iter().map(|e| self.build(e, next)).collect()
+    }
+
+    fn build_fields(
+        &self,
+        fields: Vec<(String, typ::Expr)>,
+        next: &mut usize,
+    ) -> Vec<(String, ck::Expr)> {
+        fields
+            .into_iter()
+            .map(|(field, e)| (field, self.build(e, next)))
+            .collect()
     }
 }
@@ -1,22 +1,29 @@
 //! AST for typed LucyRS without clock annotations
 
-use crate::ast::{BinOp, Type, UnOp, Value, Clock};
+use crate::ast::{Assertion, BinOp, Clock, IteratorKind, Span, Type, UnOp, Value};
 
 use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
     pub in_params: Vec<(String, Type)>,
     pub out_params: Vec<(String, Type)>,
-    pub local_params: HashMap<String, (Type, Clock)>,
+    /// Declared clocks of the inputs and outputs
+    pub param_clocks: HashMap<String, Clock>,
+    /// Local variables, with their declared clock
+    pub local_params: HashMap<String, (Type, Option<Clock>)>,
     pub eq_list: Vec<(Vec<String>, Expr)>,
+    pub assertions: Vec<Assertion>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Expr {
     pub expr: BaseExpr,
     pub typ: Vec<Type>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -24,13 +31,127 @@ pub enum BaseExpr {
     Value(Value),
     UnOp(UnOp, Box<Expr>),
     BinOp(BinOp, Box<Expr>, Box<Expr>),
-    When(Box<Expr>, String, bool),
-    Merge(String, Box<Expr>, Box<Expr>),
-    Fby(Value, Box<Expr>),
+    When(Box<Expr>, String, Value),
+    Merge(String, Vec<(Value, Expr)>),
+    Fby(Box<Expr>, Box<Expr>),
     IfThenElse(Box<Expr>, Box<Expr>, Box<Expr>),
     Var(String),
     FunCall(String, Vec<Expr>, Option<String>),
+    Iterate(IteratorKind, String, usize, Vec<Expr>),
     Current(String, Value),
     Pre(Box<Expr>),
     Arrow(Vec<Expr>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, usize, usize),
+    Concat(Box<Expr>, Box<Expr>),
+    Record(String, Vec<(String, Expr)>),
+    Field(Box<Expr>, String),
+    With(Box<Expr>, Vec<(String, Expr)>),
+    Reset(Box<Expr>, String),
+    Tuple(Vec<Expr>),
+}
+
+/// Write the expressions as they are written in the source, for the error
+/// messages. The operands which are not atomic are parenthesized
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |exprs: &Vec<Expr>, sep: &str| {
+            let exprs: Vec<String> = exprs.iter().map(Expr::to_string).collect();
+            exprs.join(sep)
+        };
+        match &self.expr {
+            BaseExpr::Value(v) => write!(f, "{}", v),
+            BaseExpr::Var(s) => write!(f, "{}", s),
+            BaseExpr::UnOp(op, box e) => write!(f, "{}{}", op, Operand(e)),
+            BaseExpr::BinOp(op, box e1, box e2) => {
+                write!(f, "{} {} {}", Operand(e1), op, Operand(e2))
+            }
+            BaseExpr::When(box e, ck, Value::Bool(true)) => {
+                write!(f, "{} when {}", Operand(e), ck)
+            }
+            BaseExpr::When(box e, ck, Value::Bool(false)) => {
+                write!(f, "{} whenot {}", Operand(e), ck)
+            }
+            BaseExpr::When(box e, ck, value) => {
+                write!(f, "{} when {} = {}", Operand(e), ck, value)
+            }
+            BaseExpr::Merge(ck, branches) => {
+                write!(f, "merge {}", ck)?;
+                for (value, e) in branches {
+                    write!(f, " ({} -> {})", value, e)?;
+                }
+                Ok(())
+            }
+            BaseExpr::Fby(box e1, box e2) => {
+                write!(f, "{} fby {}", Operand(e1), Operand(e2))
+            }
+            BaseExpr::IfThenElse(box cond, box e_t, box e_f) => {
+                write!(f, "if {} then {} else {}", cond, e_t, e_f)
+            }
+            BaseExpr::FunCall(fun, exprs, reset) => {
+                write!(f, "{}({})", fun, join(exprs, ", "))?;
+                match reset {
+                    Some(reset) => write!(f, " every {}", reset),
+                    None => Ok(()),
+                }
+            }
+            BaseExpr::Iterate(kind, fun, n, exprs) => {
+                write!(f, "{}<<{}, {}>>({})", kind, fun, n, join(exprs, ", "))
+            }
+            BaseExpr::Current(s, value) => write!(f, "current {} {}", s, value),
+            BaseExpr::Pre(box e) => write!(f, "pre {}", Operand(e)),
+            BaseExpr::Arrow(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|e| Operand(e).to_string()).collect();
+                write!(f, "{}", exprs.join(" -> "))
+            }
+            BaseExpr::Array(exprs) => write!(f, "[{}]", join(exprs, ", ")),
+            BaseExpr::Index(box e, box i) => write!(f, "{}[{}]", Operand(e), i),
+            BaseExpr::Slice(box e, i, j) => write!(f, "{}[{}..{}]", Operand(e), i, j),
+            BaseExpr::Concat(box e1, box e2) => {
+                write!(f, "{} | {}", Operand(e1), Operand(e2))
+            }
+            BaseExpr::Record(_, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, e)| format!("{} = {}", field, e))
+                    .collect();
+                write!(f, "{{{}}}", fields.join("; "))
+            }
+            BaseExpr::Field(box e, field) => write!(f, "{}.{}", Operand(e), field),
+            BaseExpr::With(box e, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, e)| format!("{} = {}", field, e))
+                    .collect();
+                write!(f, "{{{} with {}}}", e, fields.join("; "))
+            }
+            BaseExpr::Reset(box e, _) => write!(f, "{}", e),
+            BaseExpr::Tuple(exprs) => write!(f, "({})", join(exprs, ", ")),
+        }
+    }
+}
+
+/// An expression used as an operand, which is parenthesized unless it is
+/// atomic
+struct Operand<'a>(&'a Expr);
+
+impl<'a> fmt::Display for Operand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0.expr {
+            BaseExpr::Reset(box e, _) => write!(f, "{}", Operand(e)),
+            BaseExpr::Value(_)
+            | BaseExpr::Var(_)
+            | BaseExpr::FunCall(_, _, None)
+            | BaseExpr::Iterate(_, _, _, _)
+            | BaseExpr::Array(_)
+            | BaseExpr::Index(_, _)
+            | BaseExpr::Slice(_, _, _)
+            | BaseExpr::Record(_, _)
+            | BaseExpr::Field(_, _)
+            | BaseExpr::With(_, _)
+            | BaseExpr::Tuple(_) => write!(f, "{}", self.0),
+            _ => write!(f, "({})", self.0),
+        }
+    }
 }
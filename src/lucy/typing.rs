@@ -1,131 +1,746 @@
 //! Check and type untyped LucyRS nodes
 
-use crate::ast::{BinOp, Type, UnOp, Value};
-use crate::lucy::ast;
+use crate::ast::{
+    Assertion, BinOp, Clock, ImportedNode, IteratorKind, Span, Type, TypeDecl, TypeDef, UnOp,
+    Value,
+};
+use crate::diagnostic::Diagnostic;
+use crate::lucy::ast::{self, TypeClass};
 use crate::lucy::typed_ast::{BaseExpr, Expr, Node};
 use std::collections::HashMap;
 
 /// Context used in the module functions
-/// It keep tracks of the variables types, and the nodes types
+/// It keep tracks of the variables types, the nodes types,
+/// the enum types with their constructors and the record types with their fields
+/// classes contains the classes of the type variables of each node
 struct Context<'a> {
+    node: &'a str,
     variables: &'a HashMap<String, Type>,
     functions: &'a HashMap<String, (Vec<Type>, Vec<Type>)>,
+    classes: &'a HashMap<String, Vec<(String, TypeClass)>>,
+    types: &'a Vec<TypeDecl>,
+    constructors: &'a HashMap<String, String>,
+    fields: &'a HashMap<String, String>,
 }
 
-/// Annotate the types of a list of nodes
-pub fn annotate_types(nodes: Vec<ast::Node>) -> Result<Vec<Node>, String> {
+impl<'a> Context<'a> {
+    /// Check if a type is int, real, or a numeric type variable of the node
+    fn is_numeric(&self, typ: &Type) -> bool {
+        match typ {
+            Type::Int | Type::Real => true,
+            Type::Var(name) => self.classes[self.node]
+                .iter()
+                .any(|(var, class)| var == name && *class == TypeClass::Numeric),
+            _ => false,
+        }
+    }
+}
+
+/// Annotate the types of the nodes of a program
+pub fn annotate_types(program: ast::Program) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let ast::Program {
+        types,
+        mut nodes,
+        mut imported,
+        ..
+    } = program;
+    let mut errors = vec![];
+    let mut type_names = vec![];
+    let mut constructors = HashMap::new();
+    let mut fields = HashMap::new();
+    for decl in &types {
+        if type_names.contains(&decl.name) {
+            errors.push(Diagnostic::new(
+                format!("Type {} was declared twice", decl.name),
+                decl.span,
+            ));
+        }
+        type_names.push(decl.name.clone());
+        match &decl.def {
+            TypeDef::Enum(names) => {
+                for name in names {
+                    if constructors.contains_key(name) {
+                        errors.push(Diagnostic::new(
+                            format!("Constructor {} was declared twice", name),
+                            decl.span,
+                        ));
+                    } else {
+                        constructors.insert(name.clone(), decl.name.clone());
+                    }
+                }
+            }
+            TypeDef::Record(record_fields) => {
+                for (name, _) in record_fields {
+                    if fields.contains_key(name) {
+                        errors.push(Diagnostic::new(
+                            format!("Field {} was declared twice", name),
+                            decl.span,
+                        ));
+                    } else {
+                        fields.insert(name.clone(), decl.name.clone());
+                    }
+                }
+            }
+            TypeDef::Alias(_) => (),
+        }
+    }
+    for decl in &types {
+        errors.extend(check_type_decl(decl, &types));
+    }
+    for node in &mut nodes {
+        let context = format!("Error while typing node {}", node.name);
+        let mut diagnostics = resolve_declared_types(node, &types);
+        diagnostics.append(&mut check_type_variables(node));
+        errors.extend(diagnostics.into_iter().map(|d| d.with_context(&context)));
+    }
+    for node in &mut imported {
+        errors.extend(check_imported_node(node, &types));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
     let mut functions = HashMap::new();
+    let mut classes = HashMap::new();
     let take_types = |vec: &Vec<(String, Type)>| vec.iter().map(|(_, t)| t.clone()).collect();
-    let mut add_function = |node: &ast::Node| -> Result<(), String> {
-        if functions.contains_key(&node.name) {
-            Err(format!("Node {} was declared twice", node.name))
+    let signatures = nodes
+        .iter()
+        .map(|node| {
+            let signature = (&node.in_params, &node.out_params);
+            (&node.name, signature, node.constraints.clone(), node.span)
+        })
+        .chain(imported.iter().map(|node| {
+            let signature = (&node.in_params, &node.out_params);
+            (&node.name, signature, vec![], node.span)
+        }));
+    for (name, (in_params, out_params), constraints, span) in signatures {
+        if functions.contains_key(name) {
+            errors.push(Diagnostic::new(
+                format!("Node {} was declared twice", name),
+                span,
+            ));
         } else {
-            functions.insert(
-                node.name.clone(),
-                (take_types(&node.in_params), take_types(&node.out_params)),
-            );
-            Ok(())
+            functions.insert(name.clone(), (take_types(in_params), take_types(out_params)));
+            classes.insert(name.clone(), constraints);
         }
-    };
-    for node in &nodes {
-        add_function(node)?;
     }
     let mut typed_nodes = vec![];
     for node in nodes {
         let node_name = node.name.clone();
-        match type_node(node, &functions) {
+        match type_node(node, &functions, &classes, &types, &constructors, &fields) {
             Ok(node) => typed_nodes.push(node),
-            Err(message) => {
-                return Err(format!(
-                    "Error while typing node {}: {}",
-                    node_name, message
-                ))
+            Err(diagnostics) => {
+                let context = format!("Error while typing node {}", node_name);
+                errors.extend(diagnostics.into_iter().map(|d| d.with_context(&context)));
             }
         }
     }
-    Ok(typed_nodes)
+    if errors.is_empty() {
+        Ok(typed_nodes)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check that the types used in a type declaration are declared,
+/// and that a record does not contain itself
+fn check_type_decl(decl: &TypeDecl, types: &Vec<TypeDecl>) -> Vec<Diagnostic> {
+    let used_types = match &decl.def {
+        TypeDef::Enum(_) => vec![],
+        TypeDef::Record(fields) => fields.iter().map(|(_, typ)| typ).collect(),
+        TypeDef::Alias(typ) => vec![typ],
+    };
+    let mut errors = vec![];
+    let mut records = vec![];
+    for typ in used_types {
+        if !typ.variables().is_empty() {
+            errors.push(Diagnostic::new(
+                String::from("Type variables can only be used in the signature of a node"),
+                decl.span,
+            ));
+        }
+        match TypeDecl::resolve(types, typ) {
+            Ok(typ) => records.push(typ),
+            Err(message) => errors.push(Diagnostic::new(message, decl.span)),
+        }
+    }
+    if let TypeDef::Record(_) = decl.def {
+        let mut visited = vec![];
+        while let Some(typ) = records.pop() {
+            match typ {
+                Type::Array(box typ, _) => records.push(typ),
+                Type::Named(ref name) if name == &decl.name => {
+                    errors.push(Diagnostic::new(
+                        format!("Record type {} contains itself", decl.name),
+                        decl.span,
+                    ));
+                    break;
+                }
+                Type::Named(name) => {
+                    if let Some(TypeDef::Record(fields)) = TypeDecl::lookup(types, &name) {
+                        if !visited.contains(&name) {
+                            records.extend(
+                                fields
+                                    .iter()
+                                    .filter_map(|(_, typ)| TypeDecl::resolve(types, typ).ok()),
+                            );
+                            visited.push(name);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    errors
 }
 
-/// Type a node
+/// Replace the type aliases in the declarations of a node by the types
+/// they stand for, and report the undeclared types
+fn resolve_declared_types(node: &mut ast::Node, types: &Vec<TypeDecl>) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    let var_spans = &node.var_spans;
+    let span = node.span;
+    let declared_variables = node
+        .in_params
+        .iter_mut()
+        .chain(node.out_params.iter_mut())
+        .map(|(ident, typ)| (&*ident, Some(typ)))
+        .chain(
+            node.local_params
+                .iter_mut()
+                .map(|(ident, (typ, _))| (ident, typ.as_mut())),
+        );
+    for (ident, typ) in declared_variables {
+        let typ = match typ {
+            Some(typ) => typ,
+            None => continue,
+        };
+        match TypeDecl::resolve(types, typ) {
+            Ok(resolved) => *typ = resolved,
+            Err(message) => errors.push(Diagnostic::new(
+                message,
+                var_spans.get(ident).cloned().unwrap_or(span),
+            )),
+        }
+    }
+    errors
+}
+
+/// Replace the type aliases in the signature of an imported node, which
+/// cannot be polymorphic
+fn check_imported_node(node: &mut ImportedNode, types: &Vec<TypeDecl>) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    let params = node.in_params.iter_mut().chain(node.out_params.iter_mut());
+    for (ident, typ) in params {
+        if !typ.variables().is_empty() {
+            errors.push(Diagnostic::new(
+                format!(
+                    "The type of {} cannot contain type variables, since {} is imported",
+                    ident, node.name
+                ),
+                node.span,
+            ));
+        }
+        match TypeDecl::resolve(types, typ) {
+            Ok(resolved) => *typ = resolved,
+            Err(message) => errors.push(Diagnostic::new(message, node.span)),
+        }
+    }
+    errors
+}
+
+/// Check that the type variables of a node are bound by its inputs,
+/// so that they are known at each call
+fn check_type_variables(node: &ast::Node) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    let input_variables: Vec<&String> = node
+        .in_params
+        .iter()
+        .flat_map(|(_, typ)| typ.variables())
+        .collect();
+    let declared_variables = node
+        .out_params
+        .iter()
+        .map(|(ident, typ)| (ident, Some(typ)))
+        .chain(
+            node.local_params
+                .iter()
+                .map(|(ident, (typ, _))| (ident, typ.as_ref())),
+        );
+    for (ident, typ) in declared_variables {
+        for name in typ.iter().flat_map(|typ| typ.variables()) {
+            if !input_variables.contains(&name) {
+                errors.push(Diagnostic::new(
+                    format!(
+                        "The type variable '{} of {} does not appear in the inputs of the node",
+                        name, ident
+                    ),
+                    node.var_spans.get(ident).cloned().unwrap_or(node.span),
+                ));
+            }
+        }
+    }
+    for (name, _) in &node.constraints {
+        if !input_variables.contains(&name) {
+            errors.push(Diagnostic::new(
+                format!(
+                    "The type variable '{} is constrained, but does not appear in the inputs of the node",
+                    name
+                ),
+                node.span,
+            ));
+        }
+    }
+    errors
+}
+
+/// Type a node, whose declared types do not contain aliases
+/// The types of the local variables declared without type are first inferred
+/// from their equations. The equations are typed independently, so that
+/// every error is reported
 pub fn type_node(
     node: ast::Node,
     functions: &HashMap<String, (Vec<Type>, Vec<Type>)>,
-) -> Result<Node, String> {
+    classes: &HashMap<String, Vec<(String, TypeClass)>>,
+    types: &Vec<TypeDecl>,
+    constructors: &HashMap<String, String>,
+    fields: &HashMap<String, String>,
+) -> Result<Node, Vec<Diagnostic>> {
     let mut variables = HashMap::new();
-    let mut add_variables = |list: &Vec<(String, Type)>| -> Result<(), String> {
-        for (ident, typ) in list {
-            if variables.contains_key(ident) {
-                return Err(format!("The variable {} was declared twice", ident));
-            } else {
-                variables.insert(ident.clone(), typ.clone());
+    let mut errors = vec![];
+    let mut untyped = vec![];
+    let declared_variables = node
+        .in_params
+        .iter()
+        .chain(node.out_params.iter())
+        .map(|(ident, typ)| (ident, Some(typ)))
+        .chain(
+            node.local_params
+                .iter()
+                .map(|(ident, (typ, _))| (ident, typ.as_ref())),
+        );
+    for (ident, typ) in declared_variables {
+        if variables.contains_key(ident) || untyped.contains(ident) {
+            errors.push(Diagnostic::new(
+                format!("The variable {} was declared twice", ident),
+                node.span,
+            ));
+        } else if let Some(typ) = typ {
+            variables.insert(ident.clone(), typ.clone());
+        } else {
+            untyped.push(ident.clone());
+        }
+    }
+
+    // Each round types the variables defined by an equation whose
+    // types can be inferred from the variables already typed
+    while !untyped.is_empty() {
+        let context = Context {
+            node: &node.name,
+            variables: &variables,
+            functions,
+            classes,
+            types,
+            constructors,
+            fields,
+        };
+        let mut inferred = vec![];
+        for eq in &node.eq_list {
+            if !eq.idents.iter().any(|ident| untyped.contains(ident)) {
+                continue;
+            }
+            match infer_expr(&eq.expr, &context) {
+                Some(typ) if typ.len() == eq.idents.len() => inferred.extend(
+                    eq.idents
+                        .iter()
+                        .cloned()
+                        .zip(typ)
+                        .filter(|(ident, _)| untyped.contains(ident)),
+                ),
+                _ => (),
             }
         }
-        Ok(())
-    };
-    add_variables(&node.in_params)?;
-    add_variables(&node.out_params)?;
-    for (ident, (typ, _)) in &node.local_params {
-        if variables.contains_key(ident) {
-            return Err(format!("The variable {} was declared twice", ident));
-        } else {
-            variables.insert(ident.clone(), typ.clone());
+        if inferred.is_empty() {
+            break;
+        }
+        for (ident, typ) in inferred {
+            untyped.retain(|name| name != &ident);
+            variables.insert(ident, typ);
         }
     }
+    if !untyped.is_empty() {
+        untyped.sort();
+        for ident in untyped {
+            errors.push(Diagnostic::new(
+                format!(
+                    "The type of the variable {} cannot be inferred, it should be declared",
+                    ident
+                ),
+                node.var_spans.get(&ident).cloned().unwrap_or(node.span),
+            ));
+        }
+        return Err(errors);
+    }
 
     let context = Context {
+        node: &node.name,
         variables: &variables,
         functions,
+        classes,
+        types,
+        constructors,
+        fields,
     };
 
+    let mut local_params = HashMap::new();
+    for (ident, (_, ck)) in node.local_params {
+        let span = node.var_spans.get(&ident).cloned().unwrap_or(node.span);
+        match ck
+            .map(|ck| type_declared_clock(ck, span, &context))
+            .transpose()
+        {
+            Ok(ck) => {
+                let typ = variables[&ident].clone();
+                local_params.insert(ident, (typ, ck));
+            }
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+
+    let mut param_clocks = HashMap::new();
+    for (ident, ck) in node.param_clocks {
+        let span = node.var_spans.get(&ident).cloned().unwrap_or(node.span);
+        match type_declared_clock(ck, span, &context) {
+            Ok(ck) => {
+                param_clocks.insert(ident, ck);
+            }
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+
+    // The conditions of the assertions are variables defined by an equation
+    let assertions: Vec<Assertion> = node
+        .asserts
+        .into_iter()
+        .map(|assert| match assert.expr.expr {
+            ast::BaseExpr::Var(var) => Assertion {
+                var,
+                text: assert.text,
+                kind: assert.kind,
+                assumption: assert.assumption,
+                span: assert.span,
+            },
+            _ => unreachable!(),
+        })
+        .collect();
+
     let mut typed_expr = vec![];
     for eq in node.eq_list {
-        typed_expr.push((eq.0, type_expr(eq.1, &context)?));
+        let idents = eq.idents;
+        let assertion = assertions
+            .iter()
+            .find(|assertion| idents.len() == 1 && idents[0] == assertion.var);
+        let typed = type_expr(eq.expr, &context).and_then(|expr| match assertion {
+            Some(assertion) if expr.typ != [Type::Bool] => Err(Diagnostic::new(
+                format!("The condition of the {} should have type bool", assertion.kind),
+                expr.span,
+            )),
+            _ => check_equation(&idents, expr, &context),
+        });
+        match typed {
+            Ok(expr) => typed_expr.push((idents, expr)),
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
     let node = Node {
         name: node.name,
         in_params: node.in_params,
         out_params: node.out_params,
-        local_params: node.local_params,
+        param_clocks,
+        local_params,
         eq_list: typed_expr,
+        assertions,
+        span: node.span,
     };
     Ok(node)
 }
 
+/// Check that the type of an equation matches the defined variables
+fn check_equation(
+    idents: &Vec<String>,
+    mut expr: Expr,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let declared = idents
+        .iter()
+        .zip(expr.typ.iter())
+        .map(|(ident, typ)| context.variables.get(ident).unwrap_or(typ).clone())
+        .collect();
+    coerce_literal(&mut expr, &declared, context);
+    if idents.len() != expr.typ.len() {
+        return Err(Diagnostic::new(
+            format!(
+                "The equation defines {} variables, but its expression has {} values",
+                idents.len(),
+                expr.typ.len()
+            ),
+            expr.span,
+        ));
+    }
+    for (ident, typ) in idents.iter().zip(expr.typ.iter()) {
+        match context.variables.get(ident) {
+            Some(declared) if declared != typ => {
+                return Err(Diagnostic::new(
+                    format!(
+                        "The variable {} has type {}, but its definition has type {}",
+                        ident, declared, typ
+                    ),
+                    expr.span,
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(expr)
+}
+
 /// Type an expression
-fn type_expr(expr: ast::Expr, context: &Context) -> Result<Expr, String> {
-    match expr {
-        ast::Expr::Value(v) => Ok(type_value(v)),
-        ast::Expr::UnOp(op, expr) => type_unop(op, *expr, context),
-        ast::Expr::BinOp(op, lhs, rhs) => type_binop(op, *lhs, *rhs, context),
-        ast::Expr::When(box expr, ck, b) => type_when(expr, ck, b, context),
-        ast::Expr::Merge(s, box e_true, box e_false) => type_merge(s, e_true, e_false, context),
-        ast::Expr::Fby(v, expr2) => type_fby(v, *expr2, context),
-        ast::Expr::IfThenElse(e_cond, e_then, e_else) => {
-            type_ifthenelse(*e_cond, *e_then, *e_else, context)
-        }
-        ast::Expr::Var(ident) => type_var(ident, context),
-        ast::Expr::FunCall(ident, params, ck) => type_funcall(ident, params, ck, context),
-        ast::Expr::Current(ident, v) => type_current(ident, v, context),
-        ast::Expr::Pre(box e) => type_pre(e, context),
-        ast::Expr::Arrow(exprs) => type_arrow(exprs, context),
-    }
-}
-
-fn type_value(value: Value) -> Expr {
-    let typ = match value {
-        Value::Bool(_) => vec![Type::Bool],
-        Value::Int(_) => vec![Type::Int],
-        Value::Real(_) => vec![Type::Real],
-    };
+fn type_expr(expr: ast::Expr, context: &Context) -> Result<Expr, Diagnostic> {
+    let span = expr.span;
+    match expr.expr {
+        ast::BaseExpr::Value(v) => Ok(type_value(v, span)),
+        ast::BaseExpr::UnOp(op, expr) => type_unop(op, *expr, span, context),
+        ast::BaseExpr::BinOp(op, lhs, rhs) => type_binop(op, *lhs, *rhs, span, context),
+        ast::BaseExpr::When(box expr, ck, c) => {
+            type_when(expr, ck.as_var().clone(), c, span, context)
+        }
+        ast::BaseExpr::Merge(ck, branches) => {
+            type_merge(ck.as_var().clone(), branches, span, context)
+        }
+        ast::BaseExpr::Fby(box init, box e) => type_fby(init, e, span, context),
+        ast::BaseExpr::IfThenElse(e_cond, e_then, e_else) => {
+            type_ifthenelse(*e_cond, *e_then, *e_else, span, context)
+        }
+        ast::BaseExpr::Var(ident) => type_var(ident, span, context),
+        ast::BaseExpr::FunCall(ident, params, ck) => {
+            let ck = ck.map(|ck| ck.as_var().clone());
+            type_funcall(ident, params, ck, span, context)
+        }
+        ast::BaseExpr::Iterate(kind, fun, n, inputs) => {
+            type_iterate(kind, fun, n, inputs, span, context)
+        }
+        ast::BaseExpr::StaticCall(_, _, _, _) => unreachable!(),
+        ast::BaseExpr::Current(e, v) => type_current(e.as_var().clone(), v, span, context),
+        ast::BaseExpr::Pre(box e) => type_pre(e, span, context),
+        ast::BaseExpr::Arrow(exprs) => type_arrow(exprs, span, context),
+        ast::BaseExpr::Array(exprs) => type_array(exprs, span, context),
+        ast::BaseExpr::Index(box e, box i) => type_index(e, i, span, context),
+        ast::BaseExpr::Slice(box e, i, j) => type_slice(e, i, j, span, context),
+        ast::BaseExpr::Concat(box e1, box e2) => type_concat(e1, e2, span, context),
+        ast::BaseExpr::Record(fields) => type_record(fields, span, context),
+        ast::BaseExpr::Field(box e, field) => type_field(e, field, span, context),
+        ast::BaseExpr::With(box e, fields) => type_with(e, fields, span, context),
+        ast::BaseExpr::Reset(box e, r) => type_reset(e, r, span, context),
+        ast::BaseExpr::Tuple(exprs) => type_tuple(exprs, span, context),
+    }
+}
+
+/// Infer the types of an expression whose variables are not all typed yet,
+/// for the local variables declared without type. The type of a branch
+/// (of an arrow, a fby, a conditional...) is enough, so that a variable can
+/// be defined from its own previous values. Return None if the types cannot
+/// be determined yet
+fn infer_expr(expr: &ast::Expr, context: &Context) -> Option<Vec<Type>> {
+    let infer = |e: &ast::Expr| infer_expr(e, context);
+    match &expr.expr {
+        ast::BaseExpr::UnOp(UnOp::Not, _) => Some(vec![Type::Bool]),
+        ast::BaseExpr::BinOp(op, box lhs, box rhs) => match op {
+            BinOp::Mul | BinOp::Div | BinOp::Add | BinOp::Sub | BinOp::Mod => {
+                prefer(vec![infer(lhs), infer(rhs)])
+            }
+            _ => Some(vec![Type::Bool]),
+        },
+        ast::BaseExpr::UnOp(UnOp::UMinus, box e)
+        | ast::BaseExpr::When(box e, _, _)
+        | ast::BaseExpr::Pre(box e)
+        | ast::BaseExpr::With(box e, _)
+        | ast::BaseExpr::Reset(box e, _) => infer(e),
+        ast::BaseExpr::IfThenElse(_, box e_then, box e_else) => {
+            prefer(vec![infer(e_then), infer(e_else)])
+        }
+        ast::BaseExpr::Merge(_, branches) => {
+            prefer(branches.iter().map(|(_, e)| infer(e)).collect())
+        }
+        ast::BaseExpr::Arrow(exprs) => prefer(exprs.iter().map(infer).collect()),
+        ast::BaseExpr::Tuple(exprs) => {
+            let types: Option<Vec<Vec<Type>>> = exprs.iter().map(infer).collect();
+            Some(types?.concat())
+        }
+        ast::BaseExpr::Fby(box init, box e) => prefer(vec![infer(init), infer(e)]),
+        ast::BaseExpr::FunCall(fun, inputs, _) => {
+            let (in_type, out_type) = context.functions.get(fun)?;
+            infer_outputs(in_type, out_type, inputs, context)
+        }
+        ast::BaseExpr::Iterate(kind, fun, Value::Int(n), inputs) if *n > 0 => {
+            let (in_type, out_type) = context.functions.get(fun)?;
+            let n = *n as usize;
+            let in_type = kind.inputs(in_type, n);
+            let out_type = kind.outputs(out_type, n);
+            infer_outputs(&in_type, &out_type, inputs, context)
+        }
+        _ => type_expr(expr.clone(), context).ok().map(|e| e.typ),
+    }
+}
+
+/// Infer the output types of a node call. They are known from the signature
+/// of the node, unless it is polymorphic and its type variables are bound
+/// by the types of the inputs
+fn infer_outputs(
+    in_type: &Vec<Type>,
+    out_type: &Vec<Type>,
+    inputs: &Vec<ast::Expr>,
+    context: &Context,
+) -> Option<Vec<Type>> {
+    let mut bindings = HashMap::new();
+    if out_type.iter().any(|t| !t.variables().is_empty()) {
+        for (expected, input) in in_type.iter().zip(inputs) {
+            if let Some([typ]) = infer_expr(input, context).as_ref().map(|t| t.as_slice()) {
+                unify(expected, typ, &mut bindings);
+            }
+        }
+    }
+    let bound = out_type
+        .iter()
+        .flat_map(|t| t.variables())
+        .all(|name| bindings.contains_key(name));
+    if bound {
+        Some(out_type.iter().map(|t| t.substitute(&bindings)).collect())
+    } else {
+        None
+    }
+}
+
+/// Choose the types of an expression among the types inferred for its
+/// branches, value by value. An integer may be a literal, which can be given
+/// a numeric type variable, so another type is preferred
+fn prefer(types: Vec<Option<Vec<Type>>>) -> Option<Vec<Type>> {
+    let types: Vec<Vec<Type>> = types.into_iter().flatten().collect();
+    let first = types.first()?;
+    let preferred = first.iter().enumerate().map(|(i, default)| {
+        types
+            .iter()
+            .filter_map(|typ| typ.get(i))
+            .find(|typ| **typ != Type::Int)
+            .unwrap_or(default)
+            .clone()
+    });
+    Some(preferred.collect())
+}
+
+fn type_value(value: Value, span: Span) -> Expr {
+    let typ = vec![value.get_type()];
     let expr = BaseExpr::Value(value);
-    Expr { expr, typ }
+    Expr { expr, typ, span }
+}
+
+/// Get the value of a constructor given by its name
+fn type_constructor(name: String, span: Span, context: &Context) -> Result<Value, Diagnostic> {
+    match name.as_str() {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => match context.constructors.get(&name) {
+            Some(typ) => Ok(Value::Enum(typ.clone(), name)),
+            None => Err(Diagnostic::new(
+                format!("Constructor {} used but not declared", name),
+                span,
+            )),
+        },
+    }
+}
+
+/// Give its type to a constructor written in place of a constant value
+fn type_constant(value: Value, span: Span, context: &Context) -> Result<Value, Diagnostic> {
+    match value {
+        Value::Enum(ref typ, name) if typ.is_empty() => type_constructor(name, span, context),
+        value => Ok(value),
+    }
+}
+
+/// Check the clock of a declared variable, and give their type
+/// to the enum constructors it contains
+fn type_declared_clock(ck: Clock, span: Span, context: &Context) -> Result<Clock, Diagnostic> {
+    let Clock::Ck(v) = ck;
+    let mut typed_v = vec![];
+    for (ck, value) in v {
+        let value = type_constant(value, span, context)?;
+        check_clock_variable(&ck, &value, span, context)?;
+        typed_v.push((ck, value));
+    }
+    Ok(Clock::Ck(typed_v))
 }
 
-fn type_unop(op: UnOp, expr: ast::Expr, context: &Context) -> Result<Expr, String> {
+/// Check that a clock variable is declared, is a boolean or an enum, and
+/// has the type of the constructor it is sampled on
+fn check_clock_variable(
+    ck: &String,
+    value: &Value,
+    span: Span,
+    context: &Context,
+) -> Result<(), Diagnostic> {
+    match context.variables.get(ck) {
+        None => Err(Diagnostic::new(
+            format!("Variable {} used but not declared", ck),
+            span,
+        )),
+        Some(t) if t != &Type::Bool && !TypeDecl::is_enum(context.types, t) => {
+            Err(Diagnostic::new(
+                format!(
+                    "{} used as a clock should be a boolean or an enum, but has type {}",
+                    describe_variable(ck),
+                    t
+                ),
+                span,
+            ))
+        }
+        Some(t) if t != &value.get_type() => Err(Diagnostic::new(
+            format!(
+                "{} used as a clock has type {}, but is sampled on a value of type {}",
+                describe_variable(ck),
+                t,
+                value.get_type()
+            ),
+            span,
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Name a variable in a message, the expressions used as clocks or resets
+/// being defined on generated variables, whose names start with _
+fn describe_variable(name: &str) -> String {
+    if name.starts_with('_') {
+        String::from("The expression")
+    } else {
+        format!("The variable {}", name)
+    }
+}
+
+fn type_unop(
+    op: UnOp,
+    expr: ast::Expr,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
     let typed_expr = type_expr(expr, context)?;
     if typed_expr.typ.len() != 1 {
-        Err(String::from("Unary operator cannot be applied to a tuple"))
+        Err(Diagnostic::new(
+            String::from("Unary operator cannot be applied to a tuple"),
+            span,
+        ))
     } else {
         match (op, typed_expr.typ[0].clone()) {
             (UnOp::Not, t) => {
@@ -133,23 +748,29 @@ fn type_unop(op: UnOp, expr: ast::Expr, context: &Context) -> Result<Expr, Strin
                     Ok(Expr {
                         expr: BaseExpr::UnOp(UnOp::Not, box typed_expr),
                         typ: vec![Type::Bool],
+                        span,
                     })
                 } else {
-                    Err(String::from(
-                        "The not operator can only be applied to booleans",
+                    Err(Diagnostic::new(
+                        String::from("The not operator can only be applied to booleans"),
+                        span,
                     ))
                 }
             }
             (UnOp::UMinus, t) => {
-                if let Type::Bool = t {
-                    Err(String::from(
-                        "The minus unary operator can only be applied to integers or reals",
-                    ))
-                } else {
+                if context.is_numeric(&t) {
                     Ok(Expr {
                         expr: BaseExpr::UnOp(UnOp::UMinus, box typed_expr.clone()),
                         typ: typed_expr.typ.clone(),
+                        span,
                     })
+                } else {
+                    Err(Diagnostic::new(
+                        String::from(
+                            "The minus unary operator can only be applied to integers or reals",
+                        ),
+                        span,
+                    ))
                 }
             }
         }
@@ -160,112 +781,165 @@ fn type_binop(
     op: BinOp,
     lhs: ast::Expr,
     rhs: ast::Expr,
+    span: Span,
     context: &Context,
-) -> Result<Expr, String> {
-    let typed_lhs = type_expr(lhs, context)?;
-    let typed_rhs = type_expr(rhs, context)?;
+) -> Result<Expr, Diagnostic> {
+    let mut typed_lhs = type_expr(lhs, context)?;
+    let mut typed_rhs = type_expr(rhs, context)?;
+    coerce_literal(&mut typed_lhs, &typed_rhs.typ, context);
+    coerce_literal(&mut typed_rhs, &typed_lhs.typ, context);
+    let error = |message: &str| Err(Diagnostic::new(String::from(message), span));
     if typed_lhs.typ.len() != 1 || typed_rhs.typ.len() != 1 {
-        Err(String::from("Binary operator cannot be applied to tuples"))
+        error("Binary operator cannot be applied to tuples")
     } else if typed_lhs.typ[0] != typed_rhs.typ[0] {
-        Err(String::from(
-            "Binary operator should be applied on equal types",
-        ))
+        error("Binary operator should be applied on equal types")
     } else {
         let typ = typed_lhs.typ[0].clone();
         match op {
             c @ BinOp::Lt | c @ BinOp::Le | c @ BinOp::Gt | c @ BinOp::Ge => match typ {
-                Type::Bool => Err(String::from(
-                    "Lt, Le, Gt, and Ge operators should be applied on integers or reals",
-                )),
-                _ => Ok(Expr {
+                ref t if context.is_numeric(t) => Ok(Expr {
                     expr: BaseExpr::BinOp(c, box typed_lhs, box typed_rhs),
                     typ: vec![Type::Bool],
+                    span,
                 }),
+                _ => error("Lt, Le, Gt, and Ge operators should be applied on integers or reals"),
             },
             c @ BinOp::Mul | c @ BinOp::Div | c @ BinOp::Add | c @ BinOp::Sub => match typ {
-                Type::Bool => Err(String::from(
-                    "Mul, Div, Add, Sub operators should be applied on integers or reals",
-                )),
-                t => Ok(Expr {
+                t if context.is_numeric(&t) => Ok(Expr {
                     expr: BaseExpr::BinOp(c, box typed_lhs, box typed_rhs),
                     typ: vec![t],
+                    span,
                 }),
+                _ => error("Mul, Div, Add, Sub operators should be applied on integers or reals"),
             },
             BinOp::Mod => match typ {
                 Type::Int => Ok(Expr {
                     expr: BaseExpr::BinOp(BinOp::Mod, box typed_lhs, box typed_rhs),
                     typ: vec![Type::Int],
+                    span,
                 }),
-                _ => Err(String::from("Mod operator should be applied on integers")),
+                _ => error("Mod operator should be applied on integers"),
             },
             c @ BinOp::Or | c @ BinOp::Xor | c @ BinOp::And | c @ BinOp::Impl => match typ {
                 Type::Bool => Ok(Expr {
                     expr: BaseExpr::BinOp(c, box typed_lhs, box typed_rhs),
                     typ: vec![Type::Bool],
+                    span,
                 }),
-                _ => Err(String::from(
-                    "Or, And, and Impl operators should be applied on boolean types",
-                )),
+                _ => error("Or, And, and Impl operators should be applied on boolean types"),
             },
             c @ BinOp::Neq | c @ BinOp::Eq => Ok(Expr {
                 expr: BaseExpr::BinOp(c, box typed_lhs, box typed_rhs),
                 typ: vec![Type::Bool],
+                span,
             }),
         }
     }
 }
 
-fn type_when(expr: ast::Expr, ck: String, b: bool, context: &Context) -> Result<Expr, String> {
+fn type_when(
+    expr: ast::Expr,
+    ck: String,
+    constructor: String,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
     let typed_expr = type_expr(expr, context)?;
     let typ = typed_expr.typ.clone();
-    if context.variables.get(&ck).is_none() {
-        return Err(String::from(
-            "The clock in a when construct should be a boolean",
-        ));
-    }
+    let value = type_constructor(constructor, span, context)?;
+    check_clock_variable(&ck, &value, span, context)?;
     Ok(Expr {
-        expr: BaseExpr::When(box typed_expr, ck, b),
+        expr: BaseExpr::When(box typed_expr, ck, value),
         typ,
+        span,
     })
 }
 
+/// Type a merge. The branches should cover each constructor of the
+/// clock type exactly once, and are sorted in the declaration order
 fn type_merge(
     ck: String,
-    e_true: ast::Expr,
-    e_false: ast::Expr,
+    branches: Vec<(String, ast::Expr)>,
+    span: Span,
     context: &Context,
-) -> Result<Expr, String> {
-    let typed_e_true = type_expr(e_true, context)?;
-    let typed_e_false = type_expr(e_false, context)?;
-    if typed_e_false.typ != typed_e_true.typ {
-        return Err(String::from(
-            "The type of the two expressions in a merge construct should have the same type",
-        ));
+) -> Result<Expr, Diagnostic> {
+    let ck_type = match context.variables.get(&ck) {
+        Some(t) if t == &Type::Bool || TypeDecl::is_enum(context.types, t) => t.clone(),
+        Some(_) => {
+            return Err(Diagnostic::new(
+                String::from("The clock in a merge construct should be a boolean or an enum"),
+                span,
+            ))
+        }
+        None => {
+            return Err(Diagnostic::new(
+                format!("Variable {} used but not declared", ck),
+                span,
+            ))
+        }
+    };
+    let mut typed_branches: Vec<(Value, Expr)> = vec![];
+    for (constructor, expr) in branches {
+        let expr_span = expr.span;
+        let value = type_constructor(constructor, expr_span, context)?;
+        check_clock_variable(&ck, &value, expr_span, context)?;
+        if typed_branches.iter().any(|(v, _)| v == &value) {
+            return Err(Diagnostic::new(
+                format!("The constructor {} has two branches in the merge", value),
+                expr_span,
+            ));
+        }
+        typed_branches.push((value, type_expr(expr, context)?));
+    }
+    let mut sorted_branches = vec![];
+    for value in TypeDecl::clock_values(context.types, &ck_type) {
+        match typed_branches.iter().position(|(v, _)| v == &value) {
+            Some(i) => sorted_branches.push(typed_branches.remove(i)),
+            None => {
+                return Err(Diagnostic::new(
+                    format!("The merge has no branch for the constructor {}", value),
+                    span,
+                ))
+            }
+        }
     }
-    let typ = typed_e_false.typ.clone();
-    if context.variables.get(&ck).is_none() {
-        return Err(String::from(
-            "The clock in a merge construct should be a boolean",
+    let typ = sorted_branches[0].1.typ.clone();
+    if sorted_branches.iter().any(|(_, e)| e.typ != typ) {
+        return Err(Diagnostic::new(
+            String::from("The expressions in a merge construct should have the same type"),
+            span,
         ));
     }
     Ok(Expr {
-        expr: BaseExpr::Merge(ck, box typed_e_true, box typed_e_false),
+        expr: BaseExpr::Merge(ck, sorted_branches),
         typ,
+        span,
     })
 }
 
-fn type_fby(init: Value, rhs: ast::Expr, context: &Context) -> Result<Expr, String> {
-    let typed_rhs = type_expr(rhs, context)?;
-    let typed_init = type_value(init.clone());
+fn type_fby(
+    init: ast::Expr,
+    rhs: ast::Expr,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let mut typed_init = type_expr(init, context)?;
+    let mut typed_rhs = type_expr(rhs, context)?;
+    coerce_literal(&mut typed_init, &typed_rhs.typ, context);
+    coerce_literal(&mut typed_rhs, &typed_init.typ, context);
     if typed_init.typ != typed_rhs.typ {
-        Err(String::from(
-            "The type of the left hand side and the right hand side of an arrow or a fby should be equal",
+        Err(Diagnostic::new(
+            String::from(
+                "The type of the left hand side and the right hand side of an arrow or a fby should be equal",
+            ),
+            span,
         ))
     } else {
         let typ = typed_init.typ.clone();
         Ok(Expr {
-            expr: BaseExpr::Fby(init, box typed_rhs),
+            expr: BaseExpr::Fby(box typed_init, box typed_rhs),
             typ,
+            span,
         })
     }
 }
@@ -274,36 +948,48 @@ fn type_ifthenelse(
     expr_cond: ast::Expr,
     expr_then: ast::Expr,
     expr_else: ast::Expr,
+    span: Span,
     context: &Context,
-) -> Result<Expr, String> {
+) -> Result<Expr, Diagnostic> {
     let typed_cond = type_expr(expr_cond, context)?;
-    let typed_then = type_expr(expr_then, context)?;
-    let typed_else = type_expr(expr_else, context)?;
+    let mut typed_then = type_expr(expr_then, context)?;
+    let mut typed_else = type_expr(expr_else, context)?;
+    coerce_literal(&mut typed_then, &typed_else.typ, context);
+    coerce_literal(&mut typed_else, &typed_then.typ, context);
     if typed_cond.typ != [Type::Bool] {
-        Err(String::from(
-            "The conditional in a if statement should have type bool",
+        Err(Diagnostic::new(
+            String::from("The conditional in a if statement should have type bool"),
+            typed_cond.span,
         ))
     } else if typed_then.typ != typed_else.typ {
-        Err(String::from(
-            "The type of both branches of a conditional should be equal",
+        Err(Diagnostic::new(
+            String::from("The type of both branches of a conditional should be equal"),
+            span,
         ))
     } else {
         let typ = typed_then.typ.clone();
         Ok(Expr {
             expr: BaseExpr::IfThenElse(box typed_cond, box typed_then, box typed_else),
             typ,
+            span,
         })
     }
 }
 
-fn type_var(ident: String, context: &Context) -> Result<Expr, String> {
+fn type_var(ident: String, span: Span, context: &Context) -> Result<Expr, Diagnostic> {
     if let Some(t) = context.variables.get(&ident) {
         Ok(Expr {
             expr: BaseExpr::Var(ident),
             typ: vec![t.clone()],
+            span,
         })
+    } else if let Some(typ) = context.constructors.get(&ident) {
+        Ok(type_value(Value::Enum(typ.clone(), ident), span))
     } else {
-        Err(format!("Variable {} used but not declared", &ident))
+        Err(Diagnostic::new(
+            format!("Variable {} used but not declared", &ident),
+            span,
+        ))
     }
 }
 
@@ -311,99 +997,593 @@ fn type_funcall(
     ident: String,
     inputs: Vec<ast::Expr>,
     ck: Option<String>,
+    span: Span,
     context: &Context,
-) -> Result<Expr, String> {
+) -> Result<Expr, Diagnostic> {
     if let Some((in_type, out_type)) = context.functions.get(&ident) {
         if inputs.len() != in_type.len() {
-            return Err(format!(
-                "Node {} expect {} inputs, but only {} were given",
-                &ident,
-                inputs.len(),
-                in_type.len()
+            return Err(Diagnostic::new(
+                format!(
+                    "Node {} expect {} inputs, but {} were given",
+                    &ident,
+                    in_type.len(),
+                    inputs.len()
+                ),
+                span,
             ));
         }
         let mut typed_inputs = vec![];
         for input in inputs {
             typed_inputs.push(type_expr(input, context)?);
         }
+        let mut bindings = HashMap::new();
         for i in 0..typed_inputs.len() {
-            if typed_inputs[i].typ.len() != 1 || typed_inputs[i].typ[0] != in_type[i] {
-                return Err(format!(
-                    "Input {} has not the expected type in node call.",
-                    i
+            if typed_inputs[i].typ.len() != 1
+                || !unify(&in_type[i], &typed_inputs[i].typ[0], &mut bindings)
+            {
+                return Err(Diagnostic::new(
+                    format!("Input {} has not the expected type in node call.", i),
+                    typed_inputs[i].span,
                 ));
             }
         }
+        check_classes(&ident, &bindings, span, context)?;
         if let Some(ck) = ck.clone() {
             if let Some(t) = context.variables.get(&ck) {
                 if t != &Type::Bool {
-                    return Err(format!(
-                        "The variable {} was used as reset but is of type {:?}",
-                        ck, t
+                    return Err(Diagnostic::new(
+                        format!(
+                            "{} was used as reset but is of type {:?}",
+                            describe_variable(&ck),
+                            t
+                        ),
+                        span,
                     ));
                 }
             } else {
-                return Err(format!("Variable {} used but not declared", &ident));
+                return Err(Diagnostic::new(
+                    format!("Variable {} used but not declared", &ck),
+                    span,
+                ));
             }
         }
         Ok(Expr {
             expr: BaseExpr::FunCall(ident, typed_inputs, ck),
-            typ: out_type.clone(),
+            typ: out_type.iter().map(|t| t.substitute(&bindings)).collect(),
+            span,
         })
     } else {
-        Err(format!("Node {} used but not declared", &ident))
+        Err(Diagnostic::new(
+            format!("Node {} used but not declared", &ident),
+            span,
+        ))
+    }
+}
+
+/// Type an iterator, given the signature of the iterated node
+fn type_iterate(
+    kind: IteratorKind,
+    fun: String,
+    n: Value,
+    inputs: Vec<ast::Expr>,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let n = match n {
+        Value::Int(n) if n > 0 => n as usize,
+        _ => {
+            return Err(Diagnostic::new(
+                String::from("The size of an iterator should be a positive integer"),
+                span,
+            ))
+        }
+    };
+    let (in_type, out_type) = match context.functions.get(&fun) {
+        Some(signature) => signature,
+        None => {
+            return Err(Diagnostic::new(
+                format!("Node {} used but not declared", &fun),
+                span,
+            ))
+        }
+    };
+    let error = |message: String| Err(Diagnostic::new(message, span));
+    match kind {
+        IteratorKind::Map => (),
+        IteratorKind::Fold | IteratorKind::MapFold => {
+            if in_type.is_empty() || out_type.is_empty() || in_type[0] != out_type[0] {
+                return error(format!(
+                    "Node {} cannot be used in {}: its first input and output should have the same type",
+                    fun, kind
+                ));
+            }
+            if kind == IteratorKind::Fold && out_type.len() != 1 {
+                return error(format!(
+                    "Node {} cannot be used in fold: it should have a single output",
+                    fun
+                ));
+            }
+        }
+        IteratorKind::Red => {
+            if in_type.len() != 2
+                || out_type.len() != 1
+                || in_type[0] != in_type[1]
+                || in_type[0] != out_type[0]
+            {
+                return error(format!(
+                    "Node {} cannot be used in red: it should have two inputs and an output of the same type",
+                    fun
+                ));
+            }
+        }
+    };
+    let expected = kind.inputs(in_type, n);
+    if inputs.len() != expected.len() {
+        return error(format!(
+            "{}<<{}, {}>> expect {} inputs, but {} were given",
+            kind,
+            fun,
+            n,
+            expected.len(),
+            inputs.len()
+        ));
+    }
+    let mut typed_inputs = vec![];
+    let mut bindings = HashMap::new();
+    for (input, expected_type) in inputs.into_iter().zip(expected.iter()) {
+        let typed_input = type_expr(input, context)?;
+        if typed_input.typ.len() != 1 || !unify(expected_type, &typed_input.typ[0], &mut bindings) {
+            return Err(Diagnostic::new(
+                format!(
+                    "This input should have type {}",
+                    expected_type.substitute(&bindings)
+                ),
+                typed_input.span,
+            ));
+        }
+        typed_inputs.push(typed_input);
+    }
+    check_classes(&fun, &bindings, span, context)?;
+    let typ = kind
+        .outputs(out_type, n)
+        .iter()
+        .map(|t| t.substitute(&bindings))
+        .collect();
+    Ok(Expr {
+        expr: BaseExpr::Iterate(kind, fun, n, typed_inputs),
+        typ,
+        span,
+    })
+}
+
+/// Bind the type variables of a type expected by a polymorphic node to match
+/// the type of an argument. Return false if the types cannot match
+fn unify(expected: &Type, found: &Type, bindings: &mut HashMap<String, Type>) -> bool {
+    match (expected, found) {
+        (Type::Var(name), _) => match bindings.get(name) {
+            Some(typ) => typ == found,
+            None => {
+                bindings.insert(name.clone(), found.clone());
+                true
+            }
+        },
+        (Type::Array(box t1, n1), Type::Array(box t2, n2)) => n1 == n2 && unify(t1, t2, bindings),
+        _ => expected == found,
     }
 }
 
-fn type_current(ident: String, value: Value, context: &Context) -> Result<Expr, String> {
+/// Check that the types bound to the type variables of a node belong to their classes
+fn check_classes(
+    fun: &str,
+    bindings: &HashMap<String, Type>,
+    span: Span,
+    context: &Context,
+) -> Result<(), Diagnostic> {
+    for (name, class) in &context.classes[fun] {
+        if let Some(typ) = bindings.get(name) {
+            let valid = match class {
+                TypeClass::Numeric => context.is_numeric(typ),
+            };
+            if !valid {
+                return Err(Diagnostic::new(
+                    format!(
+                        "The type variable '{} of node {} should be {}, but it is {}",
+                        name, fun, class, typ
+                    ),
+                    span,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Give a numeric type variable to an integer literal, so that it can be used
+/// with the values of this type. The literal is converted when the node is
+/// monomorphised. The literals of a tuple are given the types of their values
+fn coerce_literal(expr: &mut Expr, typ: &Vec<Type>, context: &Context) {
+    if let BaseExpr::Tuple(exprs) = &mut expr.expr {
+        if expr.typ.len() == typ.len() {
+            let mut start = 0;
+            for e in exprs.iter_mut() {
+                let end = start + e.typ.len();
+                coerce_literal(e, &typ[start..end].to_vec(), context);
+                start = end;
+            }
+            expr.typ = exprs.iter().flat_map(|e| e.typ.clone()).collect();
+        }
+        return;
+    }
+    if let [var @ Type::Var(_)] = typ.as_slice() {
+        if expr.typ == [Type::Int] && context.is_numeric(var) {
+            match &mut expr.expr {
+                BaseExpr::Value(Value::Int(_)) => expr.typ = typ.clone(),
+                BaseExpr::UnOp(UnOp::UMinus, box e) => {
+                    coerce_literal(e, typ, context);
+                    if &e.typ == typ {
+                        expr.typ = typ.clone();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn type_current(
+    ident: String,
+    value: Value,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let value = type_constant(value, span, context)?;
     if let Some(t) = context.variables.get(&ident) {
         if t != &value.get_type() {
-            Err(String::from("In a current construct, the initial value and the variable should have the same type."))
+            Err(Diagnostic::new(
+                String::from("In a current construct, the initial value and the variable should have the same type."),
+                span,
+            ))
         } else {
             Ok(Expr {
                 expr: BaseExpr::Current(ident, value),
                 typ: vec![t.clone()],
+                span,
             })
         }
     } else {
-        Err(format!("Variable {} used but not declared", &ident))
+        Err(Diagnostic::new(
+            format!("Variable {} used but not declared", &ident),
+            span,
+        ))
     }
 }
 
-fn type_pre(expr: ast::Expr, context: &Context) -> Result<Expr, String> {
+fn type_pre(expr: ast::Expr, span: Span, context: &Context) -> Result<Expr, Diagnostic> {
     let typed_expr = type_expr(expr, context)?;
-    if typed_expr.typ.len() != 1 {
-        return Err(String::from("pre operator cannot be applied to a tuple"));
-    }
     let typ = typed_expr.typ.clone();
     Ok(Expr {
         expr: BaseExpr::Pre(box typed_expr),
         typ,
+        span,
     })
 }
 
-fn type_arrow(exprs: Vec<ast::Expr>, context: &Context) -> Result<Expr, String> {
+fn type_arrow(exprs: Vec<ast::Expr>, span: Span, context: &Context) -> Result<Expr, Diagnostic> {
     let mut typed_exprs = vec![];
     for expr in exprs {
         typed_exprs.push(type_expr(expr, context)?);
     }
+    if let Some(expr) = typed_exprs
+        .iter()
+        .find(|e| e.typ.iter().any(|t| !t.variables().is_empty()))
+    {
+        let typ = expr.typ.clone();
+        typed_exprs
+            .iter_mut()
+            .for_each(|e| coerce_literal(e, &typ, context));
+    }
+    let typ = typed_exprs[0].typ.clone();
     for expr in &typed_exprs {
-        if expr.typ.len() != 1 {
-            return Err(String::from(
-                "In an arrow construct, the expressions should not be tuples",
+        if typ != expr.typ {
+            return Err(Diagnostic::new(
+                String::from("In an arrow construct, both expressions should have same size"),
+                span,
             ));
         }
     }
-    let typ = typed_exprs[0].typ[0].clone();
+    Ok(Expr {
+        expr: BaseExpr::Arrow(typed_exprs),
+        typ,
+        span,
+    })
+}
+
+/// The values of a tuple are the values of its expressions
+fn type_tuple(exprs: Vec<ast::Expr>, span: Span, context: &Context) -> Result<Expr, Diagnostic> {
+    let mut typed_exprs = vec![];
+    for expr in exprs {
+        typed_exprs.push(type_expr(expr, context)?);
+    }
+    let typ = typed_exprs.iter().flat_map(|e| e.typ.clone()).collect();
+    Ok(Expr {
+        expr: BaseExpr::Tuple(typed_exprs),
+        typ,
+        span,
+    })
+}
+
+fn type_array(exprs: Vec<ast::Expr>, span: Span, context: &Context) -> Result<Expr, Diagnostic> {
+    let mut typed_exprs = vec![];
+    for expr in exprs {
+        typed_exprs.push(type_expr(expr, context)?);
+    }
+    let typ = typed_exprs[0].typ.clone();
     for expr in &typed_exprs {
-        if typ != expr.typ[0] {
-            return Err(String::from(
-                "In an arrow construct, both expressions should have same size",
+        if expr.typ.len() != 1 {
+            return Err(Diagnostic::new(
+                String::from("The elements of an array should not be tuples"),
+                expr.span,
+            ));
+        }
+        if expr.typ != typ {
+            return Err(Diagnostic::new(
+                String::from("The elements of an array should have the same type"),
+                expr.span,
             ));
         }
     }
+    let n = typed_exprs.len();
     Ok(Expr {
-        expr: BaseExpr::Arrow(typed_exprs),
+        expr: BaseExpr::Array(typed_exprs),
+        typ: vec![Type::Array(box typ[0].clone(), n)],
+        span,
+    })
+}
+
+/// Type an array expression, and return its element type and its size
+fn type_array_operand(
+    expr: ast::Expr,
+    context: &Context,
+) -> Result<(Expr, Type, usize), Diagnostic> {
+    let typed_expr = type_expr(expr, context)?;
+    match typed_expr.typ.as_slice() {
+        [Type::Array(typ, n)] => {
+            let (typ, n) = (*typ.clone(), *n);
+            Ok((typed_expr, typ, n))
+        }
+        _ => Err(Diagnostic::new(
+            String::from("This expression should be an array"),
+            typed_expr.span,
+        )),
+    }
+}
+
+/// Type an index in an array
+/// Constant indices are checked statically, and dynamic indices are
+/// clamped to the bounds of the array
+fn type_index(
+    expr: ast::Expr,
+    index: ast::Expr,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (typed_expr, typ, n) = type_array_operand(expr, context)?;
+    let typed_index = type_expr(index, context)?;
+    if typed_index.typ != [Type::Int] {
+        return Err(Diagnostic::new(
+            String::from("The index of an array should be an integer"),
+            typed_index.span,
+        ));
+    }
+    if let BaseExpr::Value(Value::Int(i)) = typed_index.expr {
+        if i < 0 || i as usize >= n {
+            return Err(Diagnostic::new(
+                format!("The index {} is out of the bounds of an array of size {}", i, n),
+                typed_index.span,
+            ));
+        }
+    }
+    Ok(Expr {
+        expr: BaseExpr::Index(box typed_expr, box typed_index),
         typ: vec![typ],
+        span,
+    })
+}
+
+fn type_slice(
+    expr: ast::Expr,
+    i: i32,
+    j: i32,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (typed_expr, typ, n) = type_array_operand(expr, context)?;
+    if i < 0 || j < i || j as usize >= n {
+        return Err(Diagnostic::new(
+            format!("The slice {}..{} is not valid for an array of size {}", i, j, n),
+            span,
+        ));
+    }
+    let (i, j) = (i as usize, j as usize);
+    Ok(Expr {
+        expr: BaseExpr::Slice(box typed_expr, i, j),
+        typ: vec![Type::Array(box typ, j - i + 1)],
+        span,
+    })
+}
+
+fn type_concat(
+    e1: ast::Expr,
+    e2: ast::Expr,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (typed_e1, typ_1, n_1) = type_array_operand(e1, context)?;
+    let (typed_e2, typ_2, n_2) = type_array_operand(e2, context)?;
+    if typ_1 != typ_2 {
+        return Err(Diagnostic::new(
+            String::from("Concatenated arrays should have the same element type"),
+            span,
+        ));
+    }
+    Ok(Expr {
+        expr: BaseExpr::Concat(box typed_e1, box typed_e2),
+        typ: vec![Type::Array(box typ_1, n_1 + n_2)],
+        span,
+    })
+}
+
+/// Type the value given to each field in a record construction or update,
+/// and return them with the name of the record type
+fn type_record_fields(
+    fields: Vec<(String, ast::Expr)>,
+    record: Option<String>,
+    span: Span,
+    context: &Context,
+) -> Result<(String, Vec<(String, Expr)>), Diagnostic> {
+    let record = match record {
+        Some(record) => record,
+        None => match context.fields.get(&fields[0].0) {
+            Some(record) => record.clone(),
+            None => {
+                return Err(Diagnostic::new(
+                    format!("Field {} used but not declared", fields[0].0),
+                    span,
+                ))
+            }
+        },
+    };
+    let record_fields = TypeDecl::fields(context.types, &record);
+    let mut typed_fields: Vec<(String, Expr)> = vec![];
+    for (field, expr) in fields {
+        let expr_span = expr.span;
+        let typ = match record_fields.iter().find(|(f, _)| f == &field) {
+            Some((_, typ)) => typ,
+            None => {
+                return Err(Diagnostic::new(
+                    format!("The record {} has no field {}", record, field),
+                    expr_span,
+                ))
+            }
+        };
+        if typed_fields.iter().any(|(f, _)| f == &field) {
+            return Err(Diagnostic::new(
+                format!("The field {} is given twice", field),
+                expr_span,
+            ));
+        }
+        let typed_expr = type_expr(expr, context)?;
+        if typed_expr.typ != [typ.clone()] {
+            return Err(Diagnostic::new(
+                format!("The field {} should have type {}", field, typ),
+                expr_span,
+            ));
+        }
+        typed_fields.push((field, typed_expr));
+    }
+    Ok((record, typed_fields))
+}
+
+/// Type a record construction. Every field should be given exactly once,
+/// and they are sorted in the declaration order
+fn type_record(
+    fields: Vec<(String, ast::Expr)>,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (record, mut typed_fields) = type_record_fields(fields, None, span, context)?;
+    let mut sorted_fields = vec![];
+    for (field, _) in TypeDecl::fields(context.types, &record) {
+        match typed_fields.iter().position(|(f, _)| f == &field) {
+            Some(i) => sorted_fields.push(typed_fields.remove(i)),
+            None => {
+                return Err(Diagnostic::new(
+                    format!("The record {} has no value for the field {}", record, field),
+                    span,
+                ))
+            }
+        }
+    }
+    Ok(Expr {
+        expr: BaseExpr::Record(record.clone(), sorted_fields),
+        typ: vec![Type::Named(record)],
+        span,
+    })
+}
+
+/// Type a record expression, and return the name of its type
+fn type_record_operand(expr: ast::Expr, context: &Context) -> Result<(Expr, String), Diagnostic> {
+    let typed_expr = type_expr(expr, context)?;
+    match typed_expr.typ.as_slice() {
+        [Type::Named(name)] => {
+            if let Some(TypeDef::Record(_)) = TypeDecl::lookup(context.types, name) {
+                let name = name.clone();
+                return Ok((typed_expr, name));
+            }
+        }
+        _ => (),
+    }
+    Err(Diagnostic::new(
+        String::from("This expression should be a record"),
+        typed_expr.span,
+    ))
+}
+
+fn type_field(
+    expr: ast::Expr,
+    field: String,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (typed_expr, record) = type_record_operand(expr, context)?;
+    match TypeDecl::fields(context.types, &record)
+        .into_iter()
+        .find(|(f, _)| f == &field)
+    {
+        Some((_, typ)) => Ok(Expr {
+            expr: BaseExpr::Field(box typed_expr, field),
+            typ: vec![typ],
+            span,
+        }),
+        None => Err(Diagnostic::new(
+            format!("The record {} has no field {}", record, field),
+            span,
+        )),
+    }
+}
+
+/// Type an expression reset by a variable, generated by the reset blocks
+fn type_reset(
+    expr: ast::Expr,
+    r: String,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    if context.variables.get(&r) != Some(&Type::Bool) {
+        return Err(Diagnostic::new(
+            format!("The variable {} was used as reset but is not a boolean", r),
+            span,
+        ));
+    }
+    let typed_expr = type_expr(expr, context)?;
+    Ok(Expr {
+        typ: typed_expr.typ.clone(),
+        expr: BaseExpr::Reset(box typed_expr, r),
+        span,
+    })
+}
+
+fn type_with(
+    expr: ast::Expr,
+    fields: Vec<(String, ast::Expr)>,
+    span: Span,
+    context: &Context,
+) -> Result<Expr, Diagnostic> {
+    let (typed_expr, record) = type_record_operand(expr, context)?;
+    let (record, typed_fields) = type_record_fields(fields, Some(record), span, context)?;
+    Ok(Expr {
+        expr: BaseExpr::With(box typed_expr, typed_fields),
+        typ: vec![Type::Named(record)],
+        span,
     })
 }
@@ -1,37 +1,64 @@
-#![feature(box_syntax)]
-#![feature(box_patterns)]
-
 use std::env;
+use std::process;
+
+use lucyrs::{AssertMode, BackendOptions, Compiler};
 
-pub mod ast;
-pub mod ident;
-pub mod lucy;
-pub mod minils;
-pub mod obc;
+const USAGE: &str = "Usage: lucyrs [--asserts=panic|hook:<function>|ignore] <file> <entry node>";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        panic!("The first argument of the program should be the file path of the lucyrs file, and the second argument should be the entry node.");
+    let mut options = BackendOptions::default();
+    let mut positional = vec![];
+    for arg in env::args().skip(1) {
+        if arg.starts_with("--asserts=") {
+            options.asserts = match parse_assert_mode(&arg["--asserts=".len()..]) {
+                Some(mode) => mode,
+                None => {
+                    eprintln!("Invalid value for --asserts: {}\n{}", arg, USAGE);
+                    process::exit(2);
+                }
+            };
+        } else {
+            positional.push(arg);
+        }
     }
-    let filename = &args[1];
-    let node_name = &args[2];
-
-    // Parse the lucyrs file
-    let nodes = lucy::parse_file(filename);
-
-    // Type the lucy nodes
-    let typed_nodes = lucy::type_nodes(nodes);
-
-    // Compile it into minils AST
-    let minils_nodes = lucy::to_minils(typed_nodes);
-
-    // Compile it into obc AST
-    let obc_machines = minils::to_obc(minils_nodes);
+    if positional.len() < 2 {
+        eprintln!("The first argument of the program should be the file path of the lucyrs file, and the second argument should be the entry node.\n{}", USAGE);
+        process::exit(2);
+    }
+    let filename = &positional[0];
+    let node_name = &positional[1];
 
-    // Compile it into rust file
-    let rust_code = obc::to_rust(obc_machines, node_name);
+    let compiler = Compiler::new()
+        .path(filename)
+        .entry_node(node_name)
+        .options(options);
+    match compiler.compile() {
+        // Output the file
+        Ok(compilation) => println!("{}", compilation.code),
+        Err(mut errors) => {
+            errors.sort_by_key(|error| error.diagnostic().span.map(|span| span.start));
+            for error in &errors {
+                eprintln!("{}", error.render(compiler.sources()));
+            }
+            eprintln!(
+                "error: could not compile {} due to {} previous error(s)",
+                filename,
+                errors.len()
+            );
+            process::exit(1);
+        }
+    }
+}
 
-    // Output the file
-    println!("{}", rust_code);
+/// Parse the mode of the assertions, the hook being given by the path of
+/// the Rust function called with the message
+fn parse_assert_mode(mode: &str) -> Option<AssertMode> {
+    match mode {
+        "panic" => Some(AssertMode::Panic),
+        "ignore" => Some(AssertMode::Ignore),
+        _ if mode.starts_with("hook:") && mode.len() > "hook:".len() => {
+            Some(AssertMode::Hook(mode["hook:".len()..].to_owned()))
+        }
+        _ => None,
+    }
 }
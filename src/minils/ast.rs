@@ -1,6 +1,6 @@
 //! AST used for minils
 
-use crate::ast::{BinOp, Clock, Type, UnOp, Value};
+use crate::ast::{Assertion, BinOp, Clock, IteratorKind, Type, UnOp, Value};
 
 use std::collections::HashMap;
 
@@ -9,8 +9,11 @@ pub struct Node {
     pub name: String,
     pub in_params: Vec<(String, Type)>,
     pub out_params: Vec<(String, Type)>,
+    /// Clocks of the inputs and outputs that are not on the base clock
+    pub param_clocks: HashMap<String, Clock>,
     pub local_params: HashMap<String, (Type, Clock)>,
     pub eq_list: Vec<(Vec<String>, Expr)>,
+    pub assertions: Vec<Assertion>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,8 +29,17 @@ pub enum BaseExpr {
     UnOp(UnOp, Box<Expr>),
     BinOp(BinOp, Box<Expr>, Box<Expr>),
     Fby(Value, Box<Expr>),
-    When(Box<Expr>, String, bool),
-    Merge(String, Box<Expr>, Box<Expr>),
+    When(Box<Expr>, String, Value),
+    Merge(String, Vec<(Value, Expr)>),
     Var(String),
-    FunCall(String, Vec<Expr>, Option<String>),
+    /// Call of a node, with the clocks of its outputs
+    FunCall(String, Vec<Expr>, Option<String>, Vec<Clock>),
+    Iterate(IteratorKind, String, usize, Vec<Expr>, Option<String>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, usize, usize),
+    Concat(Box<Expr>, Box<Expr>),
+    Record(String, Vec<(String, Expr)>),
+    Field(Box<Expr>, String),
+    With(Box<Expr>, Vec<(String, Expr)>),
 }
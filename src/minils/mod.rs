@@ -7,14 +7,32 @@ pub mod normalized_ast;
 pub mod scheduling;
 pub mod to_obc;
 
+use crate::ast::ImportedNode;
+use crate::ident;
 use crate::obc::ast as obc;
 
-/// Transform minils into obc
-pub fn to_obc(nodes: Vec<ast::Node>) -> Vec<obc::Machine> {
+use std::collections::HashMap;
+
+/// Normalize and schedule minils nodes
+pub fn normalize(nodes: Vec<ast::Node>) -> Vec<normalized_ast::Node> {
     nodes
         .into_iter()
         .map(normalization::normalize)
         .map(scheduling::schedule)
-        .map(to_obc::to_obc)
         .collect()
 }
+
+/// Transform normalized minils into obc, given the nodes implemented in Rust
+/// The stateless machines are called as functions, without instances
+pub fn to_obc(nodes: Vec<normalized_ast::Node>, imported: &Vec<ImportedNode>) -> Vec<obc::Machine> {
+    let imported: HashMap<String, &ImportedNode> = imported
+        .iter()
+        .map(|node| (ident::gen_ident(node.name.clone(), 0), node))
+        .collect();
+    let mut machines = nodes
+        .into_iter()
+        .map(|node| to_obc::to_obc(node, &imported))
+        .collect();
+    to_obc::call_stateless_machines(&mut machines);
+    machines
+}
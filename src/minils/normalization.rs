@@ -3,7 +3,7 @@
 //! struct is used to generate new identifiers that will not collide with existing
 //! identifiers.
 
-use crate::ast::Clock;
+use crate::ast::{Assertion, Clock};
 use crate::ident;
 use crate::ident::IdentGenerator;
 use crate::minils::ast as minils;
@@ -17,12 +17,26 @@ pub fn normalize(node: minils::Node) -> norm::Node {
     let add_suffix_fst = |(ident, t)| (ident::gen_ident(ident, 0), t);
     let in_params = node.in_params.into_iter().map(add_suffix_fst).collect();
     let out_params = node.out_params.into_iter().map(add_suffix_fst).collect();
+    let param_clocks = node
+        .param_clocks
+        .into_iter()
+        .map(|(ident, clock)| (ident::gen_ident(ident, 0), gen_clock_ident(clock)))
+        .collect();
     let mut normalized_node = norm::Node {
         name,
         in_params,
         out_params,
+        param_clocks,
         defined_params: HashMap::new(),
         eq_list: vec![],
+        assertions: node
+            .assertions
+            .into_iter()
+            .map(|assertion| Assertion {
+                var: ident::gen_ident(assertion.var, 0),
+                ..assertion
+            })
+            .collect(),
     };
     for (idents, expr) in node.eq_list {
         let idents = idents.into_iter().map(|s| IdentGenerator::new(s)).collect();
@@ -37,7 +51,7 @@ fn normalize_eq(idents: &Vec<IdentGenerator>, expr: minils::Expr, node: &mut nor
     let clock = gen_clock_ident(expr.clock.clone());
     let mut defined_params;
     let expr_ = match expr.expr {
-        minils::BaseExpr::FunCall(fun, params, mut reset) => {
+        minils::BaseExpr::FunCall(fun, params, mut reset, clocks) => {
             if let Some(s) = &mut reset {
                 *s = ident::gen_ident(s.clone(), 0);
             }
@@ -46,7 +60,12 @@ fn normalize_eq(idents: &Vec<IdentGenerator>, expr: minils::Expr, node: &mut nor
                 .map(|param| normalize_a(&idents[0], param, node))
                 .collect();
             let defined_params_names = idents.into_iter().map(|i| i.get_ident());
-            defined_params = defined_params_names.clone().zip(typ_.clone()).collect();
+            defined_params = defined_params_names
+                .clone()
+                .zip(typ_.clone())
+                .zip(clocks.into_iter().map(gen_clock_ident))
+                .map(|((name, typ), clock)| (name, typ, clock))
+                .collect();
             norm::ExprEqBase::FunCall(
                 defined_params_names.collect(),
                 ident::gen_ident(fun, 0),
@@ -54,9 +73,32 @@ fn normalize_eq(idents: &Vec<IdentGenerator>, expr: minils::Expr, node: &mut nor
                 reset,
             )
         }
+        minils::BaseExpr::Iterate(kind, fun, n, params, mut reset) => {
+            if let Some(s) = &mut reset {
+                *s = ident::gen_ident(s.clone(), 0);
+            }
+            let params = params
+                .into_iter()
+                .map(|param| normalize_a(&idents[0], param, node))
+                .collect();
+            let defined_params_names = idents.into_iter().map(|i| i.get_ident());
+            defined_params = defined_params_names
+                .clone()
+                .zip(typ_.clone())
+                .map(|(name, typ)| (name, typ, clock.clone()))
+                .collect();
+            norm::ExprEqBase::Iterate(
+                defined_params_names.collect(),
+                kind,
+                ident::gen_ident(fun, 0),
+                n,
+                params,
+                reset,
+            )
+        }
         minils::BaseExpr::Fby(v, box expr) => {
             assert!(idents.len() == 1);
-            defined_params = vec![(idents[0].get_ident(), typ_[0].clone())];
+            defined_params = vec![(idents[0].get_ident(), typ_[0].clone(), clock.clone())];
             norm::ExprEqBase::Fby(
                 defined_params[0].0.clone(),
                 v,
@@ -65,14 +107,14 @@ fn normalize_eq(idents: &Vec<IdentGenerator>, expr: minils::Expr, node: &mut nor
         }
         _ => {
             assert!(idents.len() == 1);
-            defined_params = vec![(idents[0].get_ident(), typ_[0].clone())];
+            defined_params = vec![(idents[0].get_ident(), typ_[0].clone(), clock.clone())];
             let expr_ca = normalize_ca(&idents[0], expr, node);
             norm::ExprEqBase::ExprCA(defined_params[0].0.clone(), box expr_ca)
         }
     };
-    for (new_param, param_type) in defined_params {
+    for (new_param, param_type, param_clock) in defined_params {
         node.defined_params
-            .insert(new_param, (param_type, clock.clone()));
+            .insert(new_param, (param_type, param_clock));
     }
     node.eq_list.push(norm::Eq {
         typ: typ_,
@@ -87,15 +129,19 @@ fn normalize_ca(ident: &IdentGenerator, expr: minils::Expr, node: &mut norm::Nod
     let typ_ = expr.typ[0].clone();
     let clock = gen_clock_ident(expr.clock.clone());
     let expr_ = match expr.expr {
-        minils::BaseExpr::FunCall(_, _, _) | minils::BaseExpr::Fby(_, _) => {
+        minils::BaseExpr::FunCall(_, _, _, _)
+        | minils::BaseExpr::Iterate(_, _, _, _, _)
+        | minils::BaseExpr::Fby(_, _) => {
             let new_ident = ident.new_ident();
             normalize_eq(&vec![new_ident.clone()], expr.clone(), node);
             return norm::ExprCA::new_var(new_ident.get_ident(), typ_, clock.clone());
         }
-        minils::BaseExpr::Merge(ck, box e_t, box e_f) => {
-            let e_t = normalize_ca(ident, e_t, node);
-            let e_f = normalize_ca(ident, e_f, node);
-            norm::ExprCABase::Merge(ident::gen_ident(ck, 0), box e_t, box e_f)
+        minils::BaseExpr::Merge(ck, branches) => {
+            let branches = branches
+                .into_iter()
+                .map(|(value, e)| (value, normalize_ca(ident, e, node)))
+                .collect();
+            norm::ExprCABase::Merge(ident::gen_ident(ck, 0), branches)
         }
         _ => {
             let expr_a = normalize_a(ident, expr, node);
@@ -115,9 +161,10 @@ fn normalize_a(ident: &IdentGenerator, expr: minils::Expr, node: &mut norm::Node
     let typ_ = expr.typ[0].clone();
     let clock = gen_clock_ident(expr.clock.clone());
     let expr_ = match expr.expr {
-        minils::BaseExpr::FunCall(_, _, _)
+        minils::BaseExpr::FunCall(_, _, _, _)
+        | minils::BaseExpr::Iterate(_, _, _, _, _)
         | minils::BaseExpr::Fby(_, _)
-        | minils::BaseExpr::Merge(_, _, _) => {
+        | minils::BaseExpr::Merge(_, _) => {
             let new_ident = ident.new_ident();
             normalize_eq(&vec![new_ident.clone()], expr.clone(), node);
             norm::ExprABase::Var(new_ident.get_ident())
@@ -133,9 +180,49 @@ fn normalize_a(ident: &IdentGenerator, expr: minils::Expr, node: &mut norm::Node
             let rhs = normalize_a(ident, rhs, node);
             norm::ExprABase::BinOp(op, box lhs, box rhs)
         }
-        minils::BaseExpr::When(box e, ck, b) => {
+        minils::BaseExpr::When(box e, ck, value) => {
             let e = normalize_a(ident, e, node);
-            norm::ExprABase::When(box e, ck, b)
+            norm::ExprABase::When(box e, ck, value)
+        }
+        minils::BaseExpr::Array(exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|expr| normalize_a(ident, expr, node))
+                .collect();
+            norm::ExprABase::Array(exprs)
+        }
+        minils::BaseExpr::Index(box expr, box index) => {
+            let expr = normalize_a(ident, expr, node);
+            let index = normalize_a(ident, index, node);
+            norm::ExprABase::Index(box expr, box index)
+        }
+        minils::BaseExpr::Slice(box expr, i, j) => {
+            let expr = normalize_a(ident, expr, node);
+            norm::ExprABase::Slice(box expr, i, j)
+        }
+        minils::BaseExpr::Concat(box lhs, box rhs) => {
+            let lhs = normalize_a(ident, lhs, node);
+            let rhs = normalize_a(ident, rhs, node);
+            norm::ExprABase::Concat(box lhs, box rhs)
+        }
+        minils::BaseExpr::Record(record, fields) => {
+            let fields = fields
+                .into_iter()
+                .map(|(field, expr)| (field, normalize_a(ident, expr, node)))
+                .collect();
+            norm::ExprABase::Record(record, fields)
+        }
+        minils::BaseExpr::Field(box expr, field) => {
+            let expr = normalize_a(ident, expr, node);
+            norm::ExprABase::Field(box expr, field)
+        }
+        minils::BaseExpr::With(box expr, fields) => {
+            let expr = normalize_a(ident, expr, node);
+            let fields = fields
+                .into_iter()
+                .map(|(field, expr)| (field, normalize_a(ident, expr, node)))
+                .collect();
+            norm::ExprABase::With(box expr, fields)
         }
     };
     norm::ExprA {
@@ -148,14 +235,10 @@ fn normalize_a(ident: &IdentGenerator, expr: minils::Expr, node: &mut norm::Node
 /// Generate identifiers for clocks. This is required because in this module,
 /// all identifiers change.
 fn gen_clock_ident(ck: Clock) -> Clock {
-    match ck {
-        Clock::Const => Clock::Const,
-        Clock::Ck(v) => {
-            let v = v
-                .into_iter()
-                .map(|(ident, b)| (ident::gen_ident(ident, 0), b))
-                .collect();
-            Clock::Ck(v)
-        }
-    }
+    let Clock::Ck(v) = ck;
+    let v = v
+        .into_iter()
+        .map(|(ident, value)| (ident::gen_ident(ident, 0), value))
+        .collect();
+    Clock::Ck(v)
 }
@@ -1,6 +1,6 @@
 //! AST for normalized minils
 
-use crate::ast::{BinOp, Clock, Type, UnOp, Value};
+use crate::ast::{Assertion, BinOp, Clock, IteratorKind, Type, UnOp, Value};
 
 use std::collections::HashMap;
 
@@ -9,8 +9,11 @@ pub struct Node {
     pub name: String,
     pub in_params: Vec<(String, Type)>,
     pub out_params: Vec<(String, Type)>,
+    /// Clocks of the inputs and outputs that are not on the base clock
+    pub param_clocks: HashMap<String, Clock>,
     pub defined_params: HashMap<String, (Type, Clock)>,
     pub eq_list: Vec<Eq>,
+    pub assertions: Vec<Assertion>,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +27,14 @@ pub struct Eq {
 pub enum ExprEqBase {
     Fby(String, Value, Box<ExprA>),
     FunCall(Vec<String>, String, Vec<ExprA>, Option<String>),
+    Iterate(
+        Vec<String>,
+        IteratorKind,
+        String,
+        usize,
+        Vec<ExprA>,
+        Option<String>,
+    ),
     ExprCA(String, Box<ExprCA>),
 }
 
@@ -51,7 +62,7 @@ impl ExprCA {
 
 #[derive(Debug, Clone)]
 pub enum ExprCABase {
-    Merge(String, Box<ExprCA>, Box<ExprCA>),
+    Merge(String, Vec<(Value, ExprCA)>),
     ExprA(Box<ExprA>),
 }
 
@@ -66,7 +77,14 @@ pub struct ExprA {
 pub enum ExprABase {
     Value(Value),
     Var(String),
-    When(Box<ExprA>, String, bool),
+    When(Box<ExprA>, String, Value),
     UnOp(UnOp, Box<ExprA>),
     BinOp(BinOp, Box<ExprA>, Box<ExprA>),
+    Array(Vec<ExprA>),
+    Index(Box<ExprA>, Box<ExprA>),
+    Slice(Box<ExprA>, usize, usize),
+    Concat(Box<ExprA>, Box<ExprA>),
+    Record(String, Vec<(String, ExprA)>),
+    Field(Box<ExprA>, String),
+    With(Box<ExprA>, Vec<(String, ExprA)>),
 }
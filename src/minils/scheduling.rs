@@ -1,5 +1,6 @@
 //! Schedule normalized minils nodes
 
+use crate::ast::Clock;
 use crate::minils::normalized_ast::*;
 
 use petgraph::graphmap::GraphMap;
@@ -62,16 +63,21 @@ fn schedule_node(node: &mut Node) {
 fn get_defined_vars(eq: &Eq) -> Vec<&str> {
     match &eq.eq {
         ExprEqBase::Fby(_, _, _) => vec![],
-        ExprEqBase::FunCall(v, _, _, _) => v.iter().map(|s| s.as_str()).collect(),
+        ExprEqBase::FunCall(v, _, _, _) | ExprEqBase::Iterate(v, _, _, _, _, _) => {
+            v.iter().map(|s| s.as_str()).collect()
+        }
         ExprEqBase::ExprCA(s, _) => vec![&s],
     }
 }
 
 /// Normalize an assignment into an eq normalized minils node
+/// An equation also depends on the variables of its clock
 fn get_var_dependencies_eq(eq: &Eq) -> Vec<&str> {
-    match &eq.eq {
+    let Clock::Ck(ck) = &eq.clock;
+    let mut vars: Vec<&str> = ck.iter().map(|(s, _)| s.as_str()).collect();
+    vars.append(&mut match &eq.eq {
         ExprEqBase::Fby(_, _, box a) => get_var_dependencies_a(a),
-        ExprEqBase::FunCall(_, _, params, r) => {
+        ExprEqBase::FunCall(_, _, params, r) | ExprEqBase::Iterate(_, _, _, _, params, r) => {
             let mut v = params
                 .iter()
                 .map(get_var_dependencies_a)
@@ -83,18 +89,20 @@ fn get_var_dependencies_eq(eq: &Eq) -> Vec<&str> {
             v
         }
         ExprEqBase::ExprCA(_, box ca) => get_var_dependencies_ca(&ca),
-    }
+    });
+    vars
 }
 
 /// Normalize an assignment into an eq normalized ca
 fn get_var_dependencies_ca(ca: &ExprCA) -> Vec<&str> {
     match &ca.expr {
-        ExprCABase::Merge(s, box ca_1, box ca_2) => {
-            let mut vars_1 = get_var_dependencies_ca(&ca_1);
-            let mut vars_2 = get_var_dependencies_ca(&ca_2);
-            vars_1.append(&mut vars_2);
-            vars_1.push(s);
-            vars_1
+        ExprCABase::Merge(s, branches) => {
+            let mut vars = vec![];
+            for (_, ca) in branches {
+                vars.append(&mut get_var_dependencies_ca(&ca));
+            }
+            vars.push(s);
+            vars
         }
         ExprCABase::ExprA(box a) => get_var_dependencies_a(&a),
     }
@@ -117,5 +125,24 @@ fn get_var_dependencies_a(a: &ExprA) -> Vec<&str> {
             vars.push(s);
             vars
         }
+        ExprABase::Array(v) => v.iter().flat_map(get_var_dependencies_a).collect(),
+        ExprABase::Index(box a_1, box a_2) | ExprABase::Concat(box a_1, box a_2) => {
+            let mut vars_1 = get_var_dependencies_a(&a_1);
+            let mut vars_2 = get_var_dependencies_a(&a_2);
+            vars_1.append(&mut vars_2);
+            vars_1
+        }
+        ExprABase::Slice(box a, _, _) | ExprABase::Field(box a, _) => get_var_dependencies_a(&a),
+        ExprABase::Record(_, fields) => fields
+            .iter()
+            .flat_map(|(_, a)| get_var_dependencies_a(a))
+            .collect(),
+        ExprABase::With(box a, fields) => {
+            let mut vars = get_var_dependencies_a(&a);
+            for (_, a) in fields {
+                vars.append(&mut get_var_dependencies_a(a));
+            }
+            vars
+        }
     }
 }
@@ -1,37 +1,65 @@
 //! Compile a normalized scheduled minils program into obc.
 
-use crate::ast::{Clock, Type, Value};
+use crate::ast::{AssertionKind, BinOp, Clock, ImportedNode, IteratorKind, Type, UnOp, Value};
 use crate::ident;
 use crate::minils::normalized_ast as norm;
 use crate::obc::ast as obc;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Compile a normalized scheduled minils program into obc.
-pub fn to_obc(node: norm::Node) -> obc::Machine {
+/// imported contains the nodes implemented in Rust, with their normalized names
+pub fn to_obc(node: norm::Node, imported: &HashMap<String, &ImportedNode>) -> obc::Machine {
     let memory = get_memories(&node);
     let name = node.name;
     let step_inputs = node.in_params;
     let mut step_returns = node.out_params;
+    let mut presence: HashMap<String, obc::Expr> = node
+        .param_clocks
+        .into_iter()
+        .map(|(s, clock)| (s, clock_condition(clock)))
+        .collect();
     let mut temp_instances = HashMap::new();
+    let mut instance_arrays = HashMap::new();
     let mut step_stmts = vec![];
     for eq in node.eq_list {
         eq_to_obc(
             eq,
             &memory,
             &mut temp_instances,
+            &mut instance_arrays,
             &mut step_stmts,
             &node.defined_params,
+            imported,
         );
     }
+    // The assumptions are checked first, so that the guarantees of a step
+    // are not checked when its assumptions fail
+    let mut assertions = node.assertions;
+    assertions.sort_by_key(|assertion| assertion.kind != AssertionKind::Assume);
+    for assertion in assertions {
+        let (cond, clock) = match memory.get(&assertion.var) {
+            Some((_, clock)) => (obc::Expr::State(assertion.var), clock.clone()),
+            None => {
+                let clock = match node.defined_params.get(&assertion.var) {
+                    Some((_, clock)) => clock.clone(),
+                    None => Clock::Ck(vec![]),
+                };
+                (obc::Expr::Var(assertion.var), clock)
+            }
+        };
+        let stmt = obc::Stmt::Assert(cond, assertion.kind, assertion.text);
+        step_stmts.push(add_control(stmt, clock));
+    }
     let mut step_vars = HashMap::new();
     for (s, (t, _)) in node.defined_params {
         step_vars.insert(s, t);
     }
     let mut instances = HashMap::new();
     for (s, i) in temp_instances {
+        let typ = instance_type(&s, imported);
         for j in 0..i {
-            instances.insert(ident::gen_ident(s.clone(), j), s.clone());
+            instances.insert(ident::gen_ident(s.clone(), j), typ.clone());
         }
     }
     for (s, t) in &mut step_returns {
@@ -42,6 +70,9 @@ pub fn to_obc(node: norm::Node) -> obc::Machine {
                 s_result.clone(),
                 obc::Expr::State(s.clone()),
             ));
+            if let Some(cond) = presence.remove(s) {
+                presence.insert(s_result.clone(), cond);
+            }
             *s = s_result;
         }
     }
@@ -57,13 +88,111 @@ pub fn to_obc(node: norm::Node) -> obc::Machine {
         name,
         memory: memory_without_clocks,
         instances,
+        instance_arrays,
         step_inputs,
         step_returns,
+        presence,
         step_vars,
         step_stmts,
     }
 }
 
+/// Replace the instances of the stateless machines, which are compiled into
+/// functions, by calls to these functions. Removing the instances of a
+/// machine can make it stateless, so this is repeated until no instance
+/// is removed
+pub fn call_stateless_machines(machines: &mut Vec<obc::Machine>) {
+    loop {
+        let functions: HashSet<String> = machines
+            .iter()
+            .filter(|machine| machine.is_stateless())
+            .map(|machine| machine.name.clone())
+            .collect();
+        let mut changed = false;
+        for machine in machines.iter_mut() {
+            let mut removed: HashMap<String, String> = machine
+                .instances
+                .iter()
+                .filter(|(_, typ)| functions.contains(*typ))
+                .map(|(instance, typ)| (instance.clone(), typ.clone()))
+                .collect();
+            removed.extend(
+                machine
+                    .instance_arrays
+                    .iter()
+                    .filter(|(_, (typ, _))| functions.contains(typ))
+                    .map(|(instance, (typ, _))| (instance.clone(), typ.clone())),
+            );
+            if removed.is_empty() {
+                continue;
+            }
+            changed = true;
+            machine
+                .instances
+                .retain(|instance, _| !removed.contains_key(instance));
+            machine
+                .instance_arrays
+                .retain(|instance, _| !removed.contains_key(instance));
+            let stmts = std::mem::replace(&mut machine.step_stmts, vec![]);
+            machine.step_stmts = call_functions(stmts, &removed);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Replace the steps of the removed instances by calls to the functions
+/// given in removed, and remove their resets
+fn call_functions(stmts: Vec<obc::Stmt>, removed: &HashMap<String, String>) -> Vec<obc::Stmt> {
+    let mut new_stmts = vec![];
+    for stmt in stmts {
+        match stmt {
+            obc::Stmt::Step(results, instance, params) => match removed.get(&instance) {
+                Some(fun) => new_stmts.push(obc::Stmt::Call(results, fun.clone(), params)),
+                None => new_stmts.push(obc::Stmt::Step(results, instance, params)),
+            },
+            obc::Stmt::Iterate(kind, results, instances, n, params) => {
+                let instances = removed.get(&instances).cloned().unwrap_or(instances);
+                new_stmts.push(obc::Stmt::Iterate(kind, results, instances, n, params));
+            }
+            obc::Stmt::Reset(instance) => {
+                if !removed.contains_key(&instance) {
+                    new_stmts.push(obc::Stmt::Reset(instance));
+                }
+            }
+            obc::Stmt::Control(x, stmts_true, stmts_false) => {
+                let stmts_true = call_functions(stmts_true, removed);
+                let stmts_false = call_functions(stmts_false, removed);
+                if !stmts_true.is_empty() || !stmts_false.is_empty() {
+                    new_stmts.push(obc::Stmt::Control(x, stmts_true, stmts_false));
+                }
+            }
+            obc::Stmt::Case(x, branches) => {
+                let branches: Vec<(Value, Vec<obc::Stmt>)> = branches
+                    .into_iter()
+                    .map(|(value, stmts)| (value, call_functions(stmts, removed)))
+                    .filter(|(_, stmts)| !stmts.is_empty())
+                    .collect();
+                if !branches.is_empty() {
+                    new_stmts.push(obc::Stmt::Case(x, branches));
+                }
+            }
+            stmt => new_stmts.push(stmt),
+        }
+    }
+    new_stmts
+}
+
+/// Get the type of the instances of a node, which is the path of
+/// the Rust type for an extern node
+fn instance_type(fun: &String, imported: &HashMap<String, &ImportedNode>) -> String {
+    match imported.get(fun) {
+        Some(node) => node.path.clone(),
+        None => fun.clone(),
+    }
+}
+
 /// Get the needed registers in a node for memories
 fn get_memories(node: &norm::Node) -> HashMap<String, (Value, Clock)> {
     let mut memory = HashMap::new();
@@ -83,8 +212,10 @@ fn eq_to_obc(
     eq: norm::Eq,
     memory: &HashMap<String, (Value, Clock)>,
     instances: &mut HashMap<String, u32>,
+    instance_arrays: &mut HashMap<String, (String, usize)>,
     step_stmts: &mut Vec<obc::Stmt>,
     step_vars: &HashMap<String, (Type, Clock)>,
+    imported: &HashMap<String, &ImportedNode>,
 ) {
     match eq.eq {
         norm::ExprEqBase::Fby(s, _, box expr) => {
@@ -92,6 +223,15 @@ fn eq_to_obc(
             let stmt = add_control(obc::Stmt::Assignment(s, expr), eq.clock);
             step_stmts.push(stmt);
         }
+        norm::ExprEqBase::FunCall(pat, fun, exprs, _) if is_function(&fun, imported) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|e| a_to_obc(e, memory, instances, step_stmts))
+                .collect();
+            let path = imported[&fun].path.clone();
+            let stmt = add_control(obc::Stmt::Call(pat, path, exprs), eq.clock);
+            step_stmts.push(stmt);
+        }
         norm::ExprEqBase::FunCall(pat, fun, exprs, r) => {
             let n_fun = if let Some(n) = instances.get(&fun) {
                 n + 1
@@ -101,14 +241,7 @@ fn eq_to_obc(
             instances.insert(fun.clone(), n_fun);
             let ident = ident::gen_ident(fun, n_fun - 1);
             if let Some(r) = r {
-                let mut stmt = obc::Stmt::Reset(ident.clone());
-                stmt = obc::Stmt::Control(r.clone(), vec![stmt], vec![]);
-                let r_clock = match step_vars.get(&r) {
-                    Some((_, ck)) => ck.clone(),
-                    None => Clock::Const,
-                };
-                stmt = add_control(stmt, r_clock);
-                step_stmts.push(stmt);
+                step_stmts.push(reset_instance(ident.clone(), r, step_vars));
             }
             let exprs = exprs
                 .into_iter()
@@ -117,6 +250,31 @@ fn eq_to_obc(
             let stmt = add_control(obc::Stmt::Step(pat, ident, exprs), eq.clock);
             step_stmts.push(stmt);
         }
+        norm::ExprEqBase::Iterate(pat, kind, fun, n, exprs, _) if is_function(&fun, imported) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|e| a_to_obc(e, memory, instances, step_stmts))
+                .collect();
+            let path = imported[&fun].path.clone();
+            let stmt = obc::Stmt::Iterate(kind, pat, path, n, exprs);
+            step_stmts.push(add_control(stmt, eq.clock));
+        }
+        norm::ExprEqBase::Iterate(pat, kind, fun, n, exprs, r) => {
+            let typ = instance_type(&fun, imported);
+            let n_arrays = instance_arrays.values().filter(|(f, _)| f == &typ).count();
+            let ident = ident::gen_ident(fun + "_iter", n_arrays as u32);
+            let n_instances = if kind == IteratorKind::Red { n - 1 } else { n };
+            instance_arrays.insert(ident.clone(), (typ, n_instances));
+            if let Some(r) = r {
+                step_stmts.push(reset_instance(ident.clone(), r, step_vars));
+            }
+            let exprs = exprs
+                .into_iter()
+                .map(|e| a_to_obc(e, memory, instances, step_stmts))
+                .collect();
+            let stmt = obc::Stmt::Iterate(kind, pat, ident, n, exprs);
+            step_stmts.push(add_control(stmt, eq.clock));
+        }
         norm::ExprEqBase::ExprCA(s, box expr) => {
             let mut stmt = ca_to_obc(s, expr, memory, instances, step_stmts);
             stmt = add_control(stmt, eq.clock);
@@ -125,23 +283,63 @@ fn eq_to_obc(
     };
 }
 
+/// Check if a node is a stateless imported node, which has no instances
+fn is_function(fun: &String, imported: &HashMap<String, &ImportedNode>) -> bool {
+    match imported.get(fun) {
+        Some(node) => !node.stateful,
+        None => false,
+    }
+}
+
+/// Reset an instance, or an instance array, when the variable r is true
+fn reset_instance(
+    ident: String,
+    r: String,
+    step_vars: &HashMap<String, (Type, Clock)>,
+) -> obc::Stmt {
+    let stmt = obc::Stmt::Control(r.clone(), vec![obc::Stmt::Reset(ident)], vec![]);
+    let r_clock = match step_vars.get(&r) {
+        Some((_, ck)) => ck.clone(),
+        None => Clock::Ck(vec![]),
+    };
+    add_control(stmt, r_clock)
+}
+
 /// Add control over a statement, until it is executed relatively to clock
 fn add_control(mut stmt: obc::Stmt, clock: Clock) -> obc::Stmt {
-    match clock {
-        Clock::Const => (),
-        Clock::Ck(hm) => {
-            for (ck, b) in hm {
-                if b {
-                    stmt = obc::Stmt::Control(ck, vec![stmt], vec![]);
-                } else {
-                    stmt = obc::Stmt::Control(ck, vec![], vec![stmt]);
-                }
-            }
-        }
+    let Clock::Ck(hm) = clock;
+    for (ck, value) in hm {
+        stmt = match value {
+            Value::Bool(true) => obc::Stmt::Control(ck, vec![stmt], vec![]),
+            Value::Bool(false) => obc::Stmt::Control(ck, vec![], vec![stmt]),
+            value => obc::Stmt::Case(ck, vec![(value, vec![stmt])]),
+        };
     }
     stmt
 }
 
+/// Get the condition of the presence of a value on a clock, whose
+/// variables are inputs of the machine
+fn clock_condition(clock: Clock) -> obc::Expr {
+    let Clock::Ck(conditions) = clock;
+    conditions
+        .into_iter()
+        .map(|(ck, value)| match value {
+            Value::Bool(true) => obc::Expr::Var(ck),
+            Value::Bool(false) => obc::Expr::UnOp(UnOp::Not, box obc::Expr::Var(ck)),
+            value => obc::Expr::BinOp(
+                BinOp::Eq,
+                box obc::Expr::Var(ck),
+                box obc::Expr::Value(value),
+            ),
+        })
+        .fold(None, |cond, c| match cond {
+            None => Some(c),
+            Some(cond) => Some(obc::Expr::BinOp(BinOp::And, box cond, box c)),
+        })
+        .unwrap_or(obc::Expr::Value(Value::Bool(true)))
+}
+
 /// Translate a normalized minils ca into obc
 fn ca_to_obc(
     lhs: String,
@@ -151,10 +349,24 @@ fn ca_to_obc(
     step_stmts: &mut Vec<obc::Stmt>,
 ) -> obc::Stmt {
     match expr.expr {
-        norm::ExprCABase::Merge(x, box expr_true, box expr_false) => {
-            let expr_true = ca_to_obc(lhs.clone(), expr_true, memory, instances, step_stmts);
-            let expr_false = ca_to_obc(lhs, expr_false, memory, instances, step_stmts);
-            obc::Stmt::Control(x, vec![expr_true], vec![expr_false])
+        norm::ExprCABase::Merge(x, branches) => {
+            let mut branches: Vec<(Value, obc::Stmt)> = branches
+                .into_iter()
+                .map(|(value, expr)| {
+                    let stmt = ca_to_obc(lhs.clone(), expr, memory, instances, step_stmts);
+                    (value, stmt)
+                })
+                .collect();
+            if let Value::Bool(_) = branches[0].0 {
+                let (stmt_false, stmt_true) = (branches.pop().unwrap(), branches.pop().unwrap());
+                obc::Stmt::Control(x, vec![stmt_true.1], vec![stmt_false.1])
+            } else {
+                let branches = branches
+                    .into_iter()
+                    .map(|(value, stmt)| (value, vec![stmt]))
+                    .collect();
+                obc::Stmt::Case(x, branches)
+            }
         }
         norm::ExprCABase::ExprA(box expr) => {
             let expr = a_to_obc(expr, memory, instances, step_stmts);
@@ -189,5 +401,49 @@ fn a_to_obc(
             obc::Expr::BinOp(op, box lhs, box rhs)
         }
         norm::ExprABase::When(box e, _, _) => a_to_obc(e, memory, instances, step_stmts),
+        norm::ExprABase::Array(exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .map(|e| a_to_obc(e, memory, instances, step_stmts))
+                .collect();
+            obc::Expr::Array(exprs)
+        }
+        norm::ExprABase::Index(box e, box i) => {
+            let e = a_to_obc(e, memory, instances, step_stmts);
+            let i = a_to_obc(i, memory, instances, step_stmts);
+            obc::Expr::Index(box e, box i)
+        }
+        norm::ExprABase::Slice(box e, i, j) => {
+            let e = a_to_obc(e, memory, instances, step_stmts);
+            obc::Expr::Slice(box e, i, j)
+        }
+        norm::ExprABase::Concat(box lhs, box rhs) => {
+            let n = match &expr.typ {
+                Type::Array(_, n) => *n,
+                _ => unreachable!(),
+            };
+            let lhs = a_to_obc(lhs, memory, instances, step_stmts);
+            let rhs = a_to_obc(rhs, memory, instances, step_stmts);
+            obc::Expr::Concat(box lhs, box rhs, n)
+        }
+        norm::ExprABase::Record(record, fields) => {
+            let fields = fields
+                .into_iter()
+                .map(|(field, e)| (field, a_to_obc(e, memory, instances, step_stmts)))
+                .collect();
+            obc::Expr::Record(record, fields)
+        }
+        norm::ExprABase::Field(box e, field) => {
+            let e = a_to_obc(e, memory, instances, step_stmts);
+            obc::Expr::Field(box e, field)
+        }
+        norm::ExprABase::With(box e, fields) => {
+            let e = a_to_obc(e, memory, instances, step_stmts);
+            let fields = fields
+                .into_iter()
+                .map(|(field, e)| (field, a_to_obc(e, memory, instances, step_stmts)))
+                .collect();
+            obc::Expr::With(box e, fields)
+        }
     }
 }
@@ -1,6 +1,6 @@
 //! obc AST
 
-use crate::ast::{BinOp, Type, UnOp, Value};
+use crate::ast::{AssertionKind, BinOp, IteratorKind, Type, UnOp, Value};
 
 use std::collections::HashMap;
 
@@ -9,19 +9,42 @@ pub struct Machine {
     pub name: String,
     pub memory: HashMap<String, Value>,
     pub instances: HashMap<String, String>,
+    /// Arrays of instances used by iterators, with their size
+    pub instance_arrays: HashMap<String, (String, usize)>,
     pub step_inputs: Vec<(String, Type)>,
     pub step_returns: Vec<(String, Type)>,
+    /// Condition of the presence of the inputs and outputs that are not on
+    /// the base clock of the machine, which are given as options
+    pub presence: HashMap<String, Expr>,
     pub step_vars: HashMap<String, Type>,
     pub step_stmts: Vec<Stmt>,
 }
 
+impl Machine {
+    /// Check if a machine has no state, so that it is compiled into a function
+    pub fn is_stateless(&self) -> bool {
+        self.memory.is_empty() && self.instances.is_empty() && self.instance_arrays.is_empty()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Assignment(String, Expr),
     StateAssignment(String, Expr),
     Step(Vec<String>, String, Vec<Expr>),
+    /// Call of the function of a stateless machine, or of an imported node
+    /// given by its path
+    Call(Vec<String>, String, Vec<Expr>),
+    /// Reset an instance, or all the instances of an instance array
     Reset(String),
+    /// Iterate a step over an instance array, or a function,
+    /// for a given number of iterations
+    Iterate(IteratorKind, Vec<String>, String, usize, Vec<Expr>),
     Control(String, Vec<Stmt>, Vec<Stmt>),
+    /// Statements executed depending on the constructor of an enum variable
+    Case(String, Vec<(Value, Vec<Stmt>)>),
+    /// Check of an assertion, given with its kind and its source
+    Assert(Expr, AssertionKind, String),
 }
 
 #[derive(Debug, Clone)]
@@ -31,4 +54,14 @@ pub enum Expr {
     State(String),
     UnOp(UnOp, Box<Expr>),
     BinOp(BinOp, Box<Expr>, Box<Expr>),
+    Array(Vec<Expr>),
+    /// Out of bounds indices are clamped to the bounds of the array
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, usize, usize),
+    /// The size of the resulting array is given
+    Concat(Box<Expr>, Box<Expr>, usize),
+    Record(String, Vec<(String, Expr)>),
+    Field(Box<Expr>, String),
+    /// Copy of a record, with some of its fields replaced
+    With(Box<Expr>, Vec<(String, Expr)>),
 }
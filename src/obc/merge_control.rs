@@ -26,6 +26,7 @@ pub fn merge_near_control(stmts: Vec<Stmt>) -> Vec<Stmt> {
 fn can_merge_stmts(stmt_1: &Stmt, stmt_2: &Stmt) -> bool {
     match (stmt_1, stmt_2) {
         (Stmt::Control(ck1, _, _), Stmt::Control(ck2, _, _)) => ck1 == ck2,
+        (Stmt::Case(ck1, _), Stmt::Case(ck2, _)) => ck1 == ck2,
         (_, _) => false,
     }
 }
@@ -40,6 +41,19 @@ fn merge_stmts(stmt_1: Stmt, stmt_2: Stmt) -> Stmt {
             let v2 = merge_near_control(v1_f);
             Stmt::Control(ck, v1, v2)
         }
+        (Stmt::Case(ck, mut branches_1), Stmt::Case(_, branches_2)) => {
+            for (value, mut stmts_2) in branches_2 {
+                match branches_1.iter_mut().find(|(v, _)| v == &value) {
+                    Some((_, stmts_1)) => stmts_1.append(&mut stmts_2),
+                    None => branches_1.push((value, stmts_2)),
+                }
+            }
+            let branches = branches_1
+                .into_iter()
+                .map(|(value, stmts)| (value, merge_near_control(stmts)))
+                .collect();
+            Stmt::Case(ck, branches)
+        }
         (_, _) => unreachable!(),
     }
 }
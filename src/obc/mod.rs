@@ -4,12 +4,54 @@ pub mod ast;
 pub mod merge_control;
 pub mod to_rust;
 
+use crate::ast::{Constant, TypeDecl};
+use crate::error::CompileError;
 use crate::obc::ast::Machine;
 
-/// Compile an obc program into Rust
-pub fn to_rust(mut machines: Vec<Machine>, entry_machine: &str) -> String {
+/// Options of the Rust backend
+#[derive(Debug, Clone)]
+pub struct BackendOptions {
+    /// Generate a main function, that reads the inputs of the entry node
+    /// on the standard input, and prints its outputs
+    pub main: bool,
+    /// Code generated for the assertions of the nodes
+    pub asserts: AssertMode,
+}
+
+impl Default for BackendOptions {
+    fn default() -> BackendOptions {
+        BackendOptions {
+            main: true,
+            asserts: AssertMode::Panic,
+        }
+    }
+}
+
+/// Behaviour of the generated code when an assertion or a clause of a
+/// contract does not hold
+/// The message gives the source of the assertion, and for the contracts of
+/// the nodes with a state, the step at which it failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssertMode {
+    /// Panic, with the message
+    Panic,
+    /// Call the Rust function given by its path, with the message as a &str
+    Hook(String),
+    /// Do not check the assertions, nor evaluate their conditions
+    Ignore,
+}
+
+/// Compile an obc program into Rust, with the type declarations and
+/// the constants it uses
+pub fn to_rust(
+    mut machines: Vec<Machine>,
+    types: &Vec<TypeDecl>,
+    constants: &Vec<Constant>,
+    entry_machine: Option<&str>,
+    options: &BackendOptions,
+) -> Result<String, CompileError> {
     for machine in &mut machines {
         machine.step_stmts = merge_control::merge_near_control(machine.step_stmts.clone());
     }
-    to_rust::obc_to_rust(&machines, entry_machine)
+    to_rust::obc_to_rust(&machines, types, constants, entry_machine, options)
 }
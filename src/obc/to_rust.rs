@@ -1,45 +1,113 @@
 //! Contains functions to translate obc into Rust
 
-use crate::ast::{BinOp, Type, UnOp, Value};
+use crate::ast::{
+    AssertionKind, BinOp, Constant, IteratorKind, Type, TypeDecl, TypeDef, UnOp, Value,
+};
+use crate::diagnostic::Diagnostic;
+use crate::error::CompileError;
 use crate::ident;
 use crate::obc::ast::{Expr, Machine, Stmt};
+use crate::obc::{AssertMode, BackendOptions};
+
+use std::collections::HashMap;
+
+/// Field counting the steps of a machine whose contract is checked, so that
+/// the monitors report the step at which a clause failed
+const STEP_COUNTER: &str = "monitor_step";
+
+/// Flag of a machine whose contract is checked, which is false once an
+/// assumption has failed, so that the guarantees are not checked anymore
+const ASSUMPTIONS_HELD: &str = "monitor_assumptions_held";
 
 /// Translate an obc program into Rust, given an entry machine
-pub fn obc_to_rust(machines: &Vec<Machine>, entry_machine: &str) -> String {
-    let entry_machine = machines
-        .iter()
-        .find(|m| m.name == ident::gen_ident(entry_machine.to_string(), 0))
-        .unwrap();
-    let prog = get_rust_main(entry_machine) + "\n\n";
-    prog + &machines.into_iter().fold(String::new(), |s, machine| {
-        s + &machine_to_rust(machine) + "\n\n"
-    })
+pub fn obc_to_rust(
+    machines: &Vec<Machine>,
+    types: &Vec<TypeDecl>,
+    constants: &Vec<Constant>,
+    entry_machine: Option<&str>,
+    options: &BackendOptions,
+) -> Result<String, CompileError> {
+    let backend_error = |message| Err(CompileError::Backend(Diagnostic::without_span(message)));
+    let entry_machine = match entry_machine {
+        None => None,
+        Some(entry_machine) => {
+            let machine = machines
+                .iter()
+                .find(|m| m.name == ident::gen_ident(entry_machine.to_string(), 0));
+            if machine.is_none() {
+                return backend_error(format!("The entry node {} is not defined", entry_machine));
+            }
+            machine
+        }
+    };
+    let mut prog = String::new();
+    if options.main {
+        match entry_machine {
+            Some(entry_machine) => prog += &(get_rust_main(entry_machine, types) + "\n\n"),
+            None => {
+                return backend_error(String::from(
+                    "An entry node is needed to generate the main function",
+                ))
+            }
+        }
+    }
+    for decl in types {
+        prog += &(type_decl_to_rust(decl) + "\n\n");
+    }
+    prog += &constants_to_rust(constants, machines);
+    Ok(prog
+        + &machines.into_iter().fold(String::new(), |s, machine| {
+            s + &machine_to_rust(machine, machines, options) + "\n\n"
+        }))
 }
 
 /// Generate the rust main code of the generated Rust program
-fn get_rust_main(machine: &Machine) -> String {
+fn get_rust_main(machine: &Machine, types: &Vec<TypeDecl>) -> String {
     let mut main = String::from("use std::io::{self, Read};\n");
     main += "fn main() {\n";
-    main += &format!(
-        "    let mut entry_machine: {} = Default::default();\n",
-        machine.name
-    );
-    main += "    entry_machine.reset();\n";
+    if !machine.is_stateless() {
+        main += &format!(
+            "    let mut entry_machine: {} = Default::default();\n",
+            machine.name
+        );
+        main += "    entry_machine.reset();\n";
+    }
     main += "    let mut buffer =  String::new();\n";
     main += "    loop {\n";
+    // An input that is not on the base clock is absent when its line is empty
     for (input, typ) in &machine.step_inputs {
+        let optional = machine.presence.contains_key(input);
         main += &format!("        buffer = String::new();\n");
         main += &format!(
-            "        println!(\"Value of {} ({}): \");\n",
+            "        println!(\"Value of {} ({}{}): \");\n",
             input,
-            type_to_rust(typ)
+            type_to_rust(typ),
+            if optional { ", empty if absent" } else { "" }
         );
         main += "        io::stdin().read_line(&mut buffer).unwrap();\n";
-        main += &format!(
-            "        let {}: {} = buffer.trim().parse().unwrap();\n",
-            input,
-            type_to_rust(typ)
-        );
+        let value = if is_compound(typ, types) {
+            main += "        let mut values = buffer\n";
+            main += "            .split(|c: char| c.is_whitespace() || \"[],{};\".contains(c))\n";
+            main += "            .filter(|s| !s.is_empty());\n";
+            parse_value_to_rust(typ, types)
+        } else {
+            String::from("buffer.trim().parse().unwrap()")
+        };
+        if optional {
+            main += &format!(
+                "        let {}: Option<{}> = if buffer.trim().is_empty() {{ None }} else {{ Some({}) }};\n",
+                input,
+                type_to_rust(typ),
+                value
+            );
+        } else {
+            main += &format!(
+                "        let {}: {} = {};\n",
+                input,
+                type_to_rust(typ),
+                value
+            );
+        }
     }
     let inputs = machine
         .step_inputs
@@ -53,51 +121,230 @@ fn get_rust_main(machine: &Machine) -> String {
         .map(|(s, _)| s.to_string())
         .collect::<Vec<String>>()
         .join(", ");
+    let step = if machine.is_stateless() {
+        machine.name.clone()
+    } else {
+        String::from("entry_machine.step")
+    };
     main += &format!(
-        "        let ({}) = entry_machine.step({});\n",
+        "        let ({}) = {}({});\n",
         outputs.clone(),
+        step,
         inputs
     );
     main += &format!("        println!(\"Results: {{:?}}\", ({}));\n", outputs);
-    main += "        println!(\"{:#?}\", entry_machine);\n";
+    if !machine.is_stateless() {
+        main += "        println!(\"{:#?}\", entry_machine);\n";
+    }
     main += "    }\n";
     main + "}\n"
 }
 
+/// Check if a type is an array or a record, which are read as several values
+fn is_compound(typ: &Type, types: &Vec<TypeDecl>) -> bool {
+    match typ {
+        Type::Array(_, _) => true,
+        Type::Named(name) => match TypeDecl::lookup(types, name) {
+            Some(TypeDef::Record(_)) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Generate the code parsing a value of a type from the values iterator
+/// Arrays are given as the list of their elements, and records as
+/// the list of their fields in the declaration order
+fn parse_value_to_rust(typ: &Type, types: &Vec<TypeDecl>) -> String {
+    match typ {
+        Type::Array(elem_typ, _) => format!(
+            "{{ let mut a = {}; for x in a.iter_mut() {{ *x = {}; }} a }}",
+            default_to_rust(typ),
+            parse_value_to_rust(elem_typ, types)
+        ),
+        Type::Named(name) if is_compound(typ, types) => {
+            let fields = TypeDecl::fields(types, name)
+                .iter()
+                .map(|(field, typ)| format!("{}: {}", field, parse_value_to_rust(typ, types)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{} {{ {} }}", type_name_to_rust(name), fields)
+        }
+        _ => String::from("values.next().unwrap().parse().unwrap()"),
+    }
+}
+
+/// Generate a type declaration in Rust
+/// Enums can be read on the standard input by the main function
+/// Records are structs with public fields, and aliases are Rust aliases
+fn type_decl_to_rust(decl: &TypeDecl) -> String {
+    let name = type_name_to_rust(&decl.name);
+    match &decl.def {
+        TypeDef::Enum(constructors) => {
+            let mut def = String::from("#[derive(Clone, Copy, Debug, PartialEq)]\n");
+            def += &format!("pub enum {} {{\n", name);
+            for constructor in constructors {
+                def += &format!("    {},\n", constructor);
+            }
+            def += "}\n\n";
+            def += &format!("impl Default for {} {{\n", name);
+            def += &format!("    fn default() -> {} {{\n", name);
+            def += &format!("        {}::{}\n", name, constructors[0]);
+            def += "    }\n";
+            def += "}\n\n";
+            def += &format!("impl std::str::FromStr for {} {{\n", name);
+            def += "    type Err = String;\n";
+            def += &format!("    fn from_str(s: &str) -> Result<{}, String> {{\n", name);
+            def += "        match s {\n";
+            for constructor in constructors {
+                def += &format!(
+                    "            \"{}\" => Ok({}::{}),\n",
+                    constructor, name, constructor
+                );
+            }
+            def += &format!(
+                "            _ => Err(format!(\"{{}} is not a constructor of {}\", s)),\n",
+                decl.name
+            );
+            def += "        }\n";
+            def += "    }\n";
+            def += "}\n";
+            def
+        }
+        TypeDef::Record(fields) => {
+            let mut def = String::from("#[derive(Clone, Copy, Debug, Default, PartialEq)]\n");
+            def += &format!("pub struct {} {{\n", name);
+            for (field, typ) in fields {
+                def += &format!("    pub {}: {},\n", field, type_to_rust(typ));
+            }
+            def += "}\n";
+            def
+        }
+        TypeDef::Alias(typ) => format!("pub type {} = {};\n", name, type_to_rust(typ)),
+    }
+}
+
+/// Generate the constants in Rust
+/// The constants of a node are associated constants of its machine, or
+/// are in a module named after the function of a stateless machine
+fn constants_to_rust(constants: &Vec<Constant>, machines: &Vec<Machine>) -> String {
+    let constant_to_rust = |constant: &Constant| {
+        format!(
+            "pub const {}: {} = {};\n",
+            ident::gen_ident(constant.name.clone(), 0),
+            type_to_rust(&constant.value.get_type()),
+            value_to_rust(&constant.value)
+        )
+    };
+    let mut def = String::new();
+    for constant in constants.iter().filter(|c| c.node.is_none()) {
+        def += &constant_to_rust(constant);
+    }
+    if !def.is_empty() {
+        def += "\n";
+    }
+    let mut nodes: Vec<&String> = constants.iter().filter_map(|c| c.node.as_ref()).collect();
+    nodes.dedup();
+    for node in nodes {
+        let name = ident::gen_ident(node.clone(), 0);
+        let stateless = machines
+            .iter()
+            .any(|machine| machine.name == name && machine.is_stateless());
+        if stateless {
+            def += &format!("pub mod {} {{\n", name);
+            def += "    #[allow(unused_imports)]\n";
+            def += "    use super::*;\n";
+        } else {
+            def += &format!("impl {} {{\n", name);
+        }
+        for constant in constants.iter().filter(|c| c.node.as_ref() == Some(node)) {
+            def += &format!("    {}", constant_to_rust(constant));
+        }
+        def += "}\n\n";
+    }
+    def
+}
+
 /// Generate the code for a machine in Rust
-fn machine_to_rust(machine: &Machine) -> String {
-    let mut machine_str = get_struct_definition(machine);
+/// machines are the machines of the program, which the machine can call
+fn machine_to_rust(machine: &Machine, machines: &Vec<Machine>, options: &BackendOptions) -> String {
+    if machine.is_stateless() {
+        return get_step_definition(machine, machines, options);
+    }
+    let mut machine_str = get_struct_definition(machine, options);
     machine_str += "\n";
-    machine_str += &get_functions_definition(machine);
+    machine_str += &get_functions_definition(machine, machines, options);
     machine_str
 }
 
 /// Generate the machine struct definition in Rust
-fn get_struct_definition(machine: &Machine) -> String {
-    let mut def = format!("#[derive(Default, Debug)]\n");
-    def += &format!("struct {} {{\n", machine.name);
+/// Default is implemented by hand, since it is not derived for large arrays
+fn get_struct_definition(machine: &Machine, options: &BackendOptions) -> String {
+    let mut def = format!("#[derive(Debug)]\n");
+    def += &format!("pub struct {} {{\n", machine.name);
     for (memory, val) in &machine.memory {
         def += &format!("    pub {}: {},\n", memory, type_to_rust(&val.get_type()));
     }
     for (instance, typ) in &machine.instances {
         def += &format!("    pub {}: {},\n", instance, typ);
     }
+    for (instance, (typ, _)) in &machine.instance_arrays {
+        def += &format!("    pub {}: Vec<{}>,\n", instance, typ);
+    }
+    if counts_steps(machine, options) {
+        def += &format!("    pub {}: u64,\n", STEP_COUNTER);
+    }
+    if latches_assumptions(machine, options) && !machine.is_stateless() {
+        def += &format!("    pub {}: bool,\n", ASSUMPTIONS_HELD);
+    }
+    def += "}\n\n";
+    def += &format!("impl Default for {} {{\n", machine.name);
+    def += &format!("    fn default() -> {} {{\n", machine.name);
+    def += &format!("        {} {{\n", machine.name);
+    for (memory, val) in &machine.memory {
+        def += &format!(
+            "            {}: {},\n",
+            memory,
+            default_to_rust(&val.get_type())
+        );
+    }
+    for (instance, _) in &machine.instances {
+        def += &format!("            {}: Default::default(),\n", instance);
+    }
+    for (instance, (_, n)) in &machine.instance_arrays {
+        def += &format!(
+            "            {}: (0..{}).map(|_| Default::default()).collect(),\n",
+            instance, n
+        );
+    }
+    if counts_steps(machine, options) {
+        def += &format!("            {}: 0,\n", STEP_COUNTER);
+    }
+    if latches_assumptions(machine, options) && !machine.is_stateless() {
+        def += &format!("            {}: true,\n", ASSUMPTIONS_HELD);
+    }
+    def += "        }\n";
+    def += "    }\n";
     def += "}\n";
     def
 }
 
 /// Generate the step and reset functions of a machine in Rust
-fn get_functions_definition(machine: &Machine) -> String {
+fn get_functions_definition(
+    machine: &Machine,
+    machines: &Vec<Machine>,
+    options: &BackendOptions,
+) -> String {
     let mut def = format!("impl {} {{\n", machine.name);
-    def += &get_reset_definition(machine);
+    def += &get_reset_definition(machine, options);
     def += "\n";
-    def += &get_step_definition(machine);
+    def += &get_step_definition(machine, machines, options);
     def += "}\n";
     def
 }
 
 /// Generate the reset function of a machine in Rust
-fn get_reset_definition(machine: &Machine) -> String {
+fn get_reset_definition(machine: &Machine, options: &BackendOptions) -> String {
     let mut def = format!("    pub fn reset(&mut self) {{\n");
     for (memory, value) in &machine.memory {
         def += &format!("        self.{} = {};\n", memory, value_to_rust(value));
@@ -105,52 +352,177 @@ fn get_reset_definition(machine: &Machine) -> String {
     for (instance, _) in &machine.instances {
         def += &format!("        self.{}.reset();\n", instance);
     }
+    for (instance, _) in &machine.instance_arrays {
+        def += &format!(
+            "        self.{}.iter_mut().for_each(|instance| instance.reset());\n",
+            instance
+        );
+    }
+    if counts_steps(machine, options) {
+        def += &format!("        self.{} = 0;\n", STEP_COUNTER);
+    }
+    if latches_assumptions(machine, options) && !machine.is_stateless() {
+        def += &format!("        self.{} = true;\n", ASSUMPTIONS_HELD);
+    }
     def += "    }\n";
     def
 }
 
 /// Generate the step function of a machine in Rust
-fn get_step_definition(machine: &Machine) -> String {
+/// A stateless machine is compiled into a free function, named after the machine
+/// The inputs and outputs that are not on the base clock are options, which
+/// are None when they are absent
+fn get_step_definition(
+    machine: &Machine,
+    machines: &Vec<Machine>,
+    options: &BackendOptions,
+) -> String {
     let inputs = machine
         .step_inputs
         .iter()
-        .map(|(name, typ)| format!("{}: {}", name, type_to_rust(typ)))
+        .map(|(name, typ)| format!("{}: {}", name, param_type_to_rust(machine, name, typ)))
         .collect::<Vec<String>>()
         .join(", ");
     let outputs = machine
         .step_returns
         .iter()
-        .map(|(_, typ)| type_to_rust(typ))
+        .map(|(name, typ)| param_type_to_rust(machine, name, typ))
         .collect::<Vec<String>>()
         .join(", ");
 
-    let mut def = format!(
-        "    pub fn step(&mut self, {}) -> ({}) {{\n",
-        inputs, outputs
-    );
+    let (mut def, n_indent) = if machine.is_stateless() {
+        let def = format!("pub fn {}({}) -> ({}) {{\n", machine.name, inputs, outputs);
+        (def, 1)
+    } else {
+        let def = format!(
+            "    pub fn step(&mut self, {}) -> ({}) {{\n",
+            inputs, outputs
+        );
+        (def, 2)
+    };
+    let indent = " ".repeat((n_indent as usize) * 4);
+    // An absent input is only read when its clock is true, so it is
+    // given a default value, once the caller is checked to give it exactly
+    // when its clock is true. The inputs on slower clocks come after the
+    // inputs defining their clocks.
+    let mut clocked: Vec<(&String, &Type, &Expr)> = machine
+        .step_inputs
+        .iter()
+        .filter_map(|(input, typ)| Some((input, typ, machine.presence.get(input)?)))
+        .collect();
+    clocked.sort_by_key(|(_, _, cond)| conjuncts(cond));
+    for (input, typ, cond) in clocked {
+        def += &format!(
+            "{}debug_assert_eq!({}.is_some(), {});\n",
+            indent,
+            input,
+            expr_to_rust(cond)
+        );
+        def += &format!(
+            "{}let {}: {} = {}.unwrap_or({});\n",
+            indent,
+            input,
+            type_to_rust(typ),
+            input,
+            default_to_rust(typ)
+        );
+    }
     for (var, typ) in &machine.step_vars {
         def += &format!(
-            "        let mut {}: {} = Default::default();\n",
+            "{}let mut {}: {} = {};\n",
+            indent,
             var,
-            type_to_rust(typ)
+            type_to_rust(typ),
+            default_to_rust(typ)
         );
     }
+    // A stateless machine only latches the assumptions of the current step
+    if latches_assumptions(machine, options) && machine.is_stateless() {
+        def += &format!("{}let mut {} = true;\n", indent, ASSUMPTIONS_HELD);
+    }
     for stmt in &machine.step_stmts {
-        def += &stmt_to_rust(machine, stmt, 2);
+        def += &stmt_to_rust(machine, machines, stmt, n_indent, options);
+    }
+    if counts_steps(machine, options) {
+        def += &format!("{}self.{} += 1;\n", indent, STEP_COUNTER);
     }
     let returns = machine
         .step_returns
         .iter()
-        .map(|(name, _)| name.clone())
+        .map(|(name, _)| match machine.presence.get(name) {
+            Some(cond) => format!(
+                "if {} {{ Some({}) }} else {{ None }}",
+                expr_to_rust(cond),
+                name
+            ),
+            None => name.clone(),
+        })
         .collect::<Vec<String>>()
         .join(", ");
-    def += &format!("        ({})\n", returns);
-    def += "    }\n";
+    def += &format!("{}({})\n", indent, returns);
+    def += &format!("{}}}\n", " ".repeat((n_indent as usize - 1) * 4));
     def
 }
 
+/// Get the number of variables of the clock of a condition of presence,
+/// which is a conjunction of the conditions on each variable
+fn conjuncts(cond: &Expr) -> usize {
+    match cond {
+        Expr::BinOp(BinOp::And, box cond, _) => conjuncts(cond) + 1,
+        _ => 1,
+    }
+}
+
+/// Check if a machine counts its steps, which is the case when it has a
+/// state and the clauses of its contract are checked
+fn counts_steps(machine: &Machine, options: &BackendOptions) -> bool {
+    options.asserts != AssertMode::Ignore
+        && !machine.is_stateless()
+        && checks_contract(&machine.step_stmts)
+}
+
+/// Check if statements contain the check of a clause of a contract
+fn checks_contract(stmts: &Vec<Stmt>) -> bool {
+    checks(stmts, AssertionKind::Assume) || checks(stmts, AssertionKind::Guarantee)
+}
+
+/// Check if a machine remembers that one of its assumptions failed, which is
+/// the case when it has both assumptions and guarantees that are checked
+fn latches_assumptions(machine: &Machine, options: &BackendOptions) -> bool {
+    options.asserts != AssertMode::Ignore
+        && checks(&machine.step_stmts, AssertionKind::Assume)
+        && checks(&machine.step_stmts, AssertionKind::Guarantee)
+}
+
+/// Check if statements contain the check of an assertion of the given kind
+fn checks(stmts: &Vec<Stmt>, kind: AssertionKind) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Assert(_, assert_kind, _) => *assert_kind == kind,
+        Stmt::Control(_, stmts_true, stmts_false) => {
+            checks(stmts_true, kind) || checks(stmts_false, kind)
+        }
+        Stmt::Case(_, branches) => branches.iter().any(|(_, stmts)| checks(stmts, kind)),
+        _ => false,
+    })
+}
+
+/// Generate the type of an input or an output of a machine in Rust
+fn param_type_to_rust(machine: &Machine, name: &str, typ: &Type) -> String {
+    if machine.presence.contains_key(name) {
+        format!("Option<{}>", type_to_rust(typ))
+    } else {
+        type_to_rust(typ)
+    }
+}
+
 /// Compile an obc statement into Rust
-fn stmt_to_rust(machine: &Machine, stmt: &Stmt, n_indent: i32) -> String {
+fn stmt_to_rust(
+    machine: &Machine,
+    machines: &Vec<Machine>,
+    stmt: &Stmt,
+    n_indent: i32,
+    options: &BackendOptions,
+) -> String {
     let indent = " ".repeat((n_indent as usize) * 4);
     match stmt {
         Stmt::Assignment(s, expr) => format!("{}{} = {};\n", indent, s, expr_to_rust(expr)),
@@ -158,26 +530,23 @@ fn stmt_to_rust(machine: &Machine, stmt: &Stmt, n_indent: i32) -> String {
             format!("{}self.{} = {};\n", indent, s, expr_to_rust(expr))
         }
         Stmt::Step(results, fun, params) => {
-            let params = params
-                .iter()
-                .map(expr_to_rust)
-                .collect::<Vec<String>>()
-                .join(", ");
-            let results_temp = results
-                .iter()
-                .map(|s| s.clone() + "_result")
-                .collect::<Vec<String>>();
-            let results_str = results_temp.clone().join(", ");
-            let mut step = format!(
-                "{}let ({}) = self.{}.step({});\n",
-                indent, results_str, fun, params
-            );
-            for (l, r) in results.iter().zip(results_temp) {
-                step += &format!("{}{} = {};\n", indent, l, r);
-            }
-            step
+            let callee = machines.iter().find(|m| m.name == machine.instances[fun]);
+            let step = format!("self.{}.step", fun);
+            call_to_rust(results, &step, params, callee, &indent)
+        }
+        Stmt::Call(results, fun, params) => {
+            let callee = machines.iter().find(|m| &m.name == fun);
+            call_to_rust(results, fun, params, callee, &indent)
         }
+        Stmt::Reset(s) if machine.instance_arrays.contains_key(s) => format!(
+            "{}self.{}.iter_mut().for_each(|instance| instance.reset());\n",
+            indent, s
+        ),
         Stmt::Reset(s) => format!("{}self.{}.reset();\n", indent, s),
+        Stmt::Iterate(kind, results, instances, n, params) => {
+            let function = !machine.instance_arrays.contains_key(instances);
+            iterate_to_rust(*kind, results, instances, function, *n, params, n_indent)
+        }
         Stmt::Control(x, stmts_true, stmts_false) => {
             let cond = if machine.memory.contains_key(x) {
                 format!("self.{}", x)
@@ -186,18 +555,268 @@ fn stmt_to_rust(machine: &Machine, stmt: &Stmt, n_indent: i32) -> String {
             };
             let mut string = format!("{}if {} {{\n", indent.clone(), cond);
             for stmt in stmts_true {
-                string += &stmt_to_rust(machine, stmt, n_indent + 1);
+                string += &stmt_to_rust(machine, machines, stmt, n_indent + 1, options);
             }
             string += &format!("{}}} else {{\n", indent.clone());
             for stmt in stmts_false {
-                string += &stmt_to_rust(machine, stmt, n_indent + 1);
+                string += &stmt_to_rust(machine, machines, stmt, n_indent + 1, options);
             }
             string += &format!("{}}}\n", indent);
             string
         }
+        Stmt::Case(x, branches) => {
+            let cond = if machine.memory.contains_key(x) {
+                format!("self.{}", x)
+            } else {
+                x.clone()
+            };
+            let mut string = format!("{}#[allow(unreachable_patterns)]\n", indent);
+            string += &format!("{}match {} {{\n", indent, cond);
+            for (value, stmts) in branches {
+                string += &format!("{}    {} => {{\n", indent, value_to_rust(value));
+                for stmt in stmts {
+                    string += &stmt_to_rust(machine, machines, stmt, n_indent + 2, options);
+                }
+                string += &format!("{}    }}\n", indent);
+            }
+            string += &format!("{}    _ => (),\n", indent);
+            string += &format!("{}}}\n", indent);
+            string
+        }
+        Stmt::Assert(cond, kind, text) => {
+            let failed = match kind {
+                AssertionKind::Assert => "Assertion failed",
+                AssertionKind::Assume => "Assumption failed",
+                AssertionKind::Guarantee => "Guarantee failed",
+            };
+            let (message, args) =
+                if *kind != AssertionKind::Assert && counts_steps(machine, options) {
+                    let message = format!("{} at step {{}}: {{}}", failed);
+                    (message, format!("self.{}, {:?}", STEP_COUNTER, text))
+                } else {
+                    (format!("{}: {{}}", failed), format!("{:?}", text))
+                };
+            if latches_assumptions(machine, options) && *kind != AssertionKind::Assert {
+                return latched_assert_to_rust(
+                    machine, cond, *kind, &message, &args, &indent, options,
+                );
+            }
+            match &options.asserts {
+                AssertMode::Panic => format!(
+                    "{}assert!({}, \"{}\", {});\n",
+                    indent,
+                    expr_to_rust(cond),
+                    message,
+                    args
+                ),
+                AssertMode::Hook(hook) => format!(
+                    "{}if !{} {{\n{}    {}(&format!(\"{}\", {}));\n{}}}\n",
+                    indent,
+                    expr_to_rust(cond),
+                    indent,
+                    hook,
+                    message,
+                    args,
+                    indent
+                ),
+                AssertMode::Ignore => String::new(),
+            }
+        }
+    }
+}
+
+/// Compile the check of a clause of a contract whose machine latches the
+/// failure of its assumptions: a failed assumption clears the flag, and the
+/// guarantees are only checked while it is set
+fn latched_assert_to_rust(
+    machine: &Machine,
+    cond: &Expr,
+    kind: AssertionKind,
+    message: &str,
+    args: &str,
+    indent: &str,
+    options: &BackendOptions,
+) -> String {
+    let flag = if machine.is_stateless() {
+        ASSUMPTIONS_HELD.to_owned()
+    } else {
+        format!("self.{}", ASSUMPTIONS_HELD)
+    };
+    let report = match &options.asserts {
+        AssertMode::Panic => format!("panic!(\"{}\", {})", message, args),
+        AssertMode::Hook(hook) => format!("{}(&format!(\"{}\", {}))", hook, message, args),
+        AssertMode::Ignore => return String::new(),
+    };
+    match kind {
+        AssertionKind::Assume => format!(
+            "{}if !{} {{\n{}    {} = false;\n{}    {};\n{}}}\n",
+            indent,
+            expr_to_rust(cond),
+            indent,
+            flag,
+            indent,
+            report,
+            indent
+        ),
+        _ => format!(
+            "{}if {} && !{} {{\n{}    {};\n{}}}\n",
+            indent,
+            flag,
+            expr_to_rust(cond),
+            indent,
+            report,
+            indent
+        ),
+    }
+}
+
+/// Compile the call of a step function or of a Rust function
+/// The machine called is given if it is not a Rust function, its inputs
+/// that are not on the base clock are given as options, present when
+/// their clock is true, and its absent outputs do not change the results
+fn call_to_rust(
+    results: &Vec<String>,
+    fun: &str,
+    params: &Vec<Expr>,
+    callee: Option<&Machine>,
+    indent: &str,
+) -> String {
+    let presence = |i: usize| {
+        let machine = callee?;
+        let cond = machine.presence.get(&machine.step_inputs[i].0)?;
+        let args = machine
+            .step_inputs
+            .iter()
+            .map(|(name, _)| name)
+            .zip(params)
+            .collect();
+        Some(substitute(cond, &args))
+    };
+    let params = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| match presence(i) {
+            Some(cond) => format!(
+                "if {} {{ Some({}) }} else {{ None }}",
+                expr_to_rust(&cond),
+                expr_to_rust(param)
+            ),
+            None => expr_to_rust(param),
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let results_temp = results
+        .iter()
+        .map(|s| s.clone() + "_result")
+        .collect::<Vec<String>>();
+    let results_str = results_temp.clone().join(", ");
+    let mut step = format!("{}let ({}) = {}({});\n", indent, results_str, fun, params);
+    for (i, (l, r)) in results.iter().zip(results_temp).enumerate() {
+        let optional = callee.map_or(false, |machine| {
+            machine.presence.contains_key(&machine.step_returns[i].0)
+        });
+        if optional {
+            step += &format!("{}{} = {}.unwrap_or({});\n", indent, l, r, l);
+        } else {
+            step += &format!("{}{} = {};\n", indent, l, r);
+        }
+    }
+    step
+}
+
+/// Replace the variables of a condition of presence by the arguments
+/// given for them
+fn substitute(expr: &Expr, args: &HashMap<&String, &Expr>) -> Expr {
+    match expr {
+        Expr::Var(s) => match args.get(s) {
+            Some(arg) => (*arg).clone(),
+            None => expr.clone(),
+        },
+        Expr::UnOp(op, box e) => Expr::UnOp(op.clone(), box substitute(e, args)),
+        Expr::BinOp(op, box e1, box e2) => {
+            Expr::BinOp(op.clone(), box substitute(e1, args), box substitute(e2, args))
+        }
+        _ => expr.clone(),
     }
 }
 
+/// Compile an iterator into a loop over the instances of an instance array,
+/// or into a loop calling a function when function is true
+/// For fold, mapfold and red, the accumulator is the first result
+fn iterate_to_rust(
+    kind: IteratorKind,
+    results: &Vec<String>,
+    instances: &str,
+    function: bool,
+    n: usize,
+    params: &Vec<Expr>,
+    n_indent: i32,
+) -> String {
+    let indent = " ".repeat((n_indent as usize) * 4);
+    let mut string = format!("{}{{\n", indent);
+    let n_acc = match kind {
+        IteratorKind::Map | IteratorKind::Red => 0,
+        IteratorKind::Fold | IteratorKind::MapFold => 1,
+    };
+    for (i, param) in params.iter().enumerate() {
+        if i < n_acc {
+            string += &format!("{}    let mut acc = {};\n", indent, expr_to_rust(param));
+        } else {
+            string += &format!("{}    let arg_{} = {};\n", indent, i, expr_to_rust(param));
+        }
+    }
+    let (first, instance) = if kind == IteratorKind::Red {
+        string += &format!("{}    let mut acc = arg_0[0];\n", indent);
+        (1, "i - 1")
+    } else {
+        (0, "i")
+    };
+    let args = (0..params.len())
+        .map(|i| {
+            if i < n_acc {
+                String::from("acc")
+            } else {
+                format!("arg_{}[i]", i)
+            }
+        })
+        .collect::<Vec<String>>();
+    let args = if kind == IteratorKind::Red {
+        format!("acc, {}", args.join(", "))
+    } else {
+        args.join(", ")
+    };
+    let n_results = if kind == IteratorKind::Red { 1 } else { results.len() };
+    let outputs = (0..n_results)
+        .map(|i| format!("out_{}", i))
+        .collect::<Vec<String>>();
+    let step = if function {
+        instances.to_string()
+    } else {
+        format!("self.{}[{}].step", instances, instance)
+    };
+    string += &format!("{}    for i in {}..{} {{\n", indent, first, n);
+    string += &format!(
+        "{}        let ({}) = {}({});\n",
+        indent,
+        outputs.join(", "),
+        step,
+        args
+    );
+    for i in 0..n_results {
+        if i == 0 && kind != IteratorKind::Map {
+            string += &format!("{}        acc = out_0;\n", indent);
+        } else {
+            string += &format!("{}        {}[i] = out_{};\n", indent, results[i], i);
+        }
+    }
+    string += &format!("{}    }}\n", indent);
+    if kind != IteratorKind::Map {
+        string += &format!("{}    {} = acc;\n", indent, results[0]);
+    }
+    string += &format!("{}}}\n", indent);
+    string
+}
+
 /// Generate an obc expression in Rust
 fn expr_to_rust(expr: &Expr) -> String {
     match expr {
@@ -217,6 +836,52 @@ fn expr_to_rust(expr: &Expr) -> String {
                 )
             }
         }
+        Expr::Array(exprs) => {
+            let exprs = exprs
+                .iter()
+                .map(expr_to_rust)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("[{}]", exprs)
+        }
+        Expr::Index(box expr, box Expr::Value(Value::Int(i))) => {
+            format!("{}[{}]", expr_to_rust(expr), i)
+        }
+        Expr::Index(box expr, box index) => format!(
+            "{{ let a = {}; a[({}).max(0).min(a.len() as i32 - 1) as usize] }}",
+            expr_to_rust(expr),
+            expr_to_rust(index)
+        ),
+        Expr::Slice(box expr, i, j) => format!(
+            "{{ let a = {}; let mut r = [a[0]; {}]; r.copy_from_slice(&a[{}..{}]); r }}",
+            expr_to_rust(expr),
+            j - i + 1,
+            i,
+            j + 1
+        ),
+        Expr::Concat(box lhs, box rhs, n) => format!(
+            "{{ let a = {}; let b = {}; let mut r = [a[0]; {}]; \
+             r[..a.len()].copy_from_slice(&a); r[a.len()..].copy_from_slice(&b); r }}",
+            expr_to_rust(lhs),
+            expr_to_rust(rhs),
+            n
+        ),
+        Expr::Record(record, fields) => {
+            let fields = fields
+                .iter()
+                .map(|(field, expr)| format!("{}: {}", field, expr_to_rust(expr)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{} {{ {} }}", type_name_to_rust(record), fields)
+        }
+        Expr::Field(box expr, field) => format!("{}.{}", expr_to_rust(expr), field),
+        Expr::With(box expr, fields) => {
+            let updates = fields
+                .iter()
+                .map(|(field, expr)| format!("r.{} = {}; ", field, expr_to_rust(expr)))
+                .collect::<String>();
+            format!("{{ let mut r = {}; {}r }}", expr_to_rust(expr), updates)
+        }
     }
 }
 
@@ -255,6 +920,23 @@ fn type_to_rust(typ: &Type) -> String {
         Type::Int => String::from("i32"),
         Type::Real => String::from("f32"),
         Type::Bool => String::from("bool"),
+        Type::Named(name) => type_name_to_rust(name),
+        Type::Array(typ, n) => format!("[{}; {}]", type_to_rust(typ), n),
+        Type::ConstArray(_, _) | Type::Var(_) => unreachable!(),
+    }
+}
+
+/// Translate the name of a type into Rust
+/// The qualified names P::t of the packages become P__t
+fn type_name_to_rust(name: &str) -> String {
+    name.replace("::", "__")
+}
+
+/// Generate the default value of a type in Rust
+fn default_to_rust(typ: &Type) -> String {
+    match typ {
+        Type::Array(typ, n) => format!("[{}; {}]", default_to_rust(typ), n),
+        _ => String::from("Default::default()"),
     }
 }
 
@@ -265,10 +947,37 @@ fn value_to_rust(val: &Value) -> String {
         Value::Real(r) => {
             if r.is_nan() {
                 String::from("std::f32::NAN")
+            } else if r.is_infinite() && *r > 0.0 {
+                String::from("std::f32::INFINITY")
+            } else if r.is_infinite() {
+                String::from("std::f32::NEG_INFINITY")
             } else {
                 r.to_string() + "f32"
             }
         }
         Value::Bool(b) => b.to_string(),
+        Value::Enum(typ, constructor) => {
+            format!("{}::{}", type_name_to_rust(typ), constructor)
+        }
+        Value::Array(values) => {
+            if values.iter().all(|value| value == &values[0]) {
+                format!("[{}; {}]", value_to_rust(&values[0]), values.len())
+            } else {
+                let values = values
+                    .iter()
+                    .map(value_to_rust)
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("[{}]", values)
+            }
+        }
+        Value::Record(typ, fields) => {
+            let fields = fields
+                .iter()
+                .map(|(field, value)| format!("{}: {}", field, value_to_rust(value)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{} {{ {} }}", type_name_to_rust(typ), fields)
+        }
     }
 }